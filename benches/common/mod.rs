@@ -0,0 +1,89 @@
+//! Points for the benchmarks to index, spread out in different ways.
+
+// Each benchmark only uses some of these.
+#![allow(dead_code)]
+
+use neonet2::bench::Positioned;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+pub const WIDTH: f32 = 1920.0;
+pub const HEIGHT: f32 = 1080.0;
+pub const MAX_DISTANCE: f32 = 100.0;
+
+#[derive(Clone)]
+pub struct BenchPoint {
+    pub index: usize,
+    pub x: f32,
+    pub y: f32,
+    pub vx: f32,
+    pub vy: f32,
+}
+
+impl Positioned for BenchPoint {
+    fn x(&self) -> f32 {
+        self.x
+    }
+
+    fn y(&self) -> f32 {
+        self.y
+    }
+
+    fn x_mut(&mut self) -> &mut f32 {
+        &mut self.x
+    }
+
+    fn y_mut(&mut self) -> &mut f32 {
+        &mut self.y
+    }
+}
+
+/// How points are spread across the screen.
+#[derive(Debug, Copy, Clone)]
+pub enum Distribution {
+    /// Evenly across the whole screen.
+    Uniform,
+    /// Mostly within a few small clusters, leaving most of the screen empty.
+    Clustered,
+}
+
+impl Distribution {
+    pub const ALL: [Distribution; 2] = [Distribution::Uniform, Distribution::Clustered];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Distribution::Uniform => "uniform",
+            Distribution::Clustered => "clustered",
+        }
+    }
+
+    /// Picks `count` points with a fixed seed, so every run gets the same
+    /// ones.
+    pub fn points(self, count: usize) -> Vec<BenchPoint> {
+        let mut rng = StdRng::seed_from_u64(48);
+        let centers: Vec<(f32, f32)> = (0..10)
+            .map(|_| (rng.gen_range(0.0..WIDTH), rng.gen_range(0.0..HEIGHT)))
+            .collect();
+        (0..count)
+            .map(|index| {
+                // One in ten points is left out of the clusters.
+                let (x, y) = match self {
+                    Distribution::Clustered if index % 10 != 0 => {
+                        let (x, y) = centers[rng.gen_range(0..centers.len())];
+                        (
+                            (x + rng.gen_range(-MAX_DISTANCE..MAX_DISTANCE)).clamp(0.0, WIDTH),
+                            (y + rng.gen_range(-MAX_DISTANCE..MAX_DISTANCE)).clamp(0.0, HEIGHT),
+                        )
+                    },
+                    _ => (rng.gen_range(0.0..WIDTH), rng.gen_range(0.0..HEIGHT)),
+                };
+                BenchPoint {
+                    index,
+                    x,
+                    y,
+                    vx: rng.gen_range(-1.0..1.0),
+                    vy: rng.gen_range(-1.0..1.0),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,188 @@
+//! Times the hot paths of each frame: the grid's operations, a whole
+//! simulation step and encoding the buffers sent to the GPU, at several point
+//! counts.
+//!
+//! Run with `cargo bench --features bench --bench simulation`.
+
+mod common;
+
+use common::{Distribution, HEIGHT, MAX_DISTANCE, WIDTH};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use neonet2::{
+    bench::{Encodable, GPUPoint, Grid, PointIndex, Positioned},
+    flow::WindowSize,
+    neonet::{
+        config::{NeonetConfig, SpatialIndexKind},
+        simulation::{Point, Simulation},
+    },
+};
+use std::time::Duration;
+
+const POINT_COUNTS: [usize; 3] = [1_000, 10_000, 50_000];
+
+/// The point counts to encode index buffers for. Larger counts have tens of
+/// millions of lines, which would take gigabytes to hold.
+const INDEX_POINT_COUNTS: [usize; 2] = [1_000, 10_000];
+
+/// Creates a grid like the simulation's, covering the screen and a border
+/// around it.
+fn grid<P: Positioned + Clone>(points: &[P]) -> Grid<P> {
+    let mut grid = Grid::new(
+        MAX_DISTANCE,
+        MAX_DISTANCE,
+        WIDTH + MAX_DISTANCE * 2.0,
+        HEIGHT + MAX_DISTANCE * 2.0,
+    );
+    for p in points {
+        grid.insert(p.clone());
+    }
+    grid
+}
+
+/// Turns the benchmark's points into the simulation's, to fill the buffers
+/// sent to the GPU.
+fn simulation_points(distribution: Distribution, count: usize) -> Vec<Point> {
+    distribution
+        .points(count)
+        .into_iter()
+        .map(|p| Point {
+            index: p.index,
+            x: p.x,
+            y: p.y,
+            vx: p.vx,
+            vy: p.vy,
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn bench_grid(c: &mut Criterion) {
+    let mut group = c.benchmark_group("grid");
+    group.sample_size(10);
+
+    for distribution in Distribution::ALL {
+        for count in POINT_COUNTS {
+            let points = distribution.points(count);
+            let id =
+                |name: &str| BenchmarkId::new(format!("{}/{}", name, distribution.name()), count);
+            group.throughput(Throughput::Elements(count as u64));
+
+            group.bench_function(id("insert"), |b| {
+                b.iter_batched(
+                    || points.clone(),
+                    |points| grid(&points),
+                    BatchSize::LargeInput,
+                )
+            });
+
+            let mut grid = grid(&points);
+
+            // Moves every point back and forth, so they stay in the same
+            // places across iterations.
+            let mut forward = true;
+            group.bench_function(id("all_mut"), |b| {
+                b.iter(|| {
+                    let sign = if forward { 1.0 } else { -1.0 };
+                    forward = !forward;
+                    grid.all_mut(|p| {
+                        p.x += p.vx * sign;
+                        p.y += p.vy * sign;
+                    });
+                })
+            });
+
+            group.bench_function(id("pairs"), |b| {
+                b.iter(|| {
+                    let mut count = 0;
+                    grid.pairs(|_, _, distance_sqr, _| {
+                        count += 1;
+                        black_box(distance_sqr);
+                    });
+                    count
+                })
+            });
+
+            // Grows the grid and shrinks it back, which never moves points
+            // since they are all within the smaller size.
+            let mut grown = false;
+            group.bench_function(id("set_size"), |b| {
+                b.iter(|| {
+                    let scale = if grown { 1.0 } else { 1.5 };
+                    grown = !grown;
+                    grid.set_size(
+                        (WIDTH + MAX_DISTANCE * 2.0) * scale,
+                        (HEIGHT + MAX_DISTANCE * 2.0) * scale,
+                    );
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulation/step");
+    group.sample_size(10);
+
+    let size = WindowSize { width: WIDTH, height: HEIGHT };
+    for (name, spatial_index) in [
+        ("grid", SpatialIndexKind::Grid),
+        ("kd_tree", SpatialIndexKind::KdTree),
+    ] {
+        for count in POINT_COUNTS {
+            let config = NeonetConfig {
+                seed: Some(48),
+                points_per_megapixel: Some(count as f32 / (WIDTH * HEIGHT / 1_000_000.0)),
+                spatial_index,
+                ..Default::default()
+            };
+            let mut simulation = Simulation::new(&config, size, 48, count);
+            group.throughput(Throughput::Elements(simulation.point_count() as u64));
+            group.bench_function(BenchmarkId::new(name, count), |b| {
+                b.iter(|| {
+                    simulation.step(Duration::from_millis(16), |point| {
+                        black_box(point);
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_encode_slice(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_slice");
+
+    for count in POINT_COUNTS {
+        let points: Vec<GPUPoint> = simulation_points(Distribution::Uniform, count)
+            .into_iter()
+            .map(|p| GPUPoint::new(p, [0.0, 0.6, 0.9]))
+            .collect();
+        let mut bytes = vec![0; points.len() * GPUPoint::size()];
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_function(BenchmarkId::new("vertices", count), |b| {
+            b.iter(|| GPUPoint::encode_slice(&points, &mut bytes))
+        });
+    }
+
+    for distribution in Distribution::ALL {
+        for count in INDEX_POINT_COUNTS {
+            let mut indices = vec![];
+            grid(&simulation_points(distribution, count)).pairs(|p, op, distance_sqr, offset| {
+                PointIndex::push_line(&mut indices, p, op, distance_sqr, offset)
+            });
+            let mut bytes = vec![0; indices.len() * PointIndex::size()];
+            group.throughput(Throughput::Bytes(bytes.len() as u64));
+            group.bench_function(
+                BenchmarkId::new(format!("indices/{}", distribution.name()), count),
+                |b| b.iter(|| PointIndex::encode_slice(&indices, &mut bytes)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_grid, bench_step, bench_encode_slice);
+criterion_main!(benches);
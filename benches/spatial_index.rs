@@ -0,0 +1,82 @@
+//! Compares the spatial indexes with points spread evenly across the screen and
+//! with points gathered into a few clusters.
+//!
+//! Run with `cargo bench --features bench --bench spatial_index`.
+
+mod common;
+
+use common::{BenchPoint, Distribution, HEIGHT, MAX_DISTANCE, WIDTH};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use neonet2::bench::{AnySpatialIndex, Grid, KdTree, SpatialIndex};
+
+const POINT_COUNT: usize = 2000;
+
+fn indexes(distribution: Distribution) -> Vec<(&'static str, AnySpatialIndex<BenchPoint>)> {
+    let mut indexes = vec![
+        (
+            "grid",
+            AnySpatialIndex::Grid(Grid::new(
+                MAX_DISTANCE,
+                MAX_DISTANCE,
+                WIDTH + MAX_DISTANCE * 2.0,
+                HEIGHT + MAX_DISTANCE * 2.0,
+            )),
+        ),
+        (
+            "kd_tree",
+            AnySpatialIndex::KdTree(KdTree::new(MAX_DISTANCE)),
+        ),
+    ];
+    for (_, index) in indexes.iter_mut() {
+        for p in distribution.points(POINT_COUNT) {
+            index.insert(p);
+        }
+    }
+    indexes
+}
+
+fn bench_spatial_index(c: &mut Criterion) {
+    for distribution in Distribution::ALL {
+        let mut group = c.benchmark_group(format!("spatial_index/{}", distribution.name()));
+        for (name, mut index) in indexes(distribution) {
+            group.bench_function(BenchmarkId::new("pairs", name), |b| {
+                b.iter(|| {
+                    let mut count = 0;
+                    index.pairs(|_, _, distance_sqr, _| {
+                        count += 1;
+                        black_box(distance_sqr);
+                    });
+                    count
+                })
+            });
+
+            group.bench_function(BenchmarkId::new("within", name), |b| {
+                b.iter(|| {
+                    index
+                        .iter()
+                        .step_by(10)
+                        .map(|p| index.within(p.x, p.y, MAX_DISTANCE / 2.0).count())
+                        .sum::<usize>()
+                })
+            });
+
+            // Moves every point back and forth, so they stay in the same
+            // places across iterations.
+            let mut forward = true;
+            group.bench_function(BenchmarkId::new("all_mut", name), |b| {
+                b.iter(|| {
+                    let sign = if forward { 1.0 } else { -1.0 };
+                    forward = !forward;
+                    index.all_mut(|p| {
+                        p.x += p.vx * sign;
+                        p.y += p.vy * sign;
+                    });
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_spatial_index);
+criterion_main!(benches);
@@ -0,0 +1,111 @@
+//! Decoding audio files into samples for analysis.
+
+use lewton::inside_ogg::OggStreamReader;
+use std::{fs::File, io, path::Path};
+
+/// A whole decoded audio file, mixed down to mono.
+pub struct AudioClip {
+    samples: Vec<f32>,
+    sample_rate: u32,
+}
+
+impl AudioClip {
+    /// Decodes a WAV or Ogg Vorbis file, picked by the file's extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<AudioClip, AudioError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        match extension.as_str() {
+            "wav" => AudioClip::load_wav(path),
+            "ogg" => AudioClip::load_ogg(path),
+            _ => Err(AudioError::UnsupportedFormat(extension)),
+        }
+    }
+
+    fn load_wav(path: &Path) -> Result<AudioClip, AudioError> {
+        let reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let interleaved: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .into_samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect::<Result<_, _>>()?
+            },
+        };
+
+        Ok(AudioClip {
+            samples: mix_down(&interleaved, spec.channels as usize),
+            sample_rate: spec.sample_rate,
+        })
+    }
+
+    fn load_ogg(path: &Path) -> Result<AudioClip, AudioError> {
+        let mut reader = OggStreamReader::new(File::open(path)?)?;
+        let channels = reader.ident_hdr.audio_channels as usize;
+
+        let mut samples = vec![];
+        while let Some(packet) = reader.read_dec_packet_itl()? {
+            samples.extend(mix_down(
+                &packet
+                    .into_iter()
+                    .map(|sample| sample as f32 / 32768.0)
+                    .collect::<Vec<_>>(),
+                channels,
+            ));
+        }
+
+        Ok(AudioClip {
+            samples,
+            sample_rate: reader.ident_hdr.audio_sample_rate,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Fills `window` with the samples leading up to `time` seconds into the
+    /// clip. The clip loops, so any time has samples.
+    pub fn window_at(&self, time: f32, window: &mut [f32]) {
+        if self.samples.is_empty() {
+            window.fill(0.0);
+            return;
+        }
+
+        let len = self.samples.len();
+        let end = (time.max(0.0) as f64 * self.sample_rate as f64) as usize;
+        // Step back by the window's length, adding `len` first so that the
+        // index wraps around the loop rather than going below zero.
+        let start = (end % len + len - window.len() % len) % len;
+        for (i, sample) in window.iter_mut().enumerate() {
+            *sample = self.samples[(start + i) % len];
+        }
+    }
+}
+
+/// Averages each frame of interleaved samples into one mono sample.
+fn mix_down(interleaved: &[f32], channels: usize) -> Vec<f32> {
+    interleaved
+        .chunks(channels.max(1))
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect()
+}
+
+#[derive(Debug, Error)]
+pub enum AudioError {
+    #[error("IO Error")]
+    IOError(#[from] io::Error),
+    #[error("WAV Error")]
+    WavError(#[from] hound::Error),
+    #[error("Ogg Vorbis Error")]
+    OggError(#[from] lewton::VorbisError),
+    #[error("Unsupported audio format: {0:?}")]
+    UnsupportedFormat(String),
+}
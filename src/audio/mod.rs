@@ -0,0 +1,181 @@
+//! Audio analysis for the audio-reactive mode.
+//!
+//! The analysis only ever sees buffers of samples, so it works the same
+//! whether the samples were decoded from a file or came from the browser.
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::{f32::consts::PI, sync::Arc};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod clip;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use clip::AudioClip;
+
+/// The number of samples analysed at a time.
+pub const FFT_SIZE: usize = 1024;
+
+/// The frequency ranges, in Hz, of the bass, mid and treble bands.
+const BASS_RANGE: (f32, f32) = (20.0, 250.0);
+const MID_RANGE: (f32, f32) = (250.0, 4000.0);
+const TREBLE_RANGE: (f32, f32) = (4000.0, 16000.0);
+
+/// How loud a buffer of samples is overall and in each frequency band, all
+/// roughly in `0.0..=1.0`.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct AudioFeatures {
+    pub loudness: f32,
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+}
+
+/// Turns buffers of samples into `AudioFeatures`, smoothing them over time
+/// like a Web Audio `AnalyserNode` does.
+pub struct AudioAnalyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    smoothing: f32,
+    features: AudioFeatures,
+}
+
+impl AudioAnalyzer {
+    /// Creates an analyzer. `smoothing` is how much of the previous features
+    /// are kept with each new buffer, from 0 for none to 1 for all.
+    pub fn new(smoothing: f32) -> AudioAnalyzer {
+        // A Hann window keeps the edges of the buffer from smearing energy
+        // across every frequency.
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+
+        AudioAnalyzer {
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            buffer: vec![Complex::default(); FFT_SIZE],
+            smoothing: smoothing.clamp(0.0, 1.0),
+            features: AudioFeatures::default(),
+        }
+    }
+
+    /// Analyses the last `FFT_SIZE` mono samples in `samples`, returning the
+    /// smoothed features. Shorter buffers are padded with silence.
+    pub fn analyze(&mut self, samples: &[f32], sample_rate: u32) -> AudioFeatures {
+        let samples = &samples[samples.len().saturating_sub(FFT_SIZE)..];
+        let padding = FFT_SIZE - samples.len();
+
+        let mut square_sum = 0.0;
+        for (i, value) in self.buffer.iter_mut().enumerate() {
+            let sample = if i < padding {
+                0.0
+            } else {
+                samples[i - padding]
+            };
+            square_sum += sample * sample;
+            *value = Complex::new(sample * self.window[i], 0.0);
+        }
+
+        self.fft.process(&mut self.buffer);
+
+        // A full-scale sine wave has an RMS of 1/sqrt(2) and a windowed peak
+        // magnitude of FFT_SIZE / 4, so both are scaled to make that 1.
+        let loudness = ((square_sum / FFT_SIZE as f32).sqrt() * 2f32.sqrt()).min(1.0);
+        let band = |(low, high): (f32, f32)| {
+            let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+            let low = ((low / bin_width).floor() as usize).max(1);
+            let high = ((high / bin_width).ceil() as usize).min(FFT_SIZE / 2);
+            self.buffer[low.min(high)..high]
+                .iter()
+                .map(|value| value.norm())
+                .fold(0.0, f32::max)
+                / (FFT_SIZE as f32 / 4.0)
+        };
+        let current = AudioFeatures {
+            loudness,
+            bass: band(BASS_RANGE).min(1.0),
+            mid: band(MID_RANGE).min(1.0),
+            treble: band(TREBLE_RANGE).min(1.0),
+        };
+
+        let smooth = |previous: f32, current: f32| {
+            previous * self.smoothing + current * (1.0 - self.smoothing)
+        };
+        self.features = AudioFeatures {
+            loudness: smooth(self.features.loudness, current.loudness),
+            bass: smooth(self.features.bass, current.bass),
+            mid: smooth(self.features.mid, current.mid),
+            treble: smooth(self.features.treble, current.treble),
+        };
+        self.features
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::{AudioAnalyzer, AudioClip, AudioFeatures, FFT_SIZE};
+
+    fn fixture(name: &str) -> AudioClip {
+        AudioClip::load(format!(
+            "{}/tests/fixtures/audio/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        ))
+        .unwrap()
+    }
+
+    fn analyze(clip: &AudioClip) -> AudioFeatures {
+        let mut analyzer = AudioAnalyzer::new(0.0);
+        let mut window = vec![0.0; FFT_SIZE];
+        clip.window_at(0.2, &mut window);
+        analyzer.analyze(&window, clip.sample_rate())
+    }
+
+    #[test]
+    fn test_silence() {
+        let features = analyze(&fixture("silence.wav"));
+        assert_eq!(features, AudioFeatures::default());
+    }
+
+    #[test]
+    fn test_bass_from_ogg() {
+        let features = analyze(&fixture("sine_100hz.ogg"));
+        assert!(features.loudness > 0.6, "{:?}", features);
+        assert!(features.bass > 0.5, "{:?}", features);
+        assert!(features.mid < 0.1, "{:?}", features);
+        assert!(features.treble < 0.1, "{:?}", features);
+    }
+
+    #[test]
+    fn test_mid_from_wav() {
+        let features = analyze(&fixture("sine_1khz.wav"));
+        assert!(features.loudness > 0.6, "{:?}", features);
+        assert!(features.bass < 0.1, "{:?}", features);
+        assert!(features.mid > 0.5, "{:?}", features);
+        assert!(features.treble < 0.1, "{:?}", features);
+    }
+
+    #[test]
+    fn test_treble_from_stereo_wav() {
+        let features = analyze(&fixture("sine_6khz_stereo.wav"));
+        assert!(features.loudness > 0.6, "{:?}", features);
+        assert!(features.bass < 0.1, "{:?}", features);
+        assert!(features.mid < 0.1, "{:?}", features);
+        assert!(features.treble > 0.5, "{:?}", features);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let clip = fixture("sine_1khz.wav");
+        let mut window = vec![0.0; FFT_SIZE];
+        clip.window_at(0.1, &mut window);
+
+        let mut analyzer = AudioAnalyzer::new(0.5);
+        let first = analyzer.analyze(&window, clip.sample_rate());
+        let second = analyzer.analyze(&window, clip.sample_rate());
+        assert!((second.loudness - first.loudness * 1.5).abs() < 1e-4);
+
+        let silent = analyzer.analyze(&[], clip.sample_rate());
+        assert!((silent.loudness - second.loudness * 0.5).abs() < 1e-4);
+    }
+}
@@ -6,7 +6,7 @@
 
 use crate::util::least_power_of_2_greater;
 use bytemuck::{cast_slice, Pod};
-use std::{marker::PhantomData, mem::size_of};
+use std::{marker::PhantomData, mem::size_of, slice};
 use wgpu::{
     Buffer, BufferAddress, BufferAsyncError, BufferDescriptor, BufferUsages, CommandBuffer,
     CommandEncoderDescriptor, Device, Maintain, MapMode,
@@ -160,7 +160,7 @@ impl<D: Encodable + Sized> BufferWrapper<D> {
         });
 
         encoder.copy_buffer_to_buffer(
-            &staging_buffer,
+            staging_buffer,
             0,
             &self.buffer,
             0,
@@ -285,6 +285,6 @@ impl<E: Pod + Clone> Encodable for E {
     }
 
     fn encode(&self, write_to: &mut [u8]) {
-        write_to.copy_from_slice(cast_slice(&[self.clone()]));
+        write_to.copy_from_slice(cast_slice(slice::from_ref(self)));
     }
 }
@@ -1,6 +1,8 @@
 //! Desktop-Specific Flow implementation.
 
-use crate::flow::{FlowModel, FlowModelInit, FlowSignal, FlowStartError, WindowSize};
+use crate::flow::{
+    max_sample_count, FlowModel, FlowModelInit, FlowSignal, FlowStartError, WindowSize,
+};
 use std::{sync::Arc, time::SystemTime};
 use tokio::runtime;
 use wgpu::{
@@ -10,7 +12,7 @@ use wgpu::{
 };
 use winit::{
     dpi::PhysicalSize,
-    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoopBuilder},
     window::{Fullscreen, WindowBuilder},
 };
@@ -66,8 +68,20 @@ impl DesktopFlow {
         self
     }
 
-    /// Starts the Flow's event loop.
-    pub fn start<Model: FlowModel + 'static>(self) -> Result<!, FlowStartError> {
+    /// Starts the Flow's event loop using the model's default configuration.
+    pub fn start<Model: FlowModel + 'static>(self) -> Result<!, FlowStartError>
+    where
+        Model::Config: Default,
+    {
+        self.start_with_config::<Model>(Default::default())
+    }
+
+    /// Starts the Flow's event loop, handing the given configuration to the
+    /// model.
+    pub fn start_with_config<Model: FlowModel + 'static>(
+        self,
+        model_config: Model::Config,
+    ) -> Result<!, FlowStartError> {
         info!("Creating runtime...");
         let runtime = runtime::Builder::new_multi_thread().enable_all().build()?;
 
@@ -146,8 +160,10 @@ impl DesktopFlow {
             queue: queue.clone(),
             window_size: window_size.to_window_size(),
             frame_format: config.format,
+            max_sample_count: max_sample_count(&adapter, config.format),
+            adapter_info: adapter.get_info(),
         };
-        let mut model: Option<Model> = Some(runtime.block_on(Model::init(init)));
+        let mut model: Option<Model> = Some(runtime.block_on(Model::init(init, model_config)));
         let mut previous_update = SystemTime::now();
         let mut previous_render = SystemTime::now();
 
@@ -193,6 +209,17 @@ impl DesktopFlow {
                     } => {
                         *control = ControlFlow::Exit;
                     },
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F3),
+                                ..
+                            },
+                        ..
+                    } => {
+                        model.as_mut().unwrap().toggle_debug();
+                    },
                     _ => {},
                 },
                 Event::MainEventsCleared => {
@@ -232,6 +259,9 @@ impl DesktopFlow {
 
                         frame.present();
                     }
+
+                    #[cfg(feature = "timer")]
+                    crate::timer::end_frame();
                 },
                 Event::LoopDestroyed => {
                     info!("Shutting down...");
@@ -249,6 +279,9 @@ impl DesktopFlow {
                     // shutdown the runtime
                     drop(runtime);
 
+                    #[cfg(feature = "timer")]
+                    crate::timer::finish();
+
                     info!("Done.");
                 },
                 _ => {},
@@ -257,6 +290,12 @@ impl DesktopFlow {
     }
 }
 
+impl Default for DesktopFlow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 trait ToWindowSize {
     fn to_window_size(&self) -> WindowSize;
 }
@@ -6,7 +6,10 @@ mod desktop;
 mod web;
 
 use std::{io, sync::Arc, time::Duration};
-use wgpu::{Device, Queue, RequestDeviceError, TextureFormat, TextureView};
+use wgpu::{
+    Adapter, AdapterInfo, Device, Queue, RequestDeviceError, TextureFormat,
+    TextureFormatFeatureFlags, TextureView,
+};
 
 #[cfg(not(target_arch = "wasm32"))]
 pub use desktop::DesktopFlow;
@@ -27,6 +30,11 @@ pub struct FlowModelInit {
     pub queue: Arc<Queue>,
     pub window_size: WindowSize,
     pub frame_format: TextureFormat,
+    /// The largest MSAA sample count that can be rendered into the frame
+    /// format and resolved. This is 1 when multisampling is unsupported.
+    pub max_sample_count: u32,
+    /// Describes the adapter the device was requested from.
+    pub adapter_info: AdapterInfo,
 }
 
 /// Represents an application's data, allowing the application to receive
@@ -34,7 +42,13 @@ pub struct FlowModelInit {
 /// support an asynchronous application.
 #[async_trait]
 pub trait FlowModel {
-    async fn init(init: FlowModelInit) -> Self
+    /// Application-specific configuration handed to the model when it is
+    /// initialized.
+    type Config: Send + 'static
+    where
+        Self: Sized;
+
+    async fn init(init: FlowModelInit, config: Self::Config) -> Self
     where
         Self: Sized;
 
@@ -45,9 +59,28 @@ pub trait FlowModel {
 
     fn render(&mut self, frame_view: &TextureView, render_delta: Duration);
 
+    /// Shows or hides debugging information, when asked to by the user.
+    fn toggle_debug(&mut self) {}
+
     fn shutdown(&mut self);
 }
 
+/// The sample count every adapter supporting multisampling of a format must
+/// allow.
+const GUARANTEED_SAMPLE_COUNT: u32 = 4;
+
+/// Determines the largest MSAA sample count usable with the frame format.
+fn max_sample_count(adapter: &Adapter, frame_format: TextureFormat) -> u32 {
+    let flags = adapter.get_texture_format_features(frame_format).flags;
+    if flags.contains(
+        TextureFormatFeatureFlags::MULTISAMPLE | TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE,
+    ) {
+        GUARANTEED_SAMPLE_COUNT
+    } else {
+        1
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum FlowStartError {
     #[error("IO error")]
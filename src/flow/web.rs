@@ -1,19 +1,19 @@
 //! Web-Specific Flow implementation.
 
-use crate::flow::{FlowModel, FlowModelInit, FlowStartError, WindowSize};
+use crate::{
+    flow::{max_sample_count, FlowModel, FlowModelInit, FlowStartError, WindowSize},
+    util::now,
+};
 use futures::lock::Mutex;
 use js_sys::Promise;
 use raw_window_handle::{
     HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle, WebDisplayHandle,
     WebWindowHandle,
 };
-use std::{
-    sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use std::{sync::Arc, time::SystemTime};
 use wasm_bindgen::{prelude::wasm_bindgen, JsCast, JsValue};
 use wasm_bindgen_futures::future_to_promise;
-use web_sys::{Element, HtmlCanvasElement};
+use web_sys::{Element, HtmlCanvasElement, WebGl2RenderingContext};
 use wgpu::{
     Backends, CompositeAlphaMode, Device, DeviceDescriptor, Instance, Limits, PresentMode, Queue,
     RequestAdapterOptions, Surface, SurfaceConfiguration, TextureFormat, TextureUsages,
@@ -44,7 +44,17 @@ impl WebFlowBuilder {
         self
     }
 
-    pub async fn start<Model: FlowModel + 'static>(self) -> Result<WebFlow, FlowStartError> {
+    pub async fn start<Model: FlowModel + 'static>(self) -> Result<WebFlow, FlowStartError>
+    where
+        Model::Config: Default,
+    {
+        self.start_with_config::<Model>(Default::default()).await
+    }
+
+    pub async fn start_with_config<Model: FlowModel + 'static>(
+        self,
+        model_config: Model::Config,
+    ) -> Result<WebFlow, FlowStartError> {
         let Self { canvas_container_id, canvas_id } = self;
 
         info!("Getting window data...");
@@ -130,6 +140,15 @@ impl WebFlowBuilder {
 
         surface.configure(&device, &config);
 
+        // WebGL2 only guarantees a minimum of 4 samples for multisampled renderbuffers,
+        // and some implementations support fewer for certain formats, so ask the
+        // context what it actually allows.
+        let max_sample_count = webgl2_max_samples(&canvas)
+            .map_or(max_sample_count(&adapter, config.format), |max_samples| {
+                max_sample_count(&adapter, config.format).min(max_samples)
+            });
+        info!("Max sample count: {}", max_sample_count);
+
         // setup model
         info!("Creating model...");
         let init = FlowModelInit {
@@ -137,8 +156,11 @@ impl WebFlowBuilder {
             queue: queue.clone(),
             window_size,
             frame_format: config.format,
+            max_sample_count,
+            adapter_info: adapter.get_info(),
         };
-        let model: Arc<Mutex<dyn FlowModel>> = Arc::new(Mutex::new(Model::init(init).await));
+        let model: Arc<Mutex<dyn FlowModel>> =
+            Arc::new(Mutex::new(Model::init(init, model_config).await));
 
         let previous_render = now();
 
@@ -264,11 +286,13 @@ fn set_canvas_size(canvas_element: &Element, window_size: &WindowSize) {
         .unwrap();
 }
 
-#[cfg(target_arch = "wasm32")]
-fn now() -> SystemTime {
-    let performance = web_sys::window().unwrap().performance().unwrap();
-    let amt = performance.now();
-    let secs = (amt as u64) / 1_000;
-    let nanos = ((amt as u32) % 1_000) * 1_000_000;
-    UNIX_EPOCH + Duration::new(secs, nanos)
+/// Queries the maximum multisample count of the canvas' WebGL2 context, if the
+/// canvas is using one.
+fn webgl2_max_samples(canvas: &HtmlCanvasElement) -> Option<u32> {
+    let context: WebGl2RenderingContext = canvas.get_context("webgl2").ok()??.dyn_into().ok()?;
+    let max_samples = context
+        .get_parameter(WebGl2RenderingContext::MAX_SAMPLES)
+        .ok()?
+        .as_f64()?;
+    Some((max_samples as u32).max(1))
 }
@@ -1,11 +1,134 @@
+//! A grid of chunks for quickly finding points that are near each other.
+//!
+//! Points are kept in one array sorted by chunk, so each chunk is a contiguous
+//! slice of it, and they are sorted again with a counting sort whenever they
+//! all move.
+
+use crate::spatial;
 #[cfg(feature = "timer")]
 use crate::timer::Timer;
+#[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+use rayon::prelude::*;
 
 pub struct Grid<P: Positioned + Clone> {
     position_offset: f32,
     chunk_size: f32,
-    chunks: Vec<Vec<Vec<P>>>,
-    tmp: Vec<P>,
+    x_chunks: usize,
+    y_chunks: usize,
+    /// Every point, sorted by the chunk it is in, with chunks in rows from the
+    /// bottom.
+    entries: Vec<Entry<P>>,
+    /// Where each chunk's points start in `entries`, indexed by
+    /// `y * x_chunks + x`, followed by the number of entries.
+    chunk_starts: Vec<usize>,
+    /// Where each entry goes while sorting, kept to reuse its memory.
+    destinations: Vec<usize>,
+    torus: Option<Torus>,
+    /// Where each key's point is in `entries`, indexed by `GridKey::slot`.
+    slots: Vec<Slot>,
+    /// The slots without a point, to be reused before adding new ones.
+    free_slots: Vec<usize>,
+}
+
+/// Identifies a point in a grid for as long as it stays there. Keys of removed
+/// points aren't given to new points, so they stay invalid.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct GridKey {
+    slot: usize,
+    generation: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Entry<P> {
+    key: GridKey,
+    point: P,
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    /// Counts the points that have had this slot, to tell their keys apart.
+    generation: u32,
+    /// The index of the point in `entries`.
+    location: Option<usize>,
+}
+
+/// Where a grid's chunks are, for drawing them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChunkLayout {
+    /// The bottom-left corner of the bottom-left chunk.
+    pub origin: (f32, f32),
+    pub chunk_width: f32,
+    pub chunk_height: f32,
+    pub x_chunks: usize,
+    pub y_chunks: usize,
+}
+
+/// The shape of a grid whose opposite edges are joined, so that points near
+/// one edge are neighbors of points near the other.
+#[derive(Debug, Clone)]
+struct Torus {
+    width: f32,
+    height: f32,
+    /// Chunks are stretched so that a whole number of them covers the torus,
+    /// which keeps them at least `chunk_size` across.
+    chunk_width: f32,
+    chunk_height: f32,
+    /// Kept rather than worked out from the chunk sizes, which would divide
+    /// zero by zero for a torus with no width or height.
+    x_chunks: usize,
+    y_chunks: usize,
+    /// Every distinct pair of neighboring chunks, as `(x, y)` coordinates,
+    /// including the ones that neighbor across the edges.
+    neighbor_pairs: Vec<ChunkPair>,
+}
+
+/// Two chunks, as `(x, y)` coordinates.
+type ChunkPair = ((usize, usize), (usize, usize));
+
+impl Torus {
+    fn new(chunk_size: f32, width: f32, height: f32) -> Torus {
+        let x_chunks = ((width / chunk_size).floor() as usize).max(1);
+        let y_chunks = ((height / chunk_size).floor() as usize).max(1);
+
+        // With only one or two chunks across, several offsets wrap around to
+        // the same neighbor, so duplicates have to be removed.
+        let mut neighbor_pairs = vec![];
+        for y in 0..y_chunks {
+            for x in 0..x_chunks {
+                for (dx, dy) in [(1, 0), (-1, 1), (0, 1), (1, 1)] {
+                    let other_x = (x as isize + dx).rem_euclid(x_chunks as isize) as usize;
+                    let other_y = (y as isize + dy).rem_euclid(y_chunks as isize) as usize;
+                    let other = (other_x, other_y);
+                    if other != (x, y) {
+                        neighbor_pairs.push(((x, y).min(other), (x, y).max(other)));
+                    }
+                }
+            }
+        }
+        neighbor_pairs.sort_unstable();
+        neighbor_pairs.dedup();
+
+        Torus {
+            width,
+            height,
+            chunk_width: width / x_chunks as f32,
+            chunk_height: height / y_chunks as f32,
+            x_chunks,
+            y_chunks,
+            neighbor_pairs,
+        }
+    }
+
+    /// Gets how far `b` has to be moved to get its image nearest to `a`.
+    fn image_offset(&self, ax: f32, ay: f32, bx: f32, by: f32) -> (f32, f32) {
+        spatial::image_offset(bx - ax, by - ay, self.width, self.height)
+    }
+
+    /// Gets the offset from `a` to the nearest image of `b`.
+    fn displacement(&self, ax: f32, ay: f32, bx: f32, by: f32) -> (f32, f32) {
+        let (offset_x, offset_y) = self.image_offset(ax, ay, bx, by);
+        (bx + offset_x - ax, by + offset_y - ay)
+    }
 }
 
 pub trait Positioned {
@@ -21,243 +144,1240 @@ impl<P: Positioned + Clone> Grid<P> {
         let x_chunks = (width / chunk_size).ceil() as usize;
         let y_chunks = (height / chunk_size).ceil() as usize;
 
-        let mut chunks = vec![];
-        for _y in 0..y_chunks {
-            let mut strip = vec![];
-            for _x in 0..x_chunks {
-                strip.push(vec![]);
-            }
-            chunks.push(strip);
+        Grid {
+            position_offset,
+            chunk_size,
+            x_chunks,
+            y_chunks,
+            entries: vec![],
+            chunk_starts: vec![0; x_chunks * y_chunks + 1],
+            destinations: vec![],
+            torus: None,
+            slots: vec![],
+            free_slots: vec![],
         }
+    }
+
+    /// Creates a grid covering `0..width` and `0..height` whose opposite edges
+    /// are joined. Distances are measured to the nearest wrapped image of each
+    /// point. Points are expected to already be wrapped into the grid.
+    pub fn new_torus(chunk_size: f32, width: f32, height: f32) -> Grid<P> {
+        let torus = Torus::new(chunk_size, width, height);
+        let (x_chunks, y_chunks) = (torus.x_chunks, torus.y_chunks);
 
         Grid {
-            position_offset,
+            position_offset: 0.0,
             chunk_size,
-            chunks,
-            tmp: vec![],
+            x_chunks,
+            y_chunks,
+            entries: vec![],
+            chunk_starts: vec![0; x_chunks * y_chunks + 1],
+            destinations: vec![],
+            torus: Some(torus),
+            slots: vec![],
+            free_slots: vec![],
         }
     }
 
     pub fn set_size(&mut self, width: f32, height: f32) {
+        if self.torus.is_some() {
+            // Chunk sizes depend on the torus size, so every point has to be
+            // sorted again.
+            let torus = Torus::new(self.chunk_size, width, height);
+            self.x_chunks = torus.x_chunks;
+            self.y_chunks = torus.y_chunks;
+            self.torus = Some(torus);
+            self.sort();
+            return;
+        }
+
         let x_chunks = (width / self.chunk_size).ceil() as usize;
         let y_chunks = (height / self.chunk_size).ceil() as usize;
 
-        for strip in self.chunks.iter_mut() {
-            if x_chunks > strip.len() {
-                for _ in strip.len()..x_chunks {
-                    strip.push(vec![]);
+        // Points in chunks past the new edges are folded back into the grid,
+        // as if the area that was lost wrapped around onto what is left.
+        for chunk_y in 0..self.y_chunks {
+            for chunk_x in 0..self.x_chunks {
+                let dif_x = (chunk_x - chunk_x % x_chunks) as f32 * self.chunk_size;
+                let dif_y = (chunk_y - chunk_y % y_chunks) as f32 * self.chunk_size;
+                if dif_x == 0.0 && dif_y == 0.0 {
+                    continue;
                 }
-            } else if x_chunks < strip.len() {
-                for x in (x_chunks..strip.len()).rev() {
-                    let chunk = strip.remove(x);
-                    let new_x = x % x_chunks;
-                    let new_chunk = &mut strip[new_x];
-                    let dif_x = (x - new_x) as f32 * self.chunk_size;
-                    for mut p in chunk {
-                        *p.x_mut() -= dif_x;
-                        new_chunk.push(p);
-                    }
+
+                let chunk = chunk_y * self.x_chunks + chunk_x;
+                let range = self.chunk_starts[chunk]..self.chunk_starts[chunk + 1];
+                for entry in self.entries[range].iter_mut() {
+                    *entry.point.x_mut() -= dif_x;
+                    *entry.point.y_mut() -= dif_y;
                 }
             }
         }
 
-        if y_chunks > self.chunks.len() {
-            for _ in self.chunks.len()..y_chunks {
-                let mut strip = vec![];
-                for _ in 0..x_chunks {
-                    strip.push(vec![]);
-                }
-                self.chunks.push(strip);
-            }
-        } else if y_chunks < self.chunks.len() {
-            for y in (y_chunks..self.chunks.len()).rev() {
-                let strip = self.chunks.remove(y);
-                let new_y = y % y_chunks;
-                let new_strip = &mut self.chunks[new_y];
-                let dif_y = (y - new_y) as f32 * self.chunk_size;
-                for (x, chunk) in strip.into_iter().enumerate() {
-                    let new_chunk = &mut new_strip[x];
-                    for mut p in chunk {
-                        *p.y_mut() -= dif_y;
-                        new_chunk.push(p);
-                    }
-                }
+        // Points outside the old grid were clamped into its edge chunks, and
+        // may belong in a new chunk now that it has grown, which sorting again
+        // takes care of.
+        self.x_chunks = x_chunks;
+        self.y_chunks = y_chunks;
+        self.sort();
+    }
+
+    /// Sorts the entries by chunk with a counting sort, keeping the entries in
+    /// each chunk in the order they were in.
+    fn sort(&mut self) {
+        let chunk_count = self.x_chunks * self.y_chunks;
+        let mut destinations = std::mem::take(&mut self.destinations);
+        destinations.clear();
+        destinations.extend(
+            self.entries
+                .iter()
+                .map(|entry| self.chunk_index(&entry.point)),
+        );
+
+        self.chunk_starts.clear();
+        self.chunk_starts.resize(chunk_count + 1, 0);
+        for &chunk in destinations.iter() {
+            self.chunk_starts[chunk + 1] += 1;
+        }
+        for chunk in 0..chunk_count {
+            self.chunk_starts[chunk + 1] += self.chunk_starts[chunk];
+        }
+
+        // Each entry goes after the entries before it in the same chunk. This
+        // moves each chunk's start up to the next one's, so they are moved
+        // back afterwards.
+        for destination in destinations.iter_mut() {
+            let chunk = *destination;
+            *destination = self.chunk_starts[chunk];
+            self.chunk_starts[chunk] += 1;
+        }
+        self.chunk_starts.copy_within(..chunk_count, 1);
+        self.chunk_starts[0] = 0;
+
+        // Move the entries into place by following each cycle of the
+        // permutation, so they don't need to be copied into another array.
+        for index in 0..destinations.len() {
+            while destinations[index] != index {
+                let destination = destinations[index];
+                self.entries.swap(index, destination);
+                destinations.swap(index, destination);
             }
         }
+        self.destinations = destinations;
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            self.slots[entry.key.slot].location = Some(index);
+        }
     }
 
-    pub fn insert(&mut self, p: P) {
-        let x = (((p.x() + self.position_offset) / self.chunk_size)
+    fn chunk_coords(&self, p: &P) -> (usize, usize) {
+        let (chunk_width, chunk_height) = match self.torus.as_ref() {
+            Some(torus) => (torus.chunk_width, torus.chunk_height),
+            None => (self.chunk_size, self.chunk_size),
+        };
+        let x = (((p.x() + self.position_offset) / chunk_width)
             .floor()
             .max(0.0) as usize)
-            .min(self.chunks[0].len() - 1);
-        let y = (((p.y() + self.position_offset) / self.chunk_size)
+            .min(self.x_chunks - 1);
+        let y = (((p.y() + self.position_offset) / chunk_height)
             .floor()
             .max(0.0) as usize)
-            .min(self.chunks.len() - 1);
-        self.chunks[y][x].push(p);
+            .min(self.y_chunks - 1);
+        (x, y)
     }
 
-    #[allow(dead_code)]
-    pub fn clear(&mut self) {
-        for strip in self.chunks.iter_mut() {
-            for chunk in strip.iter_mut() {
-                chunk.clear();
-            }
+    /// Gets the index of the chunk a point's position belongs in.
+    fn chunk_index(&self, p: &P) -> usize {
+        let (x, y) = self.chunk_coords(p);
+        y * self.x_chunks + x
+    }
+
+    /// Gets the entries in the chunk at `x` and `y`.
+    fn chunk(&self, x: usize, y: usize) -> &[Entry<P>] {
+        let chunk = y * self.x_chunks + x;
+        &self.entries[self.chunk_starts[chunk]..self.chunk_starts[chunk + 1]]
+    }
+
+    /// Gets the index of the chunk holding the entry at `index`. This may not
+    /// be the chunk its position belongs in, if it was moved through
+    /// `get_mut` or `iter_mut`.
+    fn chunk_holding(&self, index: usize) -> usize {
+        self.chunk_starts.partition_point(|&start| start <= index) - 1
+    }
+
+    /// Describes where the chunks are, in the same coordinates as the points.
+    pub fn chunk_layout(&self) -> ChunkLayout {
+        let (chunk_width, chunk_height) = match self.torus.as_ref() {
+            Some(torus) => (torus.chunk_width, torus.chunk_height),
+            None => (self.chunk_size, self.chunk_size),
+        };
+        ChunkLayout {
+            origin: (-self.position_offset, -self.position_offset),
+            chunk_width,
+            chunk_height,
+            x_chunks: self.x_chunks,
+            y_chunks: self.y_chunks,
         }
     }
 
-    pub fn all_mut<F: FnMut(&mut P)>(&mut self, mut f: F) {
-        for strip in self.chunks.iter_mut() {
-            for chunk in strip.iter_mut() {
-                self.tmp.append(chunk);
+    /// Gets how many points are in each chunk, in rows from the bottom.
+    pub fn chunk_lens(&self) -> impl Iterator<Item = usize> + '_ {
+        self.chunk_starts
+            .windows(2)
+            .map(|range| range[1] - range[0])
+    }
+
+    /// Adds a point, returning the key to find it by.
+    pub fn insert(&mut self, p: P) -> GridKey {
+        // The new entry starts out past the last chunk, and is moved down
+        // into its own.
+        let key = self.new_key();
+        let chunk = self.chunk_index(&p);
+        self.entries.push(Entry { key, point: p });
+        let index = self.entries.len() - 1;
+        self.slots[key.slot].location = Some(index);
+        self.move_entry(index, self.chunk_starts.len() - 1, chunk);
+        key
+    }
+
+    /// Adds many points, sorting them all into their chunks at once rather
+    /// than moving each one past the chunks in between like `insert` does.
+    pub fn extend<I: IntoIterator<Item = P>>(&mut self, points: I) {
+        for p in points {
+            let key = self.new_key();
+            self.entries.push(Entry { key, point: p });
+        }
+        self.sort();
+    }
+
+    /// Takes a free slot, or adds one, for a new point's key.
+    fn new_key(&mut self) -> GridKey {
+        let slot = match self.free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(Slot { generation: 0, location: None });
+                self.slots.len() - 1
+            },
+        };
+        GridKey {
+            slot,
+            generation: self.slots[slot].generation,
+        }
+    }
+
+    /// Moves the entry at `index` from chunk `from` to chunk `to`, by moving
+    /// one entry of each chunk in between across the gap, which keeps every
+    /// chunk contiguous. Chunk `x_chunks * y_chunks` is the end of the
+    /// entries, past the last chunk.
+    fn move_entry(&mut self, mut index: usize, from: usize, to: usize) {
+        if from < to {
+            // Swap the entry to the end of each chunk, and then shrink that
+            // chunk to leave it at the start of the next.
+            for chunk in from..to {
+                let last = self.chunk_starts[chunk + 1] - 1;
+                self.swap(index, last);
+                index = last;
+                self.chunk_starts[chunk + 1] -= 1;
+            }
+        } else {
+            for chunk in (to + 1..=from).rev() {
+                let first = self.chunk_starts[chunk];
+                self.swap(index, first);
+                index = first;
+                self.chunk_starts[chunk] += 1;
             }
         }
+    }
+
+    /// Swaps two entries, keeping their locations up to date.
+    fn swap(&mut self, a: usize, b: usize) {
+        self.entries.swap(a, b);
+        self.slots[self.entries[a].key.slot].location = Some(a);
+        self.slots[self.entries[b].key.slot].location = Some(b);
+    }
 
-        for mut p in self.tmp.drain(..) {
-            f(&mut p);
-            let x = (((p.x() + self.position_offset) / self.chunk_size)
-                .floor()
-                .max(0.0) as usize)
-                .min(self.chunks[0].len() - 1);
-            let y = (((p.y() + self.position_offset) / self.chunk_size)
-                .floor()
-                .max(0.0) as usize)
-                .min(self.chunks.len() - 1);
-            self.chunks[y][x].push(p);
+    /// Marks a key's slot as free, so the key no longer finds anything.
+    fn free(&mut self, key: GridKey) {
+        let slot = &mut self.slots[key.slot];
+        slot.location = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(key.slot);
+    }
+
+    fn location(&self, key: GridKey) -> Option<usize> {
+        self.slots
+            .get(key.slot)
+            .filter(|slot| slot.generation == key.generation)
+            .and_then(|slot| slot.location)
+    }
+
+    pub fn get(&self, key: GridKey) -> Option<&P> {
+        let index = self.location(key)?;
+        Some(&self.entries[index].point)
+    }
+
+    /// Gets a point mutably. Its position shouldn't be changed through this,
+    /// since it would stay in its old chunk; use `update_position` instead.
+    pub fn get_mut(&mut self, key: GridKey) -> Option<&mut P> {
+        let index = self.location(key)?;
+        Some(&mut self.entries[index].point)
+    }
+
+    /// Removes a point, returning it if the key still had one.
+    pub fn remove(&mut self, key: GridKey) -> Option<P> {
+        let index = self.location(key)?;
+        let end = self.chunk_starts.len() - 1;
+        self.move_entry(index, self.chunk_holding(index), end);
+        let entry = self.entries.pop().unwrap();
+        self.free(key);
+        Some(entry.point)
+    }
+
+    /// Moves a point, moving only it to its new chunk if it has left the old
+    /// one. Returns false if the key no longer has a point.
+    pub fn update_position(&mut self, key: GridKey, x: f32, y: f32) -> bool {
+        let index = match self.location(key) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let point = &mut self.entries[index].point;
+        *point.x_mut() = x;
+        *point.y_mut() = y;
+        let chunk = self.chunk_index(&self.entries[index].point);
+        let old_chunk = self.chunk_holding(index);
+        if chunk != old_chunk {
+            self.move_entry(index, old_chunk, chunk);
+        }
+        true
+    }
+
+    pub fn clear(&mut self) {
+        let mut entries = std::mem::take(&mut self.entries);
+        for entry in entries.drain(..) {
+            self.free(entry.key);
+        }
+        self.entries = entries;
+        self.chunk_starts.fill(0);
+    }
+
+    pub fn all_mut<F: FnMut(&mut P)>(&mut self, mut f: F) {
+        for entry in self.entries.iter_mut() {
+            f(&mut entry.point);
         }
+        self.sort();
+    }
+
+    /// Removes every point for which `f` returns false.
+    pub fn retain<F: FnMut(&P) -> bool>(&mut self, mut f: F) {
+        let mut entries = std::mem::take(&mut self.entries);
+        entries.retain(|entry| {
+            let keep = f(&entry.point);
+            if !keep {
+                self.free(entry.key);
+            }
+            keep
+        });
+        self.entries = entries;
+
+        // The entries are still in order, so this only finds where the chunks
+        // start now.
+        self.sort();
+    }
+
+    /// Iterates over every point, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &P> + '_ {
+        self.entries.iter().map(|entry| &entry.point)
+    }
+
+    /// Iterates over every point along with its key, in no particular order.
+    pub fn iter_keys(&self) -> impl Iterator<Item = (GridKey, &P)> + '_ {
+        self.entries.iter().map(|entry| (entry.key, &entry.point))
+    }
+
+    /// Iterates mutably over every point, in no particular order.
+    ///
+    /// Points stay in the chunks they were in, so moving them through this
+    /// leaves them out of place until the next `all_mut`. Use `all_mut` to
+    /// move points instead.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut P> + '_ {
+        self.entries.iter_mut().map(|entry| &mut entry.point)
+    }
+
+    /// Counts the points in the grid.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
     }
 
-    #[allow(dead_code)]
     pub fn all_within<F: FnMut(&P, f32)>(&self, x: f32, y: f32, distance: f32, mut f: F) {
+        for (p, distance_sqr) in self.within(x, y, distance) {
+            f(p, distance_sqr);
+        }
+    }
+
+    /// Iterates over every point closer than `distance` to `x` and `y`, along
+    /// with its squared distance. On a torus, distances are measured to the
+    /// nearest images of points.
+    pub fn within(&self, x: f32, y: f32, distance: f32) -> impl Iterator<Item = (&P, f32)> + '_ {
+        let max_distance_sqr = distance * distance;
+        let ((min_x, max_x), (min_y, max_y)) = self.chunk_range(x, y, distance);
+        let x_chunks = self.x_chunks as isize;
+        let y_chunks = self.y_chunks as isize;
+        (min_y..=max_y)
+            .flat_map(move |chunk_y| (min_x..=max_x).map(move |chunk_x| (chunk_x, chunk_y)))
+            .flat_map(move |(chunk_x, chunk_y)| {
+                self.chunk(
+                    chunk_x.rem_euclid(x_chunks) as usize,
+                    chunk_y.rem_euclid(y_chunks) as usize,
+                )
+                .iter()
+                .map(|entry| &entry.point)
+            })
+            .filter_map(move |p| {
+                let (x, y) = self.displacement(x, y, p);
+                let distance_sqr = x * x + y * y;
+                (distance_sqr < max_distance_sqr).then_some((p, distance_sqr))
+            })
+    }
+
+    /// Finds the `k` points nearest to `x` and `y` that are closer than
+    /// `max_distance`, nearest first, along with their squared distances. They
+    /// replace what `found` held, so that it can be reused between searches.
+    pub fn k_nearest<'a>(
+        &'a self,
+        x: f32,
+        y: f32,
+        k: usize,
+        max_distance: f32,
+        found: &mut Vec<(&'a P, f32)>,
+    ) {
+        found.clear();
+        found.extend(self.within(x, y, max_distance));
+        spatial::nearest(found, k);
+    }
+
+    /// Gets the inclusive ranges of chunk coordinates that may hold points
+    /// within `distance` of `x` and `y`. On a torus, the ranges may go past
+    /// the edges and have to be wrapped.
+    fn chunk_range(&self, x: f32, y: f32, distance: f32) -> ((isize, isize), (isize, isize)) {
+        let x_chunks = self.x_chunks as isize;
+        let y_chunks = self.y_chunks as isize;
+
+        if let Some(torus) = self.torus.as_ref() {
+            let min_x = ((x - distance) / torus.chunk_width).floor() as isize;
+            let min_y = ((y - distance) / torus.chunk_height).floor() as isize;
+            // Once the range covers the whole torus, wrapping would visit
+            // chunks twice.
+            let max_x =
+                (((x + distance) / torus.chunk_width).floor() as isize).min(min_x + x_chunks - 1);
+            let max_y =
+                (((y + distance) / torus.chunk_height).floor() as isize).min(min_y + y_chunks - 1);
+            return ((min_x, max_x), (min_y, max_y));
+        }
+
         let x = x + self.position_offset;
         let y = y + self.position_offset;
-        let max_distance_sqr = distance * distance;
-        let min_x = ((x - distance - 1.0) / self.chunk_size).floor().max(0.0) as usize;
-        let min_y = ((y - distance - 1.0) / self.chunk_size).floor().max(0.0) as usize;
-        let max_x = (((x + distance + 1.0) / self.chunk_size).floor() as usize)
-            .min(self.chunks[0].len() - 1);
+        // Points outside the grid are clamped into its edge chunks, so those
+        // have to be checked even when the range starts past them.
+        let min_x =
+            (((x - distance - 1.0) / self.chunk_size).floor().max(0.0) as isize).min(x_chunks - 1);
+        let min_y =
+            (((y - distance - 1.0) / self.chunk_size).floor().max(0.0) as isize).min(y_chunks - 1);
+        let max_x =
+            (((x + distance + 1.0) / self.chunk_size).floor().max(0.0) as isize).min(x_chunks - 1);
         let max_y =
-            (((y + distance + 1.0) / self.chunk_size).floor() as usize).min(self.chunks.len() - 1);
-        for strip in &self.chunks[min_y..=max_y] {
-            for chunk in &strip[min_x..=max_x] {
-                for p in chunk.iter() {
-                    let x = (p.x() + self.position_offset) - x;
-                    let y = (p.y() + self.position_offset) - y;
-                    let distance_sqr = x * x + y * y;
-                    if distance_sqr < max_distance_sqr {
-                        f(p, distance_sqr);
-                    }
-                }
-            }
+            (((y + distance + 1.0) / self.chunk_size).floor().max(0.0) as isize).min(y_chunks - 1);
+        ((min_x, max_x), (min_y, max_y))
+    }
+
+    /// Gets the offset from `x` and `y` to a point, or to its nearest image on
+    /// a torus.
+    fn displacement(&self, x: f32, y: f32, p: &P) -> (f32, f32) {
+        match self.torus.as_ref() {
+            Some(torus) => torus.displacement(x, y, p.x(), p.y()),
+            None => (
+                (p.x() + self.position_offset) - (x + self.position_offset),
+                (p.y() + self.position_offset) - (y + self.position_offset),
+            ),
+        }
+    }
+
+    /// Iterates over every pair of points closer than the chunk size, along
+    /// with their squared distance, like `pairs` does. On a torus, distances
+    /// are measured to the nearest images of points.
+    pub fn pairs_iter(&self) -> impl Iterator<Item = (&P, &P, f32)> + '_ {
+        let max_distance_sqr = self.chunk_size * self.chunk_size;
+
+        let chunks =
+            (0..self.y_chunks).flat_map(move |y| (0..self.x_chunks).map(move |x| self.chunk(x, y)));
+        let self_pairs = chunks.flat_map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .flat_map(move |(i, p)| chunk[i + 1..].iter().map(move |op| (p, op)))
+                .map(|(p, op)| (&p.point, &op.point))
+        });
+        let neighbor_pairs = self
+            .neighbor_chunk_pairs()
+            .flat_map(move |((ax, ay), (bx, by))| {
+                let other = self.chunk(bx, by);
+                self.chunk(ax, ay)
+                    .iter()
+                    .flat_map(move |p| other.iter().map(move |op| (&p.point, &op.point)))
+            });
+
+        self_pairs.chain(neighbor_pairs).filter_map(move |(p, op)| {
+            let (distance_sqr, _) = self.pair_distance_sqr(p, op);
+            (distance_sqr < max_distance_sqr).then_some((p, op, distance_sqr))
+        })
+    }
+
+    /// Iterates over every distinct pair of neighboring chunks, as `(x, y)`
+    /// coordinates.
+    fn neighbor_chunk_pairs(&self) -> Box<dyn Iterator<Item = ChunkPair> + '_> {
+        if let Some(torus) = self.torus.as_ref() {
+            return Box::new(torus.neighbor_pairs.iter().copied());
         }
+
+        // The over, below, over-below and back-below neighbors of each chunk.
+        let x_chunks = self.x_chunks as isize;
+        let y_chunks = self.y_chunks as isize;
+        Box::new((0..y_chunks).flat_map(move |y| {
+            (0..x_chunks).flat_map(move |x| {
+                [(1, 0), (0, 1), (1, 1), (-1, 1)]
+                    .into_iter()
+                    .map(move |(dx, dy)| (x + dx, y + dy))
+                    .filter(move |&(other_x, other_y)| {
+                        (0..x_chunks).contains(&other_x) && other_y < y_chunks
+                    })
+                    .map(move |(other_x, other_y)| {
+                        (
+                            (x as usize, y as usize),
+                            (other_x as usize, other_y as usize),
+                        )
+                    })
+            })
+        }))
     }
 
-    pub fn pairs<F: FnMut(&P, &P, f32)>(&mut self, mut f: F) {
+    pub fn pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, mut f: F) {
         #[cfg(feature = "timer")]
         let _timer = Timer::from_str("Grid::pairs");
 
+        for y in 0..self.y_chunks {
+            self.strip_pairs(y, &mut f);
+        }
+        if let Some(torus) = self.torus.as_ref() {
+            for &chunk_pair in torus.neighbor_pairs.iter() {
+                self.chunk_pair_pairs(chunk_pair, &mut f);
+            }
+        }
+    }
+
+    /// Like `pairs`, but searching strips of chunks on several threads. The
+    /// pairs are collected and then passed to `f` on this thread, in the same
+    /// order `pairs` finds them.
+    #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+    pub fn par_pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, mut f: F)
+    where
+        P: Sync,
+    {
+        #[cfg(feature = "timer")]
+        let _timer = Timer::from_str("Grid::par_pairs");
+
+        let strips = (0..self.y_chunks).into_par_iter().map(|y| {
+            let mut found = vec![];
+            self.strip_pairs(y, &mut |p, op, distance_sqr, offset| {
+                found.push((p, op, distance_sqr, offset))
+            });
+            found
+        });
+        let found: Vec<Vec<spatial::FoundPair<P>>> = match self.torus.as_ref() {
+            Some(torus) => strips
+                .chain(torus.neighbor_pairs.par_iter().map(|&chunk_pair| {
+                    let mut found = vec![];
+                    self.chunk_pair_pairs(chunk_pair, &mut |p, op, distance_sqr, offset| {
+                        found.push((p, op, distance_sqr, offset))
+                    });
+                    found
+                }))
+                .collect(),
+            None => strips.collect(),
+        };
+
+        for (p, op, distance_sqr, offset) in found.into_iter().flatten() {
+            f(p, op, distance_sqr, offset);
+        }
+    }
+
+    /// Gets the squared distance between two points, or between their nearest
+    /// images on a torus, along with how far `op` has to be moved to get its
+    /// image nearest to `p`.
+    fn pair_distance_sqr(&self, p: &P, op: &P) -> (f32, (f32, f32)) {
+        let offset = match self.torus.as_ref() {
+            Some(torus) => torus.image_offset(p.x(), p.y(), op.x(), op.y()),
+            None => (0.0, 0.0),
+        };
+        let (x, y) = (op.x() + offset.0 - p.x(), op.y() + offset.1 - p.y());
+        (x * x + y * y, offset)
+    }
+
+    /// Finds the pairs whose first point is in strip `y`. On a torus, that is
+    /// only the pairs within each chunk, since the pairs between chunks are
+    /// found by `chunk_pair_pairs`.
+    fn strip_pairs<'a, F: FnMut(&'a P, &'a P, f32, (f32, f32))>(&'a self, y: usize, f: &mut F) {
+        #[cfg(feature = "timer")]
+        let _timer = Timer::new(format!("Grid::pairs y={}", y));
+
         let max_distance_sqr = self.chunk_size * self.chunk_size;
-        let mut try_call = |p: &P, op: &P| {
-            let x = p.x() - op.x();
-            let y = p.y() - op.y();
-            let distance_sqr = x * x + y * y;
+        let mut try_call = |p: &'a Entry<P>, op: &'a Entry<P>| {
+            let (distance_sqr, offset) = self.pair_distance_sqr(&p.point, &op.point);
             if distance_sqr < max_distance_sqr {
-                f(p, op, distance_sqr);
+                f(&p.point, &op.point, distance_sqr, offset);
             }
         };
 
-        let grid_len = self.chunks.len();
-        for y in 0..grid_len {
+        if self.torus.is_some() {
+            for chunk in (0..self.x_chunks).map(|x| self.chunk(x, y)) {
+                for (i, p) in chunk.iter().enumerate() {
+                    for op in chunk[i + 1..].iter() {
+                        try_call(p, op);
+                    }
+                }
+            }
+            return;
+        }
+
+        let has_next_strip = y < self.y_chunks - 1;
+        let strip_len = self.x_chunks;
+        for x in 0..strip_len {
             #[cfg(feature = "timer")]
-            let _timer = Timer::new(format!("Grid::paris y={}", y));
+            let _timer = Timer::new(format!("Grid::pairs y={} x={}", y, x));
 
-            let strip = &self.chunks[y];
-            let next_strip = if y < grid_len - 1 {
-                Some(&self.chunks[y + 1])
+            let chunk = self.chunk(x, y);
+            let over = if x < strip_len - 1 {
+                Some(self.chunk(x + 1, y))
+            } else {
+                None
+            };
+            let below = if has_next_strip {
+                Some(self.chunk(x, y + 1))
+            } else {
+                None
+            };
+            let over_below = if has_next_strip && x < strip_len - 1 {
+                Some(self.chunk(x + 1, y + 1))
+            } else {
+                None
+            };
+            let back_below = if has_next_strip && x > 0 {
+                Some(self.chunk(x - 1, y + 1))
             } else {
                 None
             };
 
-            let strip_len = strip.len();
-            for x in 0..strip_len {
-                #[cfg(feature = "timer")]
-                let _timer = Timer::new(format!("Grid::pairs y={} x={}", y, x));
-
-                let chunk = &strip[x];
-                let over = if x < strip_len - 1 {
-                    Some(&strip[x + 1])
-                } else {
-                    None
-                };
-                let below = next_strip.map(|next_strip| &next_strip[x]);
-                let over_below = next_strip.and_then(|next_strip| {
-                    if x < strip_len - 1 {
-                        Some(&next_strip[x + 1])
-                    } else {
-                        None
-                    }
-                });
-                let back_below = next_strip.and_then(|next_strip| {
-                    if x > 0 {
-                        Some(&next_strip[x - 1])
-                    } else {
-                        None
+            for (i, p) in chunk.iter().enumerate() {
+                {
+                    #[cfg(feature = "timer")]
+                    let _timer = Timer::new(format!("Grid::pairs self-chunk y={} x={}", y, x));
+                    for op in chunk[..i].iter() {
+                        try_call(p, op);
                     }
-                });
+                }
 
-                for p in chunk.iter() {
-                    {
-                        #[cfg(feature = "timer")]
-                        let _timer = Timer::new(format!("Grid::pairs self-chunk y={} x={}", y, x));
-                        for op in self.tmp.iter() {
+                {
+                    #[cfg(feature = "timer")]
+                    let _timer = Timer::new(format!("Grid::pairs over y={} x={}", y, x));
+                    if let Some(over) = over {
+                        for op in over.iter() {
                             try_call(p, op);
                         }
-                        self.tmp.push(p.clone());
                     }
+                }
 
-                    {
-                        #[cfg(feature = "timer")]
-                        let _timer = Timer::new(format!("Grid::pairs over y={} x={}", y, x));
-                        if let Some(over) = over {
-                            for op in over.iter() {
-                                try_call(p, op);
-                            }
+                {
+                    #[cfg(feature = "timer")]
+                    let _timer = Timer::new(format!("Grid::pairs below y={} x={}", y, x));
+                    if let Some(below) = below {
+                        for op in below.iter() {
+                            try_call(p, op);
                         }
                     }
+                }
 
-                    {
-                        #[cfg(feature = "timer")]
-                        let _timer = Timer::new(format!("Grid::pairs below y={} x={}", y, x));
-                        if let Some(below) = below {
-                            for op in below.iter() {
-                                try_call(p, op);
-                            }
+                {
+                    #[cfg(feature = "timer")]
+                    let _timer = Timer::new(format!("Grid::pairs over-below y={} x={}", y, x));
+                    if let Some(over_below) = over_below {
+                        for op in over_below.iter() {
+                            try_call(p, op);
                         }
                     }
+                }
 
-                    {
-                        #[cfg(feature = "timer")]
-                        let _timer = Timer::new(format!("Grid::pairs over-below y={} x={}", y, x));
-                        if let Some(over_below) = over_below {
-                            for op in over_below.iter() {
-                                try_call(p, op);
-                            }
+                {
+                    #[cfg(feature = "timer")]
+                    let _timer = Timer::new(format!("Grid::pairs back-below y={} x={}", y, x));
+                    if let Some(back_below) = back_below {
+                        for op in back_below.iter() {
+                            try_call(p, op);
                         }
                     }
+                }
+            }
+        }
+    }
 
-                    {
-                        #[cfg(feature = "timer")]
-                        let _timer = Timer::new(format!("Grid::pairs back-below y={} x={}", y, x));
-                        if let Some(back_below) = back_below {
-                            for op in back_below.iter() {
-                                try_call(p, op);
-                            }
-                        }
+    /// Finds the pairs between two neighboring chunks of a torus.
+    fn chunk_pair_pairs<'a, F: FnMut(&'a P, &'a P, f32, (f32, f32))>(
+        &'a self,
+        ((ax, ay), (bx, by)): ChunkPair,
+        f: &mut F,
+    ) {
+        let max_distance_sqr = self.chunk_size * self.chunk_size;
+        for p in self.chunk(ax, ay).iter() {
+            for op in self.chunk(bx, by).iter() {
+                let (distance_sqr, offset) = self.pair_distance_sqr(&p.point, &op.point);
+                if distance_sqr < max_distance_sqr {
+                    f(&p.point, &op.point, distance_sqr, offset);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid::{Grid, GridKey, Positioned};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[derive(Debug, Clone)]
+    struct TestPoint {
+        index: usize,
+        x: f32,
+        y: f32,
+    }
+
+    impl Positioned for TestPoint {
+        fn x(&self) -> f32 {
+            self.x
+        }
+
+        fn y(&self) -> f32 {
+            self.y
+        }
+
+        fn x_mut(&mut self) -> &mut f32 {
+            &mut self.x
+        }
+
+        fn y_mut(&mut self) -> &mut f32 {
+            &mut self.y
+        }
+    }
+
+    fn torus_distance_sqr(a: &TestPoint, b: &TestPoint, width: f32, height: f32) -> f32 {
+        let x = (a.x - b.x).abs();
+        let y = (a.y - b.y).abs();
+        let x = x.min(width - x);
+        let y = y.min(height - y);
+        x * x + y * y
+    }
+
+    /// The offset, chunk size and sizes of the grids to test, including sizes
+    /// that aren't a whole number of chunks.
+    const OFFSET: f32 = 100.0;
+    const CHUNK_SIZE: f32 = 100.0;
+    const SIZES: [(f32, f32); 5] = [
+        (1000.0, 700.0),
+        (450.0, 330.0),
+        (250.0, 120.0),
+        (100.0, 100.0),
+        (60.0, 240.0),
+    ];
+
+    fn distance_sqr(a: &TestPoint, b: &TestPoint) -> f32 {
+        (a.x - b.x) * (a.x - b.x) + (a.y - b.y) * (a.y - b.y)
+    }
+
+    /// Picks points across a grid, with some exactly on the edges between
+    /// chunks and some outside the grid, which get clamped into its edge
+    /// chunks.
+    fn random_points(rng: &mut StdRng, width: f32, height: f32, count: usize) -> Vec<TestPoint> {
+        let coordinate = |rng: &mut StdRng, length: f32| match rng.gen_range(0..4) {
+            0 => (rng.gen_range(0..=(length / CHUNK_SIZE) as usize) as f32) * CHUNK_SIZE - OFFSET,
+            1 => rng.gen_range(-OFFSET - 150.0..length - OFFSET + 150.0),
+            _ => rng.gen_range(-OFFSET..length - OFFSET),
+        };
+        (0..count)
+            .map(|index| TestPoint {
+                index,
+                x: coordinate(rng, width),
+                y: coordinate(rng, height),
+            })
+            .collect()
+    }
+
+    fn grid_points(grid: &Grid<TestPoint>) -> Vec<TestPoint> {
+        let mut points: Vec<TestPoint> = grid.iter().cloned().collect();
+        points.sort_unstable_by_key(|p| p.index);
+        points
+    }
+
+    /// Checks `pairs` and `all_within` against comparing every point with
+    /// every other.
+    fn assert_matches_brute_force(grid: &mut Grid<TestPoint>, rng: &mut StdRng, context: &str) {
+        // Every point is in the chunk its position belongs in.
+        assert_eq!(grid.chunk_starts.last(), Some(&grid.len()), "{}", context);
+        for chunk in 0..grid.x_chunks * grid.y_chunks {
+            let range = grid.chunk_starts[chunk]..grid.chunk_starts[chunk + 1];
+            for entry in grid.entries[range].iter() {
+                assert_eq!(
+                    grid.chunk_index(&entry.point),
+                    chunk,
+                    "chunk with {}",
+                    context
+                );
+            }
+        }
+
+        let points = grid_points(grid);
+        for (key, p) in grid.iter_keys() {
+            assert_eq!(
+                grid.get(key).unwrap().index,
+                p.index,
+                "get with {}",
+                context
+            );
+        }
+
+        let mut found = vec![];
+        grid.pairs(|p, op, distance, _| {
+            assert_eq!(distance, distance_sqr(p, op));
+            found.push((p.index.min(op.index), p.index.max(op.index)));
+        });
+        found.sort_unstable();
+
+        let mut expected = vec![];
+        for (a, p) in points.iter().enumerate() {
+            for op in points[a + 1..].iter() {
+                if distance_sqr(p, op) < CHUNK_SIZE * CHUNK_SIZE {
+                    expected.push((p.index.min(op.index), p.index.max(op.index)));
+                }
+            }
+        }
+        expected.sort_unstable();
+        assert_eq!(found, expected, "pairs with {}", context);
+
+        let mut found: Vec<(usize, usize)> = grid
+            .pairs_iter()
+            .map(|(p, op, distance)| {
+                assert_eq!(distance, distance_sqr(p, op));
+                (p.index.min(op.index), p.index.max(op.index))
+            })
+            .collect();
+        found.sort_unstable();
+        assert_eq!(found, expected, "pairs_iter with {}", context);
+
+        for _ in 0..20 {
+            let (x, y, distance) = match points.get(rng.gen_range(0..points.len().max(1))) {
+                Some(p) if rng.gen_bool(0.5) => (p.x, p.y, rng.gen_range(0.0..CHUNK_SIZE)),
+                _ => (
+                    rng.gen_range(-OFFSET - 50.0..1000.0),
+                    rng.gen_range(-OFFSET - 50.0..800.0),
+                    rng.gen_range(0.0..CHUNK_SIZE * 2.0),
+                ),
+            };
+            let query = TestPoint { index: usize::MAX, x, y };
+
+            let mut within = vec![];
+            grid.all_within(x, y, distance, |p, distance| {
+                let expected = distance_sqr(p, &query);
+                assert!((distance - expected).abs() <= expected * 1e-5 + 1e-3);
+                within.push(p.index);
+            });
+            within.sort_unstable();
+            let expected: Vec<usize> = points
+                .iter()
+                .filter(|p| distance_sqr(p, &query) < distance * distance)
+                .map(|p| p.index)
+                .collect();
+            assert_eq!(
+                within, expected,
+                "all_within({}, {}, {}) with {}",
+                x, y, distance, context
+            );
+
+            let mut within: Vec<usize> =
+                grid.within(x, y, distance).map(|(p, _)| p.index).collect();
+            within.sort_unstable();
+            assert_eq!(
+                within, expected,
+                "within({}, {}, {}) with {}",
+                x, y, distance, context
+            );
+        }
+    }
+
+    #[test]
+    fn test_k_nearest() {
+        let mut rng = StdRng::seed_from_u64(47);
+        for (width, height) in SIZES {
+            let mut grid = Grid::new(OFFSET, CHUNK_SIZE, width, height);
+            for p in random_points(&mut rng, width, height, 200) {
+                grid.insert(p);
+            }
+            let points = grid_points(&grid);
+
+            for _ in 0..20 {
+                let query = TestPoint {
+                    index: usize::MAX,
+                    x: rng.gen_range(-OFFSET..width - OFFSET),
+                    y: rng.gen_range(-OFFSET..height - OFFSET),
+                };
+                let k = rng.gen_range(0..10);
+                let max_distance = rng.gen_range(1.0..CHUNK_SIZE);
+                let mut found = vec![];
+                grid.k_nearest(query.x, query.y, k, max_distance, &mut found);
+                let found: Vec<f32> = found
+                    .into_iter()
+                    .map(|(p, found_sqr)| {
+                        assert_eq!(found_sqr, distance_sqr(p, &query));
+                        found_sqr
+                    })
+                    .collect();
+
+                // Compare distances, since points at the same distance could be
+                // picked in either order.
+                let mut expected: Vec<f32> = points
+                    .iter()
+                    .map(|p| distance_sqr(p, &query))
+                    .filter(|&distance_sqr| distance_sqr < max_distance * max_distance)
+                    .collect();
+                expected.sort_unstable_by(f32::total_cmp);
+                expected.truncate(k);
+                assert_eq!(found, expected, "{} nearest in {}x{}", k, width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn test_iterators() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut grid = Grid::new(OFFSET, CHUNK_SIZE, 450.0, 330.0);
+        assert!(grid.is_empty());
+        assert_eq!(grid.iter().count(), 0);
+        assert_eq!(grid.pairs_iter().count(), 0);
+
+        for p in random_points(&mut rng, 450.0, 330.0, 100) {
+            grid.insert(p);
+        }
+        assert!(!grid.is_empty());
+        assert_eq!(grid.len(), 100);
+
+        let mut indices: Vec<usize> = grid.iter().map(|p| p.index).collect();
+        indices.sort_unstable();
+        assert_eq!(indices, (0..100).collect::<Vec<_>>());
+
+        for p in grid.iter_mut() {
+            p.index += 1000;
+        }
+        assert!(grid.iter().all(|p| p.index >= 1000));
+
+        // Searching can stop at the first match.
+        let (p, op, _) = grid.pairs_iter().next().unwrap();
+        assert!(distance_sqr(p, op) < CHUNK_SIZE * CHUNK_SIZE);
+        let nearby = grid
+            .within(p.x, p.y, 1.0)
+            .find(|(other, _)| other.index == p.index);
+        assert!(nearby.is_some());
+    }
+
+    #[test]
+    fn test_chunk_layout() {
+        let mut grid = Grid::new(OFFSET, CHUNK_SIZE, 450.0, 330.0);
+        let layout = grid.chunk_layout();
+        assert_eq!(layout.origin, (-OFFSET, -OFFSET));
+        assert_eq!((layout.x_chunks, layout.y_chunks), (5, 4));
+        assert_eq!(
+            (layout.chunk_width, layout.chunk_height),
+            (CHUNK_SIZE, CHUNK_SIZE)
+        );
+
+        for (index, (x, y)) in [(-50.0, -50.0), (-40.0, -60.0), (120.0, 10.0)]
+            .into_iter()
+            .enumerate()
+        {
+            grid.insert(TestPoint { index, x, y });
+        }
+        let lens: Vec<usize> = grid.chunk_lens().collect();
+        assert_eq!(lens.len(), 20);
+        assert_eq!((lens[0], lens[7]), (2, 1));
+        assert_eq!(lens.iter().sum::<usize>(), 3);
+
+        // Torus chunks are stretched to fit.
+        let torus: Grid<TestPoint> = Grid::new_torus(CHUNK_SIZE, 450.0, 330.0);
+        let layout = torus.chunk_layout();
+        assert_eq!(layout.origin, (0.0, 0.0));
+        assert_eq!((layout.x_chunks, layout.y_chunks), (4, 3));
+        assert_eq!((layout.chunk_width, layout.chunk_height), (112.5, 110.0));
+    }
+
+    #[test]
+    fn test_pairs_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(41);
+        for (width, height) in SIZES {
+            for _ in 0..10 {
+                let count = rng.gen_range(0..200);
+                let mut grid = Grid::new(OFFSET, CHUNK_SIZE, width, height);
+                for p in random_points(&mut rng, width, height, count) {
+                    grid.insert(p);
+                }
+                let context = format!("width={} height={} count={}", width, height, count);
+                assert_matches_brute_force(&mut grid, &mut rng, &context);
+            }
+        }
+    }
+
+    #[test]
+    fn test_extend_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(46);
+        for (width, height) in SIZES {
+            let mut grid = Grid::new(OFFSET, CHUNK_SIZE, width, height);
+            let mut points = random_points(&mut rng, width, height, 150);
+            let extended = points.split_off(50);
+            let keys: Vec<_> = points.into_iter().map(|p| grid.insert(p)).collect();
+            grid.extend(extended);
+            assert_eq!(grid.len(), 150);
+            for (index, key) in keys.into_iter().enumerate() {
+                assert_eq!(grid.get(key).unwrap().index, index);
+            }
+            let context = format!("extend width={} height={}", width, height);
+            assert_matches_brute_force(&mut grid, &mut rng, &context);
+        }
+    }
+
+    #[test]
+    fn test_all_mut_keeps_matching_brute_force() {
+        let mut rng = StdRng::seed_from_u64(41);
+        let (width, height) = (450.0, 330.0);
+        let mut grid = Grid::new(OFFSET, CHUNK_SIZE, width, height);
+        for p in random_points(&mut rng, width, height, 150) {
+            grid.insert(p);
+        }
+
+        for step in 0..20 {
+            grid.all_mut(|p| {
+                p.x += rng.gen_range(-80.0..80.0);
+                p.y += rng.gen_range(-80.0..80.0);
+            });
+            let context = format!("step={}", step);
+            assert_matches_brute_force(&mut grid, &mut rng, &context);
+        }
+    }
+
+    #[test]
+    fn test_set_size_keeps_matching_brute_force() {
+        let mut rng = StdRng::seed_from_u64(41);
+        for _ in 0..20 {
+            let (mut width, mut height) = SIZES[rng.gen_range(0..SIZES.len())];
+            let mut grid = Grid::new(OFFSET, CHUNK_SIZE, width, height);
+            for p in random_points(&mut rng, width, height, 150) {
+                grid.insert(p);
+            }
+
+            for _ in 0..6 {
+                let (new_width, new_height) = SIZES[rng.gen_range(0..SIZES.len())];
+                grid.set_size(new_width, new_height);
+                let context = format!(
+                    "set_size from {}x{} to {}x{}",
+                    width, height, new_width, new_height
+                );
+                (width, height) = (new_width, new_height);
+
+                assert_eq!(grid_points(&grid).len(), 150, "{}", context);
+                assert_matches_brute_force(&mut grid, &mut rng, &context);
+
+                // New points should also be found next to the moved ones.
+                for (i, mut p) in random_points(&mut rng, width, height, 10)
+                    .into_iter()
+                    .enumerate()
+                {
+                    p.index = 1000 + i;
+                    grid.insert(p);
+                }
+                assert_matches_brute_force(&mut grid, &mut rng, &context);
+                grid.retain(|p| p.index < 1000);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut rng = StdRng::seed_from_u64(43);
+        let (width, height) = (450.0, 330.0);
+        let mut grid = Grid::new(OFFSET, CHUNK_SIZE, width, height);
+        let mut keys: Vec<GridKey> = random_points(&mut rng, width, height, 150)
+            .into_iter()
+            .map(|p| grid.insert(p))
+            .collect();
+        for (index, &key) in keys.iter().enumerate() {
+            assert_eq!(grid.get(key).unwrap().index, index);
+        }
+
+        // Removed points can't be found by their keys, even once their slots
+        // are reused.
+        let removed: Vec<GridKey> = keys.drain(100..).collect();
+        for (i, &key) in removed.iter().enumerate() {
+            assert_eq!(grid.remove(key).unwrap().index, 100 + i);
+            assert!(grid.remove(key).is_none());
+        }
+        assert_eq!(grid.len(), 100);
+        for p in random_points(&mut rng, width, height, 50) {
+            keys.push(grid.insert(TestPoint { index: p.index + 100, ..p }));
+        }
+        for &key in removed.iter() {
+            assert!(grid.get(key).is_none());
+            assert!(!grid.update_position(key, 0.0, 0.0));
+        }
+
+        // Drag points around one at a time.
+        for _ in 0..300 {
+            let key = keys[rng.gen_range(0..keys.len())];
+            let (x, y) = (
+                rng.gen_range(-OFFSET - 50.0..width),
+                rng.gen_range(-OFFSET - 50.0..height),
+            );
+            assert!(grid.update_position(key, x, y));
+            let p = grid.get(key).unwrap();
+            assert_eq!((p.x, p.y), (x, y));
+        }
+        grid.get_mut(keys[0]).unwrap().index = 1000;
+        assert_eq!(grid.get(keys[0]).unwrap().index, 1000);
+        assert_matches_brute_force(&mut grid, &mut rng, "moved by key");
+
+        // Keys last through everything else that moves points around.
+        grid.all_mut(|p| p.x += 30.0);
+        grid.retain(|p| p.index % 7 != 0);
+        grid.set_size(1000.0, 700.0);
+        grid.set_size(250.0, 120.0);
+        assert_matches_brute_force(&mut grid, &mut rng, "all_mut, retain and set_size");
+        for &key in keys.iter() {
+            match grid.get(key) {
+                Some(p) => assert_ne!(p.index % 7, 0),
+                None => assert!(grid.iter().all(|p| p.index % 7 != 0)),
+            }
+        }
+
+        grid.clear();
+        assert!(grid.is_empty());
+        assert!(keys.iter().all(|&key| grid.get(key).is_none()));
+    }
+
+    #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_par_pairs_match_serial() {
+        let mut rng = StdRng::seed_from_u64(44);
+        for (width, height) in SIZES {
+            for torus in [false, true] {
+                let mut grid = if torus {
+                    Grid::new_torus(CHUNK_SIZE, width, height)
+                } else {
+                    Grid::new(OFFSET, CHUNK_SIZE, width, height)
+                };
+                for mut p in random_points(&mut rng, width, height, 300) {
+                    if torus {
+                        p.x = (p.x + OFFSET).rem_euclid(width);
+                        p.y = (p.y + OFFSET).rem_euclid(height);
                     }
+                    grid.insert(p);
                 }
-                self.tmp.clear();
+
+                let mut serial = vec![];
+                grid.pairs(|p, op, distance, _| serial.push((p.index, op.index, distance)));
+                let mut parallel = vec![];
+                grid.par_pairs(|p, op, distance, _| parallel.push((p.index, op.index, distance)));
+                assert!(!serial.is_empty());
+                assert_eq!(
+                    parallel, serial,
+                    "width={} height={} torus={}",
+                    width, height, torus
+                );
             }
         }
     }
+
+    #[test]
+    fn test_torus_pairs_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(34);
+        let chunk_size = 100.0;
+        // Includes sizes only one or two chunks across, where neighbors wrap
+        // onto each other.
+        for (width, height) in [
+            (1000.0, 700.0),
+            (450.0, 330.0),
+            (250.0, 120.0),
+            (90.0, 80.0),
+        ] {
+            let points: Vec<TestPoint> = (0..150)
+                .map(|index| TestPoint {
+                    index,
+                    x: rng.gen_range(0.0..width),
+                    y: rng.gen_range(0.0..height),
+                })
+                .collect();
+
+            let mut grid = Grid::new_torus(chunk_size, width, height);
+            for p in points.iter() {
+                grid.insert(p.clone());
+            }
+
+            let mut found = vec![];
+            grid.pairs(|p, op, _, _| found.push((p.index.min(op.index), p.index.max(op.index))));
+            found.sort_unstable();
+
+            let mut expected = vec![];
+            for a in 0..points.len() {
+                for b in (a + 1)..points.len() {
+                    let distance_sqr = torus_distance_sqr(&points[a], &points[b], width, height);
+                    if distance_sqr < chunk_size * chunk_size {
+                        expected.push((a, b));
+                    }
+                }
+            }
+
+            assert_eq!(found, expected, "width={} height={}", width, height);
+
+            let mut found: Vec<(usize, usize)> = grid
+                .pairs_iter()
+                .map(|(p, op, _)| (p.index.min(op.index), p.index.max(op.index)))
+                .collect();
+            found.sort_unstable();
+            assert_eq!(found, expected, "width={} height={}", width, height);
+
+            let mut within = vec![];
+            grid.all_within(points[0].x, points[0].y, 60.0, |p, _| within.push(p.index));
+            within.sort_unstable();
+            let expected: Vec<usize> = (0..points.len())
+                .filter(|&b| {
+                    torus_distance_sqr(&points[0], &points[b], width, height) < 60.0 * 60.0
+                })
+                .collect();
+            assert_eq!(within, expected, "width={} height={}", width, height);
+        }
+    }
+
+    #[test]
+    fn test_torus_without_area() {
+        let mut grid = Grid::new_torus(100.0, 500.0, 0.0);
+        grid.insert(TestPoint { index: 0, x: 0.0, y: 0.0 });
+        grid.set_size(0.0, 0.0);
+        assert_eq!((grid.x_chunks, grid.y_chunks), (1, 1));
+        grid.insert(TestPoint { index: 1, x: 0.0, y: 0.0 });
+        assert_eq!(grid.len(), 2);
+        grid.pairs(|_, _, _, _| {});
+    }
 }
@@ -0,0 +1,231 @@
+#[cfg(feature = "timer")]
+use crate::timer::Timer;
+
+/// The 3D counterpart to `Grid`, bucketing points into cubic chunks so that
+/// nearby pairs can be found without comparing every point to every other.
+///
+/// Unlike `Grid`, positions are not offset: the grid covers the box from
+/// `(0, 0, 0)` to `(width, height, depth)` and points outside it are clamped
+/// into the edge chunks.
+pub struct Grid3<P: Positioned3 + Clone> {
+    chunk_size: f32,
+    x_chunks: usize,
+    y_chunks: usize,
+    z_chunks: usize,
+    chunks: Vec<Vec<P>>,
+    tmp: Vec<P>,
+}
+
+pub trait Positioned3 {
+    fn x(&self) -> f32;
+    fn y(&self) -> f32;
+    fn z(&self) -> f32;
+}
+
+/// Offsets of the neighboring chunks that come after a chunk, so that every
+/// pair of neighboring chunks is visited exactly once.
+const FORWARD_NEIGHBORS: [(isize, isize, isize); 13] = [
+    (1, 0, 0),
+    (-1, 1, 0),
+    (0, 1, 0),
+    (1, 1, 0),
+    (-1, -1, 1),
+    (0, -1, 1),
+    (1, -1, 1),
+    (-1, 0, 1),
+    (0, 0, 1),
+    (1, 0, 1),
+    (-1, 1, 1),
+    (0, 1, 1),
+    (1, 1, 1),
+];
+
+impl<P: Positioned3 + Clone> Grid3<P> {
+    pub fn new(chunk_size: f32, width: f32, height: f32, depth: f32) -> Grid3<P> {
+        let x_chunks = ((width / chunk_size).ceil() as usize).max(1);
+        let y_chunks = ((height / chunk_size).ceil() as usize).max(1);
+        let z_chunks = ((depth / chunk_size).ceil() as usize).max(1);
+
+        Grid3 {
+            chunk_size,
+            x_chunks,
+            y_chunks,
+            z_chunks,
+            chunks: vec![vec![]; x_chunks * y_chunks * z_chunks],
+            tmp: vec![],
+        }
+    }
+
+    /// Changes the size of the box covered by this grid, re-bucketing every
+    /// point.
+    pub fn set_size(&mut self, width: f32, height: f32, depth: f32) {
+        for chunk in self.chunks.iter_mut() {
+            self.tmp.append(chunk);
+        }
+
+        self.x_chunks = ((width / self.chunk_size).ceil() as usize).max(1);
+        self.y_chunks = ((height / self.chunk_size).ceil() as usize).max(1);
+        self.z_chunks = ((depth / self.chunk_size).ceil() as usize).max(1);
+        self.chunks = vec![vec![]; self.x_chunks * self.y_chunks * self.z_chunks];
+
+        let mut tmp = std::mem::take(&mut self.tmp);
+        for p in tmp.drain(..) {
+            self.insert(p);
+        }
+        self.tmp = tmp;
+    }
+
+    pub fn insert(&mut self, p: P) {
+        let index = self.chunk_index(&p);
+        self.chunks[index].push(p);
+    }
+
+    pub fn all_mut<F: FnMut(&mut P)>(&mut self, mut f: F) {
+        for chunk in self.chunks.iter_mut() {
+            self.tmp.append(chunk);
+        }
+
+        let mut tmp = std::mem::take(&mut self.tmp);
+        for mut p in tmp.drain(..) {
+            f(&mut p);
+            self.insert(p);
+        }
+        self.tmp = tmp;
+    }
+
+    pub fn pairs<F: FnMut(&P, &P, f32)>(&self, mut f: F) {
+        #[cfg(feature = "timer")]
+        let _timer = Timer::from_str("Grid3::pairs");
+
+        let max_distance_sqr = self.chunk_size * self.chunk_size;
+        let mut try_call = |p: &P, op: &P| {
+            let x = p.x() - op.x();
+            let y = p.y() - op.y();
+            let z = p.z() - op.z();
+            let distance_sqr = x * x + y * y + z * z;
+            if distance_sqr < max_distance_sqr {
+                f(p, op, distance_sqr);
+            }
+        };
+
+        for z in 0..self.z_chunks {
+            for y in 0..self.y_chunks {
+                for x in 0..self.x_chunks {
+                    let chunk = &self.chunks[self.flat_index(x, y, z)];
+                    if chunk.is_empty() {
+                        continue;
+                    }
+
+                    for (i, p) in chunk.iter().enumerate() {
+                        for op in chunk[i + 1..].iter() {
+                            try_call(p, op);
+                        }
+                    }
+
+                    for (dx, dy, dz) in FORWARD_NEIGHBORS {
+                        let (nx, ny, nz) = (x as isize + dx, y as isize + dy, z as isize + dz);
+                        if nx < 0
+                            || ny < 0
+                            || nx >= self.x_chunks as isize
+                            || ny >= self.y_chunks as isize
+                            || nz >= self.z_chunks as isize
+                        {
+                            continue;
+                        }
+
+                        let other =
+                            &self.chunks[self.flat_index(nx as usize, ny as usize, nz as usize)];
+                        for p in chunk.iter() {
+                            for op in other.iter() {
+                                try_call(p, op);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn chunk_index(&self, p: &P) -> usize {
+        let x = ((p.x() / self.chunk_size).floor().max(0.0) as usize).min(self.x_chunks - 1);
+        let y = ((p.y() / self.chunk_size).floor().max(0.0) as usize).min(self.y_chunks - 1);
+        let z = ((p.z() / self.chunk_size).floor().max(0.0) as usize).min(self.z_chunks - 1);
+        self.flat_index(x, y, z)
+    }
+
+    fn flat_index(&self, x: usize, y: usize, z: usize) -> usize {
+        (z * self.y_chunks + y) * self.x_chunks + x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid3::{Grid3, Positioned3};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[derive(Debug, Copy, Clone)]
+    struct TestPoint {
+        index: usize,
+        position: [f32; 3],
+    }
+
+    impl Positioned3 for TestPoint {
+        fn x(&self) -> f32 {
+            self.position[0]
+        }
+
+        fn y(&self) -> f32 {
+            self.position[1]
+        }
+
+        fn z(&self) -> f32 {
+            self.position[2]
+        }
+    }
+
+    fn pairs(grid: &Grid3<TestPoint>) -> Vec<(usize, usize)> {
+        let mut pairs = vec![];
+        grid.pairs(|p, op, _| pairs.push((p.index.min(op.index), p.index.max(op.index))));
+        pairs.sort_unstable();
+        pairs
+    }
+
+    fn brute_force(points: &[TestPoint], distance: f32) -> Vec<(usize, usize)> {
+        let mut pairs = vec![];
+        for (i, p) in points.iter().enumerate() {
+            for op in points[i + 1..].iter() {
+                let x = p.position[0] - op.position[0];
+                let y = p.position[1] - op.position[1];
+                let z = p.position[2] - op.position[2];
+                if x * x + y * y + z * z < distance * distance {
+                    pairs.push((p.index, op.index));
+                }
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn test_pairs_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let points: Vec<TestPoint> = (0..300)
+            .map(|index| TestPoint {
+                index,
+                position: [
+                    rng.gen_range(0.0..500.0),
+                    rng.gen_range(0.0..400.0),
+                    rng.gen_range(0.0..300.0),
+                ],
+            })
+            .collect();
+
+        let mut grid = Grid3::new(100.0, 500.0, 400.0, 300.0);
+        for &p in points.iter() {
+            grid.insert(p);
+        }
+        assert_eq!(pairs(&grid), brute_force(&points, 100.0));
+
+        grid.set_size(350.0, 450.0, 120.0);
+        assert_eq!(pairs(&grid), brute_force(&points, 100.0));
+    }
+}
@@ -3,19 +3,38 @@
 #[macro_use]
 extern crate async_trait;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 #[macro_use]
 extern crate thiserror;
 
+mod audio;
 mod buffer;
 pub mod flow;
+mod font;
 mod grid;
+mod grid3;
 pub mod neonet;
+mod noise;
+mod spatial;
 mod util;
 
 #[cfg(feature = "timer")]
 mod timer;
 
+/// The internals timed by the benchmarks, which may change at any time.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::{
+        buffer::Encodable,
+        grid::{Grid, Positioned},
+        neonet::gpu::{GPUPoint, PointIndex},
+        spatial::{AnySpatialIndex, KdTree, SpatialIndex},
+    };
+}
+
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::wasm_bindgen;
 
@@ -29,7 +48,11 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 #[cfg(target_arch = "wasm32")]
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
-pub async fn start_neonet(canvas_container_id: String, canvas_id: String) -> flow::WebFlow {
+pub async fn start_neonet(
+    canvas_container_id: String,
+    canvas_id: String,
+    config_json: Option<String>,
+) -> flow::WebFlow {
     wasm_logger::init(wasm_logger::Config::new(log::Level::Warn));
 
     // This provides better error messages in debug mode.
@@ -37,10 +60,51 @@ pub async fn start_neonet(canvas_container_id: String, canvas_id: String) -> flo
     #[cfg(all(debug_assertions, target_arch = "wasm32"))]
     console_error_panic_hook::set_once();
 
+    let config = match config_json {
+        Some(json) => neonet::config::NeonetConfig::from_json(&json).unwrap_or_else(|err| {
+            error!("Error parsing neonet config, using defaults: {:?}", err);
+            Default::default()
+        }),
+        None => Default::default(),
+    };
+
     flow::WebFlowBuilder::new()
         .canvas_container_id(canvas_container_id)
         .canvas_id(canvas_id)
-        .start::<neonet::NeonetApp>()
+        .start_with_config::<neonet::NeonetApp>(config)
         .await
         .unwrap()
 }
+
+/// Moves an attractor, or the center of its orbit, to a position in pixels with
+/// the origin at the bottom-left of the canvas.
+#[cfg(target_arch = "wasm32")]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn move_attractor(index: usize, x: f32, y: f32) {
+    neonet::control::send(neonet::control::ControlCommand::MoveAttractor { index, x, y });
+}
+
+/// Changes an attractor's strength. Negative strengths repel points.
+#[cfg(target_arch = "wasm32")]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn set_attractor_strength(index: usize, strength: f32) {
+    neonet::control::send(neonet::control::ControlCommand::SetAttractorStrength {
+        index,
+        strength,
+    });
+}
+
+/// Shows or hides the debug overlay.
+#[cfg(target_arch = "wasm32")]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn toggle_overlay() {
+    neonet::control::send(neonet::control::ControlCommand::ToggleOverlay);
+}
+
+/// Feeds the audio-reactive mode with mono samples, such as those from an
+/// `AnalyserNode`'s `getFloatTimeDomainData`.
+#[cfg(target_arch = "wasm32")]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn set_audio_samples(samples: Vec<f32>, sample_rate: u32) {
+    neonet::control::send_audio(neonet::control::AudioSamples { samples, sample_rate });
+}
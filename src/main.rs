@@ -4,12 +4,291 @@ fn main() {
         dotenv::dotenv().ok();
         env_logger::init();
 
+        let config = match std::env::var("NEONET_CONFIG") {
+            Ok(path) => neonet2::neonet::config::NeonetConfig::load(&path).unwrap_or_else(|err| {
+                log::error!(
+                    "Error loading neonet config {:?}, using defaults: {:?}",
+                    path,
+                    err
+                );
+                Default::default()
+            }),
+            Err(_) => Default::default(),
+        };
+
+        let args = desktop::Args::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+            log::error!("{}", err);
+            std::process::exit(1);
+        });
+        if let Some(path) = args.svg.as_deref() {
+            if let Err(err) = desktop::export_svg(config, &args, path) {
+                log::error!("Error exporting SVG {:?}: {}", path, err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        if args.terminal {
+            if let Err(err) = desktop::run_in_terminal(config, &args) {
+                log::error!("Error drawing in the terminal: {}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+
         neonet2::flow::DesktopFlow::new()
             .title("NeoNet 2")
             .width(1920)
             .height(1080)
             .fullscreen(true)
-            .start::<neonet2::neonet::NeonetApp>()
+            .start_with_config::<neonet2::neonet::NeonetApp>(config)
             .unwrap();
     }
 }
+
+#[cfg(not(target_arch = "wasm32"))]
+mod desktop {
+    use neonet2::{
+        flow::WindowSize,
+        neonet::{
+            config::NeonetConfig,
+            point_capacity,
+            simulation::Simulation,
+            svg::write_svg,
+            terminal::{TerminalCanvas, TerminalStyle},
+        },
+    };
+    use rand::{thread_rng, Rng};
+    use std::{
+        env,
+        fs::File,
+        io::{self, BufWriter, Write},
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::{Duration, Instant},
+    };
+    use thiserror::Error;
+    use wgpu::Limits;
+
+    /// Set once Ctrl-C is pressed while drawing in the terminal.
+    static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+    #[derive(Debug, Error)]
+    pub enum HeadlessError {
+        #[error("{0} must be a number, not {1:?}")]
+        InvalidNumber(String, String),
+        #[error("{0} needs a value")]
+        MissingValue(String),
+        #[error("{0} must be {1}, not {2}")]
+        OutOfRange(String, &'static str, f32),
+        #[error("Unknown argument {0:?}")]
+        UnknownArgument(String),
+        #[error("Unknown terminal style {0:?}, expected braille or half-block")]
+        UnknownStyle(String),
+        #[error("IO Error: {0}")]
+        IOError(#[from] io::Error),
+    }
+
+    /// The command-line arguments, for drawing without a window.
+    pub struct Args {
+        /// Where to save an SVG frame, instead of opening a window.
+        pub svg: Option<String>,
+        /// Whether to draw in the terminal instead of opening a window.
+        pub terminal: bool,
+        width: Option<f32>,
+        height: Option<f32>,
+        time: Option<f32>,
+        columns: Option<f32>,
+        rows: Option<f32>,
+        style: TerminalStyle,
+    }
+
+    impl Args {
+        pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, HeadlessError> {
+            let mut parsed = Args {
+                svg: None,
+                terminal: false,
+                width: None,
+                height: None,
+                time: None,
+                columns: None,
+                rows: None,
+                style: TerminalStyle::Braille,
+            };
+
+            let mut args = args.into_iter();
+            while let Some(flag) = args.next() {
+                let number = match flag.as_str() {
+                    "--width" => &mut parsed.width,
+                    "--height" => &mut parsed.height,
+                    "--time" => &mut parsed.time,
+                    "--columns" => &mut parsed.columns,
+                    "--rows" => &mut parsed.rows,
+                    "--terminal" => {
+                        parsed.terminal = true;
+                        continue;
+                    },
+                    "--svg" => {
+                        parsed.svg = Some(args.next().ok_or(HeadlessError::MissingValue(flag))?);
+                        continue;
+                    },
+                    "--style" => {
+                        parsed.style = match args.next().as_deref() {
+                            Some("braille") => TerminalStyle::Braille,
+                            Some("half-block") => TerminalStyle::HalfBlock,
+                            Some(style) => {
+                                return Err(HeadlessError::UnknownStyle(style.to_owned()))
+                            },
+                            None => return Err(HeadlessError::MissingValue(flag)),
+                        };
+                        continue;
+                    },
+                    _ => return Err(HeadlessError::UnknownArgument(flag)),
+                };
+                let value = args
+                    .next()
+                    .ok_or_else(|| HeadlessError::MissingValue(flag.clone()))?;
+                let value: f32 = value
+                    .parse()
+                    .map_err(|_| HeadlessError::InvalidNumber(flag.clone(), value))?;
+                // Sizes of zero would leave the simulation with no room for
+                // points.
+                let (valid, expected) = match flag.as_str() {
+                    "--time" => (value >= 0.0, "a finite number of seconds, at least zero"),
+                    _ => (value > 0.0, "a finite number greater than zero"),
+                };
+                if !(valid && value.is_finite()) {
+                    return Err(HeadlessError::OutOfRange(flag, expected, value));
+                }
+                *number = Some(value);
+            }
+            Ok(parsed)
+        }
+    }
+
+    /// Creates a simulation with as many points as a window would have room
+    /// for, with the limits the desktop flow requests.
+    fn new_simulation(config: &NeonetConfig, size: WindowSize) -> Simulation {
+        let seed = config.seed.unwrap_or_else(|| thread_rng().gen());
+        log::info!("Simulation seed: {}", seed);
+        let max_points = point_capacity(&Limits::downlevel_webgl2_defaults(), config);
+        Simulation::new(config, size, seed, max_points)
+    }
+
+    /// Runs the simulation without a window for `--time` seconds, then writes
+    /// the frame to an SVG file.
+    pub fn export_svg(config: NeonetConfig, args: &Args, path: &str) -> Result<(), HeadlessError> {
+        let size = WindowSize {
+            width: args.width.unwrap_or(1920.0),
+            height: args.height.unwrap_or(1080.0),
+        };
+        let time = args.time.unwrap_or(10.0);
+
+        let mut simulation = new_simulation(&config, size);
+        let frame = Duration::from_secs_f32(1.0 / 60.0);
+        simulation.step(Duration::ZERO, |_| {});
+        for _ in 0..(time * 60.0).round() as usize {
+            simulation.step(frame, |_| {});
+        }
+
+        let file = File::create(path)?;
+        write_svg(&mut simulation, BufWriter::new(file))?;
+        log::info!("Wrote {}", path);
+        Ok(())
+    }
+
+    /// Runs the simulation in the terminal at 30 frames per second, for
+    /// `--time` seconds or until interrupted. The terminal's size comes from
+    /// `--columns` and `--rows`, or else the `COLUMNS` and `LINES` variables.
+    pub fn run_in_terminal(config: NeonetConfig, args: &Args) -> Result<(), HeadlessError> {
+        let env_number = |name: &str, default: f32| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        let columns = args.columns.unwrap_or_else(|| env_number("COLUMNS", 80.0)) as usize;
+        // Leave a row free so the last one doesn't scroll the terminal.
+        let rows = args.rows.unwrap_or_else(|| env_number("LINES", 24.0) - 1.0) as usize;
+        // Even a tiny terminal gets a row and column to draw in, as the
+        // simulation needs some room.
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        let mut canvas = TerminalCanvas::new(args.style, columns, rows);
+
+        // Give each pixel the room of a few screen pixels, so that lines are
+        // about as long, relative to the screen, as they are in a window.
+        let (width, height) = canvas.pixel_size();
+        let scale = match args.style {
+            TerminalStyle::Braille => 8.0,
+            TerminalStyle::HalfBlock => 16.0,
+        };
+        let size = WindowSize {
+            width: width as f32 * scale,
+            height: height as f32 * scale,
+        };
+        let mut simulation = new_simulation(&config, size);
+        simulation.step(Duration::ZERO, |_| {});
+
+        let frame = Duration::from_secs_f32(1.0 / 30.0);
+        let mut stdout = io::stdout().lock();
+        catch_interrupts();
+        let _guard = TerminalGuard::new(&mut stdout)?;
+
+        let start = Instant::now();
+        let mut last_frame = start;
+        while !INTERRUPTED.load(Ordering::Relaxed)
+            && args
+                .time
+                .is_none_or(|end| start.elapsed().as_secs_f32() < end)
+        {
+            let now = Instant::now();
+            simulation.step(now - last_frame, |_| {});
+            last_frame = now;
+
+            canvas.draw(&mut simulation);
+            write!(stdout, "\x1b[H")?;
+            canvas.write(&mut stdout)?;
+
+            thread::sleep(frame.saturating_sub(now.elapsed()));
+        }
+        Ok(())
+    }
+
+    /// Hides the cursor and clears the terminal for drawing, and resets the
+    /// colors and shows the cursor again when dropped, however drawing stops.
+    struct TerminalGuard;
+
+    impl TerminalGuard {
+        fn new(out: &mut impl Write) -> io::Result<TerminalGuard> {
+            write!(out, "\x1b[?25l\x1b[2J")?;
+            Ok(TerminalGuard)
+        }
+    }
+
+    impl Drop for TerminalGuard {
+        fn drop(&mut self) {
+            let mut stdout = io::stdout();
+            // Nothing more can be done if the terminal is gone.
+            let _ = writeln!(stdout, "\x1b[0m\x1b[?25h");
+            let _ = stdout.flush();
+        }
+    }
+
+    /// Makes Ctrl-C stop drawing in the terminal, rather than killing the
+    /// process before the terminal is restored.
+    #[cfg(unix)]
+    fn catch_interrupts() {
+        extern "C" fn on_interrupt(_: libc::c_int) {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+        }
+
+        let handler: extern "C" fn(libc::c_int) = on_interrupt;
+        // The handler only stores to an atomic, which is safe in a signal
+        // handler.
+        unsafe {
+            libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn catch_interrupts() {}
+}
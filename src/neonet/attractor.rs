@@ -0,0 +1,167 @@
+//! Attractors and repulsors that pull on or push away nearby points.
+
+use crate::neonet::{config::AttractorConfig, simulation::Point};
+use std::f32::consts::PI;
+
+pub struct Attractor {
+    config: AttractorConfig,
+    position: (f32, f32),
+}
+
+impl Attractor {
+    pub fn new(config: AttractorConfig) -> Attractor {
+        let position = (config.position[0], config.position[1]);
+        let mut attractor = Attractor { config, position };
+        attractor.update(0.0);
+        attractor
+    }
+
+    /// Moves the attractor. Orbiting attractors have their orbit's center
+    /// moved instead.
+    pub fn move_to(&mut self, x: f32, y: f32) {
+        match self.config.orbit.as_mut() {
+            Some(orbit) => orbit.center = [x, y],
+            None => self.config.position = [x, y],
+        }
+        self.position = (x, y);
+    }
+
+    pub fn set_strength(&mut self, strength: f32) {
+        self.config.strength = strength;
+    }
+
+    /// Updates the attractor's position along its orbit, if it has one.
+    pub fn update(&mut self, time: f32) {
+        self.position = match self.config.orbit.as_ref() {
+            Some(orbit) => {
+                let angle = orbit.phase * PI * 2.0 + time * orbit.speed;
+                (
+                    orbit.center[0] + angle.cos() * orbit.radius,
+                    orbit.center[1] + angle.sin() * orbit.radius,
+                )
+            },
+            None => (self.config.position[0], self.config.position[1]),
+        };
+    }
+
+    /// Gets how strongly the attractor affects a point at `(x, y)`, from 1 right
+    /// next to it down to 0 at its radius, along with the direction from the
+    /// point to the attractor.
+    fn influence(&self, x: f32, y: f32) -> Option<(f32, (f32, f32))> {
+        let dx = self.position.0 - x;
+        let dy = self.position.1 - y;
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance >= self.config.radius || distance == 0.0 {
+            return None;
+        }
+
+        let falloff = (1.0 - distance / self.config.radius).powf(self.config.falloff);
+        Some((falloff, (dx / distance, dy / distance)))
+    }
+
+    /// Gets the acceleration this attractor applies to a point at `(x, y)`.
+    pub fn acceleration(&self, x: f32, y: f32) -> (f32, f32) {
+        let Some((falloff, (nx, ny))) = self.influence(x, y) else {
+            return (0.0, 0.0);
+        };
+
+        let pull = self.config.strength * falloff;
+        // The swirl pushes points sideways, counter-clockwise around the attractor.
+        let swirl = self.config.swirl * falloff;
+        (nx * pull - ny * swirl, ny * pull + nx * swirl)
+    }
+
+    pub fn apply(&self, point: &mut Point, delta: f32) {
+        let Some((falloff, _)) = self.influence(point.x, point.y) else {
+            return;
+        };
+
+        let (ax, ay) = self.acceleration(point.x, point.y);
+        point.vx += ax * delta;
+        point.vy += ay * delta;
+
+        let drag = (1.0 - self.config.damping * falloff * delta).max(0.0);
+        point.vx *= drag;
+        point.vy *= drag;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::neonet::{
+        attractor::Attractor,
+        config::{AttractorConfig, OrbitConfig},
+        simulation::Point,
+    };
+
+    /// Moves a point around a swirling attractor for a minute, returning its
+    /// fastest speed. The attractor reaches so far, with no falloff, that the
+    /// point never escapes it.
+    fn top_swirl_speed(damping: f32) -> f32 {
+        let attractor = Attractor::new(AttractorConfig {
+            position: [0.0, 0.0],
+            strength: 60.0,
+            radius: 1e6,
+            falloff: 0.0,
+            swirl: 30.0,
+            damping,
+            ..Default::default()
+        });
+        let mut point = Point { x: 100.0, ..Default::default() };
+        let delta = 1.0 / 60.0;
+        let mut top_speed = 0.0f32;
+        for _ in 0..3600 {
+            attractor.apply(&mut point, delta);
+            point.x += point.vx * delta;
+            point.y += point.vy * delta;
+            top_speed = top_speed.max((point.vx * point.vx + point.vy * point.vy).sqrt());
+        }
+        top_speed
+    }
+
+    #[test]
+    fn test_attractor_pulls_and_repulsor_pushes() {
+        let mut attractor = Attractor::new(AttractorConfig {
+            position: [100.0, 100.0],
+            strength: 50.0,
+            radius: 200.0,
+            ..Default::default()
+        });
+
+        let (ax, ay) = attractor.acceleration(0.0, 100.0);
+        assert!(ax > 0.0);
+        assert_eq!(ay, 0.0);
+        assert_eq!(attractor.acceleration(400.0, 100.0), (0.0, 0.0));
+
+        attractor.set_strength(-50.0);
+        let (ax, _) = attractor.acceleration(0.0, 100.0);
+        assert!(ax < 0.0);
+    }
+
+    #[test]
+    fn test_orbiting_attractor_moves_with_center() {
+        let mut attractor = Attractor::new(AttractorConfig {
+            orbit: Some(OrbitConfig {
+                center: [0.0, 0.0],
+                radius: 10.0,
+                speed: 1.0,
+                phase: 0.0,
+            }),
+            ..Default::default()
+        });
+        assert_eq!(attractor.position, (10.0, 0.0));
+
+        attractor.move_to(100.0, 50.0);
+        attractor.update(0.0);
+        assert_eq!(attractor.position, (110.0, 50.0));
+    }
+
+    #[test]
+    fn test_swirl_speed_bounded() {
+        // The fastest a point can go is the acceleration over the damping.
+        // Without damping, the swirl keeps speeding it up.
+        let bound = (60.0f32 * 60.0 + 30.0 * 30.0).sqrt() / 0.5;
+        assert!(top_swirl_speed(0.5) <= bound);
+        assert!(top_swirl_speed(0.0) > bound);
+    }
+}
@@ -0,0 +1,601 @@
+//! Configuration for the Neonet screen-saver.
+//!
+//! Every field has a sensible default, so a config file only needs to contain
+//! the settings it wants to change.
+
+pub use crate::spatial::SpatialIndexKind;
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs, io, path::Path};
+
+/// Top-level configuration handed to `NeonetApp` when it is initialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NeonetConfig {
+    /// The seed for the random initial points and noise. A random seed is
+    /// picked when this is not set.
+    pub seed: Option<u64>,
+    /// The number of MSAA samples to render with. This is lowered
+    /// automatically to what the device supports, and 1 disables MSAA.
+    pub msaa_samples: u32,
+    /// How many points to show per million pixels of screen. When this is
+    /// set, points are added or removed as the screen is resized to keep the
+    /// same density. Otherwise, there is always the same number of points.
+    /// Volume mode only supports the fixed number.
+    pub points_per_megapixel: Option<f32>,
+    /// The most lines drawn from each point. When this is set, two points are
+    /// only connected if each is among the other's nearest points, which keeps
+    /// dense areas readable. This only affects the flat mode.
+    pub max_connections: Option<usize>,
+    /// Settings for how points move.
+    pub motion: MotionConfig,
+    /// What happens to points that reach the edges of the screen.
+    pub boundary: BoundaryMode,
+    /// How points are indexed to find the ones near each other. The grid is
+    /// fastest for evenly spread points, and the k-d tree for clustered ones.
+    pub spatial_index: SpatialIndexKind,
+    /// Attractors and repulsors that pull on or push away nearby points.
+    pub attractors: Vec<AttractorConfig>,
+    /// Settings for spawning, aging and fading out points.
+    pub lifecycle: LifecycleConfig,
+    /// Settings for gathering points into text or an image.
+    pub shape: ShapeConfig,
+    /// Settings for reacting to audio.
+    pub audio: AudioConfig,
+    /// Settings for drawing the points themselves.
+    pub nodes: NodeConfig,
+    /// Settings for filling mutually-connected triples of points.
+    pub triangles: TriangleConfig,
+    /// Settings for the 3D mode.
+    pub volume: VolumeConfig,
+    /// Settings for the debug overlay.
+    pub overlay: OverlayConfig,
+}
+
+impl Default for NeonetConfig {
+    fn default() -> Self {
+        NeonetConfig {
+            seed: None,
+            msaa_samples: 4,
+            points_per_megapixel: None,
+            max_connections: None,
+            motion: Default::default(),
+            boundary: Default::default(),
+            spatial_index: Default::default(),
+            attractors: vec![],
+            lifecycle: Default::default(),
+            shape: Default::default(),
+            audio: Default::default(),
+            nodes: Default::default(),
+            triangles: Default::default(),
+            volume: Default::default(),
+            overlay: Default::default(),
+        }
+    }
+}
+
+impl NeonetConfig {
+    /// Parses a configuration from a JSON string, rejecting values the app
+    /// can't run with.
+    pub fn from_json(json: &str) -> Result<NeonetConfig, ConfigError> {
+        let config: NeonetConfig = serde_json::from_str(json)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads a configuration from a JSON file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<Path>) -> Result<NeonetConfig, ConfigError> {
+        NeonetConfig::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Checks for values that would otherwise only fail once the app is
+    /// running.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.lifecycle.enabled {
+            self.lifecycle.validate()?;
+        }
+        if self.volume.enabled && self.points_per_megapixel.is_some() {
+            return Err(ConfigError::InvalidValue(
+                "points_per_megapixel isn't supported in volume mode, which always has 200 points"
+                    .to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Describes how points move.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MotionConfig {
+    /// How points' velocities change over time.
+    pub mode: MotionMode,
+    /// Settings for the flow-field motion mode.
+    pub flow_field: FlowFieldConfig,
+    /// Settings for the flocking motion mode.
+    pub flocking: FlockingConfig,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MotionMode {
+    /// Points move in straight lines at a constant velocity.
+    #[default]
+    Linear,
+    /// Points are steered by a time-varying noise field.
+    FlowField,
+    /// Points flock together like boids, steering by their neighbors.
+    Flocking,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundaryMode {
+    /// Points leaving the border around the screen reappear on the other side.
+    /// Lines do not connect across the seam, which stays hidden off-screen.
+    #[default]
+    Wrap,
+    /// Points bounce off the edges of the screen.
+    Bounce,
+    /// The screen's opposite edges are joined, so points near one edge
+    /// connect to points near the other.
+    Torus,
+    /// Points leaving the border around the screen are replaced by a new point
+    /// coming in from a random edge.
+    Respawn,
+}
+
+/// Describes the noise field that steers points in the flow-field motion mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlowFieldConfig {
+    /// How quickly the field changes across the screen, per pixel.
+    pub scale: f32,
+    /// How quickly the field changes over time, per second.
+    pub time_speed: f32,
+    /// How quickly points turn to follow the field, per second.
+    pub strength: f32,
+}
+
+impl Default for FlowFieldConfig {
+    fn default() -> Self {
+        FlowFieldConfig {
+            scale: 0.002,
+            time_speed: 0.1,
+            strength: 1.5,
+        }
+    }
+}
+
+/// Describes how points steer by their neighbors in the flocking motion mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlockingConfig {
+    /// How far away, in pixels, a point can see its neighbors.
+    pub perception_radius: f32,
+    /// How close, in pixels, neighbors have to be before a point steers away
+    /// from them.
+    pub separation_radius: f32,
+    /// How strongly points steer away from crowded neighbors.
+    pub separation_weight: f32,
+    /// How strongly points match their neighbors' average velocity.
+    pub alignment_weight: f32,
+    /// How strongly points steer towards their neighbors' average position.
+    pub cohesion_weight: f32,
+    /// The slowest a point may move, in pixels per second.
+    pub min_speed: f32,
+    /// The fastest a point may move, in pixels per second.
+    pub max_speed: f32,
+}
+
+impl Default for FlockingConfig {
+    fn default() -> Self {
+        FlockingConfig {
+            perception_radius: 120.0,
+            separation_radius: 40.0,
+            separation_weight: 1.5,
+            alignment_weight: 0.5,
+            cohesion_weight: 0.1,
+            min_speed: 20.0,
+            max_speed: 80.0,
+        }
+    }
+}
+
+/// Describes an attractor, or a repulsor when its strength is negative.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttractorConfig {
+    /// The attractor's position in pixels, with the origin at the bottom-left
+    /// of the screen. This is ignored when the attractor orbits.
+    pub position: [f32; 2],
+    /// The acceleration, in pixels per second squared, applied to points right
+    /// next to the attractor. Negative strengths push points away.
+    pub strength: f32,
+    /// How far away, in pixels, the attractor affects points.
+    pub radius: f32,
+    /// How sharply the attractor's influence falls off with distance. The
+    /// influence is scaled by `(1 - distance / radius) ^ falloff`.
+    pub falloff: f32,
+    /// Sideways acceleration that makes points swirl counter-clockwise around
+    /// the attractor. Negative values swirl clockwise.
+    pub swirl: f32,
+    /// How much of their speed points inside the radius lose per second,
+    /// scaled like the pull. Without it, swirling points would keep speeding
+    /// up, since the swirl never stops pushing them along.
+    pub damping: f32,
+    /// An optional circular path the attractor follows.
+    pub orbit: Option<OrbitConfig>,
+}
+
+impl Default for AttractorConfig {
+    fn default() -> Self {
+        AttractorConfig {
+            position: [0.0, 0.0],
+            strength: 40.0,
+            radius: 300.0,
+            falloff: 2.0,
+            swirl: 0.0,
+            damping: 0.5,
+            orbit: None,
+        }
+    }
+}
+
+/// Describes a circular path followed by an attractor.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OrbitConfig {
+    /// The center of the orbit in pixels.
+    pub center: [f32; 2],
+    /// The radius of the orbit in pixels.
+    pub radius: f32,
+    /// How fast the attractor orbits, in radians per second.
+    pub speed: f32,
+    /// Where along the orbit the attractor starts, as a fraction of a turn.
+    pub phase: f32,
+}
+
+/// Describes how points spawn, age and fade out. Without a lifecycle, points
+/// live forever.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LifecycleConfig {
+    /// Whether points have a limited lifetime.
+    pub enabled: bool,
+    /// The shortest a point may live, in seconds.
+    pub min_lifetime: f32,
+    /// The longest a point may live, in seconds.
+    pub max_lifetime: f32,
+    /// How long a new point takes to fade in, in seconds.
+    pub fade_in: f32,
+    /// How long a dying point takes to fade out, in seconds.
+    pub fade_out: f32,
+    /// How many points spawn per second while there is room for them. When
+    /// this is 0, dead points are replaced immediately.
+    pub spawn_rate: f32,
+    /// Where new points spawn. When there are no emitters, points spawn at
+    /// random positions.
+    pub emitters: Vec<EmitterConfig>,
+}
+
+impl Default for LifecycleConfig {
+    fn default() -> Self {
+        LifecycleConfig {
+            enabled: false,
+            min_lifetime: 10.0,
+            max_lifetime: 30.0,
+            fade_in: 2.0,
+            fade_out: 2.0,
+            spawn_rate: 0.0,
+            emitters: vec![],
+        }
+    }
+}
+
+impl LifecycleConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        // Written so that NaN fails too.
+        let lifetimes_valid = self.min_lifetime > 0.0
+            && self.min_lifetime <= self.max_lifetime
+            && self.max_lifetime.is_finite();
+        if !lifetimes_valid {
+            return Err(ConfigError::InvalidValue(format!(
+                "lifecycle lifetimes must be above 0 with min_lifetime at most max_lifetime, \
+                 not {} and {}",
+                self.min_lifetime, self.max_lifetime
+            )));
+        }
+        if let Some(emitter) = self
+            .emitters
+            .iter()
+            .find(|emitter| emitter.radius < 0.0 || emitter.radius.is_nan())
+        {
+            return Err(ConfigError::InvalidValue(format!(
+                "emitter radius must not be negative, not {}",
+                emitter.radius
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Describes a place points spawn from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmitterConfig {
+    /// The emitter's position in pixels, with the origin at the bottom-left of
+    /// the screen.
+    pub position: [f32; 2],
+    /// How far from the position, in pixels, points may spawn.
+    pub radius: f32,
+    /// The direction new points move in, in degrees counter-clockwise from the
+    /// positive x axis.
+    pub direction: f32,
+    /// How far, in degrees, new points' directions may vary.
+    pub spread: f32,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        EmitterConfig {
+            position: [0.0, 0.0],
+            radius: 0.0,
+            direction: 0.0,
+            spread: 360.0,
+        }
+    }
+}
+
+/// Describes a shape, such as some text or a logo, that points gather into
+/// from time to time before drifting apart again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShapeConfig {
+    /// Whether points gather into the shape.
+    pub enabled: bool,
+    /// Text to gather into, drawn with the embedded font.
+    pub text: Option<String>,
+    /// A PNG image to gather into, on desktop. The points gather on the
+    /// bright, opaque pixels. This is used instead of `text` when both are set.
+    pub image: Option<String>,
+    /// Whether to gather on the image's dark pixels instead.
+    pub invert: bool,
+    /// How much of the screen the shape covers, from 0 to 1.
+    pub scale: f32,
+    /// How strongly points are pulled towards their places in the shape.
+    pub stiffness: f32,
+    /// How quickly points slow down as they are pulled into place.
+    pub damping: f32,
+    /// How long points drift freely before gathering, in seconds.
+    pub drift_time: f32,
+    /// How long points stay gathered before drifting apart, in seconds.
+    pub hold_time: f32,
+    /// Whether the points gather again after drifting apart.
+    pub repeat: bool,
+}
+
+impl Default for ShapeConfig {
+    fn default() -> Self {
+        ShapeConfig {
+            enabled: false,
+            text: None,
+            image: None,
+            invert: false,
+            scale: 0.6,
+            stiffness: 4.0,
+            damping: 3.0,
+            drift_time: 5.0,
+            hold_time: 10.0,
+            repeat: true,
+        }
+    }
+}
+
+/// Describes how the network reacts to audio. Loudness lengthens the lines and
+/// speeds up the points, and the frequency bands tint the lines.
+///
+/// On desktop, the audio comes from `file`. On the web, it comes from
+/// `set_audio_samples`, usually fed from an `AnalyserNode`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// Whether the network reacts to audio.
+    pub enabled: bool,
+    /// A WAV or Ogg Vorbis file to react to, on desktop.
+    pub file: Option<String>,
+    /// How much of the previous analysis is kept with each frame, from 0 for
+    /// none to 1 for all.
+    pub smoothing: f32,
+    /// How long lines are when the audio is silent, relative to their full
+    /// length.
+    pub min_line_scale: f32,
+    /// How much faster points move when the audio is at full volume.
+    pub max_speed_scale: f32,
+    /// The line color blended in by bass frequencies.
+    pub bass_color: [f32; 3],
+    /// The line color blended in by mid frequencies.
+    pub mid_color: [f32; 3],
+    /// The line color blended in by treble frequencies.
+    pub treble_color: [f32; 3],
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            enabled: false,
+            file: None,
+            smoothing: 0.6,
+            min_line_scale: 0.5,
+            max_speed_scale: 3.0,
+            bass_color: [0.8, 0.1, 0.4],
+            mid_color: [0.1, 0.8, 0.4],
+            treble_color: [0.5, 0.4, 1.0],
+        }
+    }
+}
+
+/// Describes how points are drawn as glowing nodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    /// Whether nodes are drawn at all.
+    pub enabled: bool,
+    /// The radius of a node's glow in pixels.
+    pub size: f32,
+    /// Whether nodes with more connections are drawn larger.
+    pub scale_by_connections: bool,
+    /// How much a node's radius grows per connection, as a fraction of `size`.
+    pub connection_scale: f32,
+    /// The node's color.
+    pub color: [f32; 3],
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            enabled: false,
+            size: 6.0,
+            scale_by_connections: false,
+            connection_scale: 0.15,
+            color: [0.0, 0.6, 0.9],
+        }
+    }
+}
+
+/// Describes how triangles of mutually-connected points are filled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TriangleConfig {
+    /// Whether triangles are filled at all.
+    pub enabled: bool,
+    /// The fill color.
+    pub color: [f32; 3],
+    /// The opacity of a triangle whose edges have no length. Triangles fade
+    /// out as their longest edge approaches the line length.
+    pub alpha: f32,
+}
+
+impl Default for TriangleConfig {
+    fn default() -> Self {
+        TriangleConfig {
+            enabled: false,
+            color: [0.0, 0.4, 0.6],
+            alpha: 0.15,
+        }
+    }
+}
+
+/// Describes the 3D mode, in which points drift inside a box and are viewed
+/// through a perspective camera. Nodes and triangles are not drawn in this
+/// mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VolumeConfig {
+    /// Whether the 3D mode is used instead of the flat one.
+    pub enabled: bool,
+    /// The depth of the box in pixels. Its width and height match the window.
+    pub depth: f32,
+    /// Whether the camera slowly orbits around the box.
+    pub orbit: bool,
+    /// How fast the camera orbits, in radians per second.
+    pub orbit_speed: f32,
+    /// The camera's vertical field of view in degrees.
+    pub field_of_view: f32,
+    /// The width in pixels of lines at the front of the box. Lines further
+    /// away are drawn thinner.
+    pub line_width: f32,
+}
+
+impl Default for VolumeConfig {
+    fn default() -> Self {
+        VolumeConfig {
+            enabled: false,
+            depth: 800.0,
+            orbit: true,
+            orbit_speed: 0.05,
+            field_of_view: 60.0,
+            line_width: 1.5,
+        }
+    }
+}
+
+/// Describes the debug overlay, which shows frame times and counts on top of
+/// the scene. It can be toggled while running with F3 on desktop, or
+/// `toggle_overlay` on the web.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OverlayConfig {
+    /// Whether the overlay is shown from the start.
+    pub enabled: bool,
+    /// Whether the spatial grid's chunks are outlined, shaded by how many
+    /// points each holds. Nothing is drawn with the k-d tree.
+    pub chunks: bool,
+    /// How many screen pixels each pixel of the font covers.
+    pub scale: f32,
+}
+
+impl Default for OverlayConfig {
+    fn default() -> Self {
+        OverlayConfig {
+            enabled: false,
+            chunks: false,
+            scale: 2.0,
+        }
+    }
+}
+
+/// Error potentially returned when loading a configuration.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("IO error")]
+    IOError(#[from] io::Error),
+    #[error("JSON error")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Invalid value: {0}")]
+    InvalidValue(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::neonet::config::{ConfigError, NeonetConfig};
+
+    #[test]
+    fn test_invalid_lifecycle_rejected() {
+        for lifecycle in [
+            r#"{"enabled": true, "min_lifetime": 30, "max_lifetime": 10}"#,
+            r#"{"enabled": true, "min_lifetime": 0, "max_lifetime": 0}"#,
+            r#"{"enabled": true, "emitters": [{"radius": -5}]}"#,
+        ] {
+            let json = format!(r#"{{"lifecycle": {}}}"#, lifecycle);
+            assert!(
+                matches!(
+                    NeonetConfig::from_json(&json),
+                    Err(ConfigError::InvalidValue(_))
+                ),
+                "{}",
+                json
+            );
+        }
+
+        // Settings that aren't used aren't checked.
+        let json = r#"{"lifecycle": {"min_lifetime": 30, "max_lifetime": 10}}"#;
+        assert!(NeonetConfig::from_json(json).is_ok());
+        let json = r#"{"lifecycle": {"enabled": true, "min_lifetime": 5, "max_lifetime": 5}}"#;
+        assert!(NeonetConfig::from_json(json).is_ok());
+    }
+
+    #[test]
+    fn test_volume_density_rejected() {
+        let json = r#"{"volume": {"enabled": true}, "points_per_megapixel": 100}"#;
+        assert!(matches!(
+            NeonetConfig::from_json(json),
+            Err(ConfigError::InvalidValue(_))
+        ));
+        let json = r#"{"volume": {"enabled": true}}"#;
+        assert!(NeonetConfig::from_json(json).is_ok());
+    }
+}
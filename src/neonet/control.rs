@@ -0,0 +1,48 @@
+//! Commands sent to a running `NeonetApp` from outside of it, such as from the
+//! web API.
+
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref PENDING: Mutex<Vec<ControlCommand>> = Mutex::new(vec![]);
+    static ref AUDIO: Mutex<Option<AudioSamples>> = Mutex::new(None);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ControlCommand {
+    /// Moves an attractor, or the center of its orbit, to a position in
+    /// pixels with the origin at the bottom-left of the screen.
+    MoveAttractor { index: usize, x: f32, y: f32 },
+    /// Changes an attractor's strength. Negative strengths repel points.
+    SetAttractorStrength { index: usize, strength: f32 },
+    /// Shows or hides the debug overlay.
+    ToggleOverlay,
+}
+
+/// Queues a command to be handled by the running app on its next update.
+pub fn send(command: ControlCommand) {
+    PENDING.lock().unwrap().push(command);
+}
+
+/// Takes every command queued since the last call.
+pub(crate) fn take_pending() -> Vec<ControlCommand> {
+    std::mem::take(&mut *PENDING.lock().unwrap())
+}
+
+/// A buffer of mono samples captured outside the app, such as by the browser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioSamples {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+/// Hands the latest audio samples to the running app, replacing any it has not
+/// analysed yet.
+pub fn send_audio(samples: AudioSamples) {
+    *AUDIO.lock().unwrap() = Some(samples);
+}
+
+/// Takes the audio samples sent since the last call, if there are any.
+pub(crate) fn take_audio() -> Option<AudioSamples> {
+    AUDIO.lock().unwrap().take()
+}
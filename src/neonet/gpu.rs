@@ -0,0 +1,97 @@
+//! The layouts of the points and line ends sent to the GPU.
+
+use crate::neonet::simulation::Point;
+use bytemuck::{Pod, Zeroable};
+use std::mem::size_of;
+use wgpu::{BufferAddress, VertexAttribute, VertexBufferLayout, VertexStepMode};
+
+#[repr(C, align(16))]
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct GPUPosition(pub(crate) [f32; 2]);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct GPUPoint {
+    pub(crate) position: GPUPosition,
+    pub(crate) color: [f32; 3],
+    pub(crate) life: f32,
+}
+
+unsafe impl Zeroable for GPUPoint {}
+unsafe impl Pod for GPUPoint {}
+
+impl GPUPoint {
+    pub fn new(point: Point, color: [f32; 3]) -> GPUPoint {
+        GPUPoint {
+            position: GPUPosition([point.x, point.y]),
+            color,
+            life: point.life,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PointIndex {
+    pub(crate) me: u32,
+    pub(crate) other: u32,
+    pub(crate) distance_sqr: f32,
+    /// Moves the vertex away from its point, for lines that are drawn twice
+    /// because they cross the seam of a torus.
+    pub(crate) offset: [f32; 2],
+}
+
+unsafe impl Zeroable for PointIndex {}
+unsafe impl Pod for PointIndex {}
+
+impl PointIndex {
+    const ATTRIBS: [VertexAttribute; 4] =
+        wgpu::vertex_attr_array![0 => Uint32, 1 => Uint32, 2 => Float32, 3 => Float32x2];
+
+    pub(crate) fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: size_of::<PointIndex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+
+    /// Adds the two vertices of the line between two points, or four when the
+    /// line crosses the seam of a torus and has to be drawn on both sides.
+    pub fn push_line(
+        indices: &mut Vec<PointIndex>,
+        point: &Point,
+        other: &Point,
+        distance_sqr: f32,
+        (offset_x, offset_y): (f32, f32),
+    ) {
+        indices.push(PointIndex {
+            me: point.index as u32,
+            other: other.index as u32,
+            distance_sqr,
+            offset: [0.0, 0.0],
+        });
+        indices.push(PointIndex {
+            me: other.index as u32,
+            other: point.index as u32,
+            distance_sqr,
+            offset: [offset_x, offset_y],
+        });
+
+        if offset_x != 0.0 || offset_y != 0.0 {
+            // Draw the line again from the other side of the seam
+            indices.push(PointIndex {
+                me: point.index as u32,
+                other: other.index as u32,
+                distance_sqr,
+                offset: [-offset_x, -offset_y],
+            });
+            indices.push(PointIndex {
+                me: other.index as u32,
+                other: point.index as u32,
+                distance_sqr,
+                offset: [0.0, 0.0],
+            });
+        }
+    }
+}
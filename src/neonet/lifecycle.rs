@@ -0,0 +1,166 @@
+//! Spawning, aging and fading out of points.
+
+use crate::{
+    flow::WindowSize,
+    neonet::{config::LifecycleConfig, simulation::Point, LINE_LENGTH},
+};
+use rand::{rngs::StdRng, Rng};
+use std::f32::consts::PI;
+
+pub struct Lifecycle {
+    config: LifecycleConfig,
+    /// Indices of points that have died and can be reused by new points.
+    free_indices: Vec<usize>,
+    /// How many points are owed by the spawn rate but have not spawned yet.
+    spawn_debt: f32,
+}
+
+impl Lifecycle {
+    pub fn new(config: LifecycleConfig) -> Lifecycle {
+        Lifecycle {
+            config,
+            free_indices: vec![],
+            spawn_debt: 0.0,
+        }
+    }
+
+    /// Whether points should start out spread across the screen, rather than
+    /// coming out of the emitters.
+    pub fn starts_populated(&self) -> bool {
+        self.config.emitters.is_empty()
+    }
+
+    /// Gives a point a random lifetime, and a random age when `staggered` so
+    /// that the initial points do not all die together.
+    pub fn birth(&self, point: &mut Point, rng: &mut StdRng, staggered: bool) {
+        point.lifetime = rng.gen_range(self.config.min_lifetime..=self.config.max_lifetime);
+        point.age = if staggered {
+            rng.gen_range(0.0..point.lifetime)
+        } else {
+            0.0
+        };
+        point.life = self.life(point);
+    }
+
+    /// Ages a point, updating how faded in or out it is.
+    pub fn age(&self, point: &mut Point, delta: f32) {
+        point.age += delta;
+        point.life = self.life(point);
+    }
+
+    /// Gets how visible a point is, fading in after it spawns and fading out
+    /// before it dies.
+    pub fn life(&self, point: &Point) -> f32 {
+        let fade_in = if self.config.fade_in > 0.0 {
+            point.age / self.config.fade_in
+        } else {
+            1.0
+        };
+        let fade_out = if self.config.fade_out > 0.0 {
+            (point.lifetime - point.age) / self.config.fade_out
+        } else {
+            1.0
+        };
+        fade_in.min(fade_out).clamp(0.0, 1.0)
+    }
+
+    pub fn is_dead(&self, point: &Point) -> bool {
+        point.age >= point.lifetime
+    }
+
+    /// Marks a point's index as reusable.
+    pub fn free(&mut self, index: usize) {
+        self.free_indices.push(index);
+    }
+
+    /// Replaces every reusable index, for when the points have been renumbered.
+    pub fn set_free(&mut self, indices: impl Iterator<Item = usize>) {
+        self.free_indices.clear();
+        self.free_indices.extend(indices);
+    }
+
+    /// Spawns new points into free indices, limited by the spawn rate.
+    pub fn spawn<F: FnMut(Point)>(
+        &mut self,
+        delta: f32,
+        rng: &mut StdRng,
+        size: WindowSize,
+        mut f: F,
+    ) {
+        let mut count = if self.config.spawn_rate > 0.0 {
+            self.spawn_debt += self.config.spawn_rate * delta;
+            let count = self.spawn_debt.floor();
+            self.spawn_debt -= count;
+            count as usize
+        } else {
+            self.free_indices.len()
+        };
+
+        count = count.min(self.free_indices.len());
+        if count == self.free_indices.len() {
+            // Don't build up a burst of points while there is no room for them.
+            self.spawn_debt = 0.0;
+        }
+
+        for _ in 0..count {
+            let index = self.free_indices.pop().unwrap();
+            let mut point = if self.config.emitters.is_empty() {
+                random_point(index, rng, size)
+            } else {
+                let emitter = &self.config.emitters[rng.gen_range(0..self.config.emitters.len())];
+                let angle = emitter.direction.to_radians()
+                    + rng.gen_range(-0.5..=0.5) * emitter.spread.to_radians();
+                let speed = rng.gen_range(20.0..100.0f32);
+                let offset_angle = rng.gen_range(0.0..(PI * 2.0));
+                let offset = rng.gen_range(0.0..=emitter.radius);
+                Point {
+                    index,
+                    x: emitter.position[0] + offset_angle.cos() * offset,
+                    y: emitter.position[1] + offset_angle.sin() * offset,
+                    vx: angle.cos() * speed,
+                    vy: angle.sin() * speed,
+                    ..Point::default()
+                }
+            };
+            self.birth(&mut point, rng, false);
+            f(point);
+        }
+    }
+}
+
+/// Creates a point at a random position within the padded screen, moving in a
+/// random direction.
+pub fn random_point(index: usize, rng: &mut StdRng, size: WindowSize) -> Point {
+    let angle = rng.gen_range(0.0..(PI * 2.0));
+    let speed = rng.gen_range(20.0..100.0f32);
+    Point {
+        index,
+        x: rng.gen_range(-LINE_LENGTH..size.width + LINE_LENGTH),
+        y: rng.gen_range(-LINE_LENGTH..size.height + LINE_LENGTH),
+        vx: angle.cos() * speed,
+        vy: angle.sin() * speed,
+        ..Point::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::neonet::{config::LifecycleConfig, lifecycle::Lifecycle, simulation::Point};
+
+    #[test]
+    fn test_life_fades_in_and_out() {
+        let lifecycle = Lifecycle::new(LifecycleConfig {
+            fade_in: 1.0,
+            fade_out: 2.0,
+            ..Default::default()
+        });
+        let point = |age| Point { age, lifetime: 10.0, ..Point::default() };
+
+        assert_eq!(lifecycle.life(&point(0.0)), 0.0);
+        assert_eq!(lifecycle.life(&point(0.5)), 0.5);
+        assert_eq!(lifecycle.life(&point(5.0)), 1.0);
+        assert_eq!(lifecycle.life(&point(9.0)), 0.5);
+        assert_eq!(lifecycle.life(&point(10.0)), 0.0);
+        assert!(lifecycle.is_dead(&point(10.0)));
+    }
+}
@@ -0,0 +1,723 @@
+mod attractor;
+pub mod config;
+pub mod control;
+pub(crate) mod gpu;
+mod lifecycle;
+mod msaa;
+mod nodes;
+mod overlay;
+pub mod raster;
+mod reactive;
+mod shape;
+pub mod simulation;
+pub mod svg;
+pub mod terminal;
+mod triangles;
+mod volume;
+
+use crate::{
+    buffer::BufferWrapper,
+    flow::{FlowModel, FlowModelInit, WindowSize},
+    neonet::{
+        config::NeonetConfig,
+        control::ControlCommand,
+        gpu::{GPUPoint, PointIndex},
+        msaa::Msaa,
+        nodes::Nodes,
+        overlay::{Overlay, OverlayCounts},
+        reactive::AudioReactive,
+        simulation::Simulation,
+        triangles::Triangles,
+        volume::Volume,
+    },
+    util::{least_power_of_2_greater, now},
+};
+use bytemuck::{Pod, Zeroable};
+use rand::{thread_rng, Rng};
+use std::{
+    borrow::Cow,
+    mem::size_of,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, BufferAddress,
+    BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites, CommandBuffer,
+    CommandEncoderDescriptor, Device, FragmentState, FrontFace, Limits, MultisampleState,
+    PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology,
+    Queue, RenderPass, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureView, VertexState,
+};
+
+#[cfg(feature = "timer")]
+use crate::timer::Timer;
+
+const LINE_LENGTH: f32 = 200f32;
+const POINT_COUNT: usize = 200;
+const BACKGROUND_COLOR: Color = Color { r: 0.0, g: 0.005, b: 0.01, a: 1.0 };
+const LINE_COLOR: Color = Color { r: 0.0, g: 0.4, b: 0.6, a: 1.0 };
+const LINE_COLOR_RGB: [f32; 3] = [
+    LINE_COLOR.r as f32,
+    LINE_COLOR.g as f32,
+    LINE_COLOR.b as f32,
+];
+
+const SHADER_SRC: &str = include_str!("shader.wgsl");
+
+/// Fills in the `{{name}}` placeholder in a shader's source.
+fn fill_placeholder(src: &str, name: &str, value: &str) -> String {
+    let placeholder = format!("{{{{{}}}}}", name);
+    assert!(
+        src.contains(&placeholder),
+        "Shader has no {} placeholder",
+        placeholder
+    );
+    src.replace(&placeholder, value)
+}
+
+/// Sets the length of the vertices uniform array in a shader.
+fn with_point_count(src: &str, point_count: usize) -> String {
+    fill_placeholder(src, "point_count", &format!("{}u", point_count))
+}
+
+/// Gets how many points the vertices uniform has room for with a device's
+/// limits. With a fixed point count, that is all there is room for. Otherwise,
+/// it is as many as fit in a uniform buffer.
+///
+/// Drawing without a GPU keeps to the same capacity, so that a configuration
+/// draws the same points either way.
+pub fn point_capacity(limits: &Limits, config: &NeonetConfig) -> usize {
+    if config.points_per_megapixel.is_some() {
+        limits.max_uniform_buffer_binding_size as usize / size_of::<GPUPoint>()
+    } else {
+        POINT_COUNT
+    }
+}
+
+pub struct NeonetApp {
+    size: WindowSize,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    queued_commands: Vec<CommandBuffer>,
+    msaa: Msaa,
+    mode: Mode,
+    overlay: Overlay,
+}
+
+/// What is drawn, which only holds the state that mode needs.
+enum Mode {
+    /// Points moving across the screen, connected by lines.
+    Flat(Box<Flat>),
+    /// Points moving through a box, seen through a perspective camera.
+    Volume(Box<Volume>),
+}
+
+/// The state of the flat mode: the simulation and everything drawn from it.
+struct Flat {
+    size: WindowSize,
+    simulation: Simulation,
+    uniform_buffer: BufferWrapper<UniformData>,
+    vertex_buffer_tmp: Vec<GPUPoint>,
+    vertex_buffer: BufferWrapper<GPUPoint>,
+    index_buffer_tmp: Vec<PointIndex>,
+    index_buffer: Option<BufferWrapper<PointIndex>>,
+    uniforms_bind_group: BindGroup,
+    pipeline: RenderPipeline,
+    nodes: Option<Nodes>,
+    triangles: Option<Triangles>,
+    line_length: f32,
+    audio: Option<AudioReactive>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct UniformData {
+    screen_width: f32,
+    screen_height: f32,
+    line_length: f32,
+    _padding2: u32,
+}
+
+unsafe impl Zeroable for UniformData {}
+unsafe impl Pod for UniformData {}
+
+impl Flat {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        device: &Device,
+        uniforms_bind_group_layout: &BindGroupLayout,
+        pipeline_layout: &PipelineLayout,
+        frame_format: TextureFormat,
+        multisample: MultisampleState,
+        config: NeonetConfig,
+        size: WindowSize,
+        seed: u64,
+    ) -> (Flat, Vec<CommandBuffer>) {
+        let point_capacity = point_capacity(&device.limits(), &config);
+        let mut simulation = Simulation::new(&config, size, seed, point_capacity);
+
+        // Take a zero-length step to collect the initial positions.
+        let mut vertex_buffer_tmp = vec![GPUPoint::zeroed(); point_capacity];
+        simulation.step(Duration::ZERO, |point| {
+            vertex_buffer_tmp[point.index] = GPUPoint::new(*point, LINE_COLOR_RGB)
+        });
+
+        let mut cbs = vec![];
+
+        let (uniform_buffer, cb) = BufferWrapper::from_data(
+            device,
+            &[UniformData {
+                screen_width: size.width,
+                screen_height: size.height,
+                line_length: LINE_LENGTH,
+                _padding2: 0,
+            }],
+            BufferUsages::UNIFORM,
+        );
+        cbs.push(cb);
+
+        // The actual vertex buffer will be a uniform.
+        let (vertex_buffer, cb) =
+            BufferWrapper::from_data(device, &vertex_buffer_tmp, BufferUsages::UNIFORM);
+        cbs.push(cb);
+
+        // Then we can specify our own per-index data as a vertex buffer.
+        let index_buffer_tmp = Vec::with_capacity(point_capacity * 2);
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Shader Module"),
+            source: ShaderSource::Wgsl(Cow::Owned(with_point_count(SHADER_SRC, point_capacity))),
+        });
+
+        let uniforms_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Uniforms Bind Group"),
+            layout: uniforms_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(
+                        uniform_buffer.buffer().as_entire_buffer_binding(),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Buffer(
+                        vertex_buffer.buffer().as_entire_buffer_binding(),
+                    ),
+                },
+            ],
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Render Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vert_main",
+                buffers: &[PointIndex::desc()],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "frag_main",
+                targets: &[Some(ColorTargetState {
+                    format: frame_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::LineList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample,
+            multiview: None,
+        });
+
+        let nodes = if config.nodes.enabled {
+            Some(Nodes::new(
+                device,
+                pipeline_layout,
+                frame_format,
+                multisample,
+                config.nodes,
+                point_capacity,
+            ))
+        } else {
+            None
+        };
+
+        let triangles = if config.triangles.enabled {
+            Some(Triangles::new(
+                device,
+                pipeline_layout,
+                frame_format,
+                multisample,
+                config.triangles,
+                point_capacity,
+            ))
+        } else {
+            None
+        };
+
+        let audio = if config.audio.enabled {
+            Some(AudioReactive::new(config.audio))
+        } else {
+            None
+        };
+
+        (
+            Flat {
+                size,
+                simulation,
+                uniform_buffer,
+                vertex_buffer_tmp,
+                vertex_buffer,
+                index_buffer_tmp,
+                index_buffer: None,
+                uniforms_bind_group,
+                pipeline,
+                nodes,
+                triangles,
+                line_length: LINE_LENGTH,
+                audio,
+            },
+            cbs,
+        )
+    }
+
+    async fn upload_uniforms(&mut self, device: &Device) -> CommandBuffer {
+        self.uniform_buffer
+            .replace_all(
+                device,
+                &[UniformData {
+                    screen_width: self.size.width,
+                    screen_height: self.size.height,
+                    line_length: self.line_length,
+                    _padding2: 0,
+                }],
+            )
+            .await
+            .unwrap()
+    }
+
+    async fn resize(&mut self, device: &Device, size: WindowSize) -> CommandBuffer {
+        self.size = size;
+        self.simulation.resize(size);
+        self.upload_uniforms(device).await
+    }
+
+    async fn update(&mut self, device: &Device, delta: Duration) -> Vec<CommandBuffer> {
+        let mut cbs = vec![];
+
+        // React to the audio
+
+        let mut line_color = LINE_COLOR_RGB;
+        if let Some(audio) = self.audio.as_mut() {
+            if let Some(samples) = control::take_audio() {
+                audio.feed(&samples.samples, samples.sample_rate);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            audio.update(self.simulation.time());
+
+            self.line_length = audio.line_length();
+            self.simulation.set_line_length(self.line_length);
+            self.simulation.set_speed(audio.speed());
+            line_color = audio.color();
+            cbs.push(self.upload_uniforms(device).await);
+        }
+
+        // Move the points
+
+        // Slots without a living point are left invisible
+        let point_count = self.simulation.point_count();
+        for point in self.vertex_buffer_tmp[..point_count].iter_mut() {
+            point.life = 0.0;
+        }
+        self.simulation.step(delta, |point| {
+            self.vertex_buffer_tmp[point.index] = GPUPoint::new(*point, line_color);
+        });
+
+        cbs.push(
+            self.vertex_buffer
+                .replace_all(device, &self.vertex_buffer_tmp[..point_count])
+                .await
+                .unwrap(),
+        );
+
+        // Draw the lines
+
+        self.index_buffer_tmp.clear();
+        if let Some(nodes) = self.nodes.as_mut() {
+            nodes.clear_connections();
+        }
+        if let Some(triangles) = self.triangles.as_mut() {
+            triangles.clear_connections();
+        }
+        self.simulation
+            .pairs(|point, other, distance_sqr, (offset_x, offset_y)| {
+                // #[cfg(debug_assertions)]
+                // let _timer1 = Timer::new(format!("Model::render point={:?} other={:?}",
+                // point, other)); let alpha = ((1.0 - distance_sqr.sqrt() /
+                // LINE_LENGTH) * 255.0) as u8;
+
+                let wrapped = offset_x != 0.0 || offset_y != 0.0;
+
+                PointIndex::push_line(
+                    &mut self.index_buffer_tmp,
+                    point,
+                    other,
+                    distance_sqr,
+                    (offset_x, offset_y),
+                );
+
+                if let Some(nodes) = self.nodes.as_mut() {
+                    nodes.connect(point.index, other.index);
+                }
+                // Triangles are drawn between the points' own positions, so ones
+                // crossing the seam would stretch across the whole screen.
+                if let Some(triangles) = self.triangles.as_mut().filter(|_| !wrapped) {
+                    triangles.connect(point.index, other.index, distance_sqr);
+                }
+            });
+
+        // Make sure the buffer is large enough
+        if self.index_buffer.is_none()
+            || self.index_buffer.as_ref().unwrap().capacity()
+                < self.index_buffer_tmp.len() as BufferAddress
+        {
+            self.index_buffer = Some(BufferWrapper::new(
+                device,
+                least_power_of_2_greater(self.index_buffer_tmp.len() as u64),
+                BufferUsages::VERTEX,
+            ));
+        }
+
+        {
+            let buffer = self.index_buffer.as_mut().unwrap();
+            cbs.push(
+                buffer
+                    .replace_all(device, &self.index_buffer_tmp)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        // Fill the triangles
+
+        if let Some(triangles) = self.triangles.as_mut() {
+            if let Some(cb) = triangles.update(device).await {
+                cbs.push(cb);
+            }
+        }
+
+        // Draw the nodes
+
+        if let Some(nodes) = self.nodes.as_mut() {
+            cbs.push(
+                nodes
+                    .update(device, &self.vertex_buffer_tmp[..point_count])
+                    .await,
+            );
+        }
+
+        cbs
+    }
+
+    /// Gets the counts shown on the debug overlay. Each line is two entries in
+    /// the index buffer.
+    fn overlay_counts(&self) -> OverlayCounts {
+        let index_buffer = self.index_buffer.as_ref().unwrap();
+        OverlayCounts {
+            points: self.simulation.point_count(),
+            connections: self.index_buffer_tmp.len() / 2,
+            point_buffer: (self.vertex_buffer.len(), self.vertex_buffer.capacity()),
+            line_buffer: (index_buffer.len(), index_buffer.capacity()),
+        }
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_bind_group(0, &self.uniforms_bind_group, &[]);
+
+        if let Some(triangles) = self.triangles.as_ref() {
+            triangles.render(render_pass);
+        }
+
+        let index_buffer = self.index_buffer.as_ref().unwrap();
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, index_buffer.buffer().slice(..));
+        render_pass.draw(0..index_buffer.len() as u32, 0..1);
+
+        if let Some(nodes) = self.nodes.as_ref() {
+            nodes.render(render_pass);
+        }
+    }
+}
+
+impl NeonetApp {
+    /// Rebuilds the debug overlay at the end of an update that began at
+    /// `start`.
+    async fn update_overlay(&mut self, start: SystemTime) {
+        self.overlay
+            .record_update(now().duration_since(start).unwrap_or_default());
+
+        // The 3D mode's points aren't in a grid that can be drawn.
+        let (counts, grid) = match &self.mode {
+            Mode::Flat(flat) => (flat.overlay_counts(), flat.simulation.grid()),
+            Mode::Volume(volume) => (volume.overlay_counts(), None),
+        };
+        if let Some(cb) = self
+            .overlay
+            .update(&self.device, self.size, &counts, grid)
+            .await
+        {
+            self.queued_commands.push(cb);
+        }
+    }
+}
+
+#[async_trait]
+impl FlowModel for NeonetApp {
+    type Config = NeonetConfig;
+
+    async fn init(init: FlowModelInit, config: NeonetConfig) -> NeonetApp {
+        let size = init.window_size;
+        let queue = init.queue;
+        let device = init.device;
+        let frame_format = init.frame_format;
+
+        let msaa = Msaa::new(
+            &device,
+            frame_format,
+            size,
+            config.msaa_samples,
+            init.max_sample_count,
+        )
+        .await;
+
+        let seed = config.seed.unwrap_or_else(|| thread_rng().gen());
+        info!("Simulation seed: {}", seed);
+
+        let uniforms_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Uniforms Bind Group Layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::VERTEX,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::VERTEX,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[&uniforms_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let overlay = Overlay::new(
+            &device,
+            frame_format,
+            msaa.multisample_state(),
+            config.overlay.clone(),
+            &init.adapter_info,
+        );
+
+        let mode = if config.volume.enabled {
+            let (volume, cbs) = Volume::new(
+                &device,
+                &uniforms_bind_group_layout,
+                &pipeline_layout,
+                frame_format,
+                msaa.multisample_state(),
+                config.volume,
+                size,
+                seed,
+            );
+            queue.submit(cbs);
+            Mode::Volume(Box::new(volume))
+        } else {
+            let (flat, cbs) = Flat::new(
+                &device,
+                &uniforms_bind_group_layout,
+                &pipeline_layout,
+                frame_format,
+                msaa.multisample_state(),
+                config,
+                size,
+                seed,
+            );
+            queue.submit(cbs);
+            Mode::Flat(Box::new(flat))
+        };
+
+        NeonetApp {
+            size,
+            device,
+            queue,
+            queued_commands: vec![],
+            msaa,
+            mode,
+            overlay,
+        }
+    }
+
+    async fn resize(&mut self, size: WindowSize) {
+        self.size = size;
+        self.msaa.resize(&self.device, size);
+        match &mut self.mode {
+            Mode::Flat(flat) => {
+                let cb = flat.resize(&self.device, size).await;
+                self.queued_commands.push(cb);
+            },
+            Mode::Volume(volume) => volume.resize(size),
+        }
+    }
+
+    async fn update(&mut self, delta: Duration) {
+        #[cfg(feature = "timer")]
+        let _timer = Timer::from_str("Model::update");
+        let start = now();
+
+        for command in control::take_pending() {
+            match (command, &mut self.mode) {
+                (ControlCommand::ToggleOverlay, _) => self.overlay.toggle(),
+                (command, Mode::Flat(flat)) => flat.simulation.handle(command),
+                // The 3D mode has no attractors to control.
+                (_, Mode::Volume(_)) => {},
+            }
+        }
+
+        let cbs = match &mut self.mode {
+            Mode::Flat(flat) => flat.update(&self.device, delta).await,
+            Mode::Volume(volume) => volume.update(&self.device, delta).await,
+        };
+        self.queued_commands.extend(cbs);
+
+        // Show the debug overlay
+
+        self.update_overlay(start).await;
+    }
+
+    fn render(&mut self, view: &TextureView, delta: Duration) {
+        #[cfg(feature = "timer")]
+        let _timer = Timer::from_str("Model::render");
+        let start = now();
+        self.overlay.record_frame(delta);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Render Command Encoder"),
+            });
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(self.msaa.color_attachment(view, BACKGROUND_COLOR))],
+                depth_stencil_attachment: None,
+            });
+
+            match &self.mode {
+                Mode::Flat(flat) => flat.render(&mut render_pass),
+                Mode::Volume(volume) => volume.render(&mut render_pass),
+            }
+
+            self.overlay.render(&mut render_pass);
+        }
+
+        self.queued_commands.push(encoder.finish());
+
+        self.queue.submit(self.queued_commands.drain(..));
+        self.overlay
+            .record_render(now().duration_since(start).unwrap_or_default());
+    }
+
+    fn toggle_debug(&mut self) {
+        self.overlay.toggle();
+    }
+
+    fn shutdown(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::neonet::{
+        nodes::NODE_SHADER_SRC,
+        overlay::OVERLAY_SHADER_SRC,
+        triangles::TRIANGLE_SHADER_SRC,
+        volume::{volume_shader_src, VOLUME_SHADER_SRC},
+        with_point_count, POINT_COUNT, SHADER_SRC,
+    };
+    use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+    fn validate(src: &str) {
+        let module = naga::front::wgsl::parse_str(src).unwrap();
+        Validator::new(ValidationFlags::all(), Capabilities::empty())
+            .validate(&module)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_line_shader_valid() {
+        validate(&with_point_count(SHADER_SRC, POINT_COUNT));
+    }
+
+    #[test]
+    fn test_node_shader_valid() {
+        validate(NODE_SHADER_SRC);
+    }
+
+    #[test]
+    fn test_triangle_shader_valid() {
+        validate(&with_point_count(TRIANGLE_SHADER_SRC, POINT_COUNT));
+    }
+
+    #[test]
+    fn test_volume_shader_valid() {
+        validate(&volume_shader_src());
+    }
+
+    #[test]
+    fn test_overlay_shader_valid() {
+        validate(OVERLAY_SHADER_SRC);
+    }
+
+    #[test]
+    fn test_point_count_replaced() {
+        for src in [SHADER_SRC, TRIANGLE_SHADER_SRC, VOLUME_SHADER_SRC] {
+            let resized = with_point_count(src, 2048);
+            assert!(resized.contains("let point_count: u32 = 2048u;"));
+            assert!(!resized.contains("{{point_count}}"));
+        }
+        validate(&with_point_count(SHADER_SRC, 2048));
+    }
+
+    #[test]
+    #[should_panic(expected = "{{point_count}}")]
+    fn test_missing_placeholder_panics() {
+        with_point_count(NODE_SHADER_SRC, 2048);
+    }
+}
@@ -0,0 +1,132 @@
+//! Multisampled color target that resolves into the frame view.
+
+use crate::flow::WindowSize;
+use wgpu::{
+    Color, Device, ErrorFilter, Extent3d, LoadOp, MultisampleState, Operations,
+    RenderPassColorAttachment, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    TextureView, TextureViewDescriptor,
+};
+
+pub struct Msaa {
+    sample_count: u32,
+    frame_format: TextureFormat,
+    framebuffer: Option<TextureView>,
+}
+
+impl Msaa {
+    /// Creates a multisampled framebuffer with as many of the `requested`
+    /// samples as the device allows, falling back to no multisampling if the
+    /// framebuffer cannot be created.
+    pub async fn new(
+        device: &Device,
+        frame_format: TextureFormat,
+        size: WindowSize,
+        requested: u32,
+        max_sample_count: u32,
+    ) -> Msaa {
+        let sample_count = requested.min(max_sample_count).max(1);
+        // Sample counts must be powers of 2.
+        let sample_count = 1 << (u32::BITS - 1 - sample_count.leading_zeros());
+
+        if sample_count == 1 {
+            return Msaa {
+                sample_count,
+                frame_format,
+                framebuffer: None,
+            };
+        }
+
+        device.push_error_scope(ErrorFilter::Validation);
+        let framebuffer = create_framebuffer(device, frame_format, size, sample_count);
+        if let Some(err) = device.pop_error_scope().await {
+            warn!(
+                "Unable to create {}x multisampled framebuffer, falling back to 1x: {}",
+                sample_count, err
+            );
+            return Msaa {
+                sample_count: 1,
+                frame_format,
+                framebuffer: None,
+            };
+        }
+
+        info!("Using {}x MSAA", sample_count);
+
+        Msaa {
+            sample_count,
+            frame_format,
+            framebuffer: Some(framebuffer),
+        }
+    }
+
+    pub fn multisample_state(&self) -> MultisampleState {
+        MultisampleState {
+            count: self.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        }
+    }
+
+    /// Recreates the multisampled framebuffer to match the new frame size.
+    pub fn resize(&mut self, device: &Device, size: WindowSize) {
+        if self.framebuffer.is_some() {
+            self.framebuffer = Some(create_framebuffer(
+                device,
+                self.frame_format,
+                size,
+                self.sample_count,
+            ));
+        }
+    }
+
+    /// Gets the color attachment that should be rendered to in order to end up
+    /// in `frame_view`.
+    pub fn color_attachment<'a>(
+        &'a self,
+        frame_view: &'a TextureView,
+        clear_color: Color,
+    ) -> RenderPassColorAttachment<'a> {
+        match self.framebuffer.as_ref() {
+            Some(framebuffer) => RenderPassColorAttachment {
+                view: framebuffer,
+                resolve_target: Some(frame_view),
+                ops: Operations {
+                    load: LoadOp::Clear(clear_color),
+                    // Only the resolved frame is needed afterwards.
+                    store: false,
+                },
+            },
+            None => RenderPassColorAttachment {
+                view: frame_view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(clear_color),
+                    store: true,
+                },
+            },
+        }
+    }
+}
+
+fn create_framebuffer(
+    device: &Device,
+    format: TextureFormat,
+    size: WindowSize,
+    sample_count: u32,
+) -> TextureView {
+    device
+        .create_texture(&TextureDescriptor {
+            label: Some("Multisampled Framebuffer"),
+            size: Extent3d {
+                width: (size.width as u32).max(1),
+                height: (size.height as u32).max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+        })
+        .create_view(&TextureViewDescriptor::default())
+}
@@ -0,0 +1,52 @@
+// Neonet node shader.
+
+struct UniformData {
+    screen_width: f32,
+    screen_height: f32,
+    line_length: f32,
+    _padding2: u32,
+};
+
+struct NodeInstance {
+    @location(0) position: vec2<f32>,
+    @location(1) radius: f32,
+    @location(2) color: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) offset: vec2<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+@group(0)
+@binding(0)
+var<uniform> uniform_data: UniformData;
+
+@vertex
+fn vert_main(@builtin(vertex_index) vertex_index: u32, node: NodeInstance) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    var corner = corners[vertex_index % 6u];
+
+    var output: VertexOutput;
+    var pixel = node.position + corner * node.radius;
+    var x = pixel.x / uniform_data.screen_width * 2.0 - 1.0;
+    var y = pixel.y / uniform_data.screen_height * 2.0 - 1.0;
+    output.position = vec4<f32>(x, y, 0.0, 1.0);
+    output.offset = corner;
+    output.color = node.color;
+    return output;
+}
+
+@fragment
+fn frag_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var falloff = max(1.0 - length(in.offset), 0.0);
+    return vec4<f32>(in.color, falloff * falloff);
+}
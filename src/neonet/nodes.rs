@@ -0,0 +1,160 @@
+//! Draws each point as an instanced, glowing quad.
+
+use crate::{
+    buffer::BufferWrapper,
+    neonet::{config::NodeConfig, GPUPoint},
+};
+use bytemuck::{Pod, Zeroable};
+use std::{borrow::Cow, mem::size_of};
+use wgpu::{
+    BlendComponent, BlendFactor, BlendOperation, BlendState, BufferAddress, BufferUsages,
+    ColorTargetState, ColorWrites, CommandBuffer, Device, FragmentState, FrontFace,
+    MultisampleState, PipelineLayout, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPass,
+    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, TextureFormat,
+    VertexAttribute, VertexBufferLayout, VertexState, VertexStepMode,
+};
+
+pub(crate) const NODE_SHADER_SRC: &str = include_str!("node.wgsl");
+
+/// Additive blending so overlapping glows brighten each other.
+const GLOW_BLENDING: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::SrcAlpha,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+    alpha: BlendComponent::OVER,
+};
+
+/// Gets how far a node's glow reaches, for a point with `connections` lines.
+pub(crate) fn node_radius(config: &NodeConfig, connections: u32) -> f32 {
+    if config.scale_by_connections {
+        config.size * (1.0 + config.connection_scale * connections as f32)
+    } else {
+        config.size
+    }
+}
+
+pub struct Nodes {
+    config: NodeConfig,
+    pipeline: RenderPipeline,
+    connection_counts: Vec<u32>,
+    instance_buffer_tmp: Vec<GPUNode>,
+    instance_buffer: BufferWrapper<GPUNode>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct GPUNode {
+    position: [f32; 2],
+    radius: f32,
+    color: [f32; 3],
+}
+
+unsafe impl Zeroable for GPUNode {}
+unsafe impl Pod for GPUNode {}
+
+impl GPUNode {
+    const ATTRIBS: [VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32, 2 => Float32x3];
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: size_of::<GPUNode>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+impl Nodes {
+    pub fn new(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        frame_format: TextureFormat,
+        multisample: MultisampleState,
+        config: NodeConfig,
+        point_count: usize,
+    ) -> Nodes {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Node Shader Module"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(NODE_SHADER_SRC)),
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Node Render Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vert_main",
+                buffers: &[GPUNode::desc()],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "frag_main",
+                targets: &[Some(ColorTargetState {
+                    format: frame_format,
+                    blend: Some(GLOW_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample,
+            multiview: None,
+        });
+
+        let instance_buffer =
+            BufferWrapper::new(device, point_count as BufferAddress, BufferUsages::VERTEX);
+
+        Nodes {
+            config,
+            pipeline,
+            connection_counts: vec![0; point_count],
+            instance_buffer_tmp: Vec::with_capacity(point_count),
+            instance_buffer,
+        }
+    }
+
+    /// Forgets the connections counted during the previous update.
+    pub fn clear_connections(&mut self) {
+        self.connection_counts.fill(0);
+    }
+
+    /// Records a connection between two points.
+    pub fn connect(&mut self, me: usize, other: usize) {
+        self.connection_counts[me] += 1;
+        self.connection_counts[other] += 1;
+    }
+
+    /// Rebuilds the node instances from the points' current positions.
+    pub async fn update(&mut self, device: &Device, points: &[GPUPoint]) -> CommandBuffer {
+        self.instance_buffer_tmp.clear();
+        for (point, &connections) in points.iter().zip(self.connection_counts.iter()) {
+            self.instance_buffer_tmp.push(GPUNode {
+                position: point.position.0,
+                radius: node_radius(&self.config, connections),
+                color: self.config.color.map(|c| c * point.life),
+            });
+        }
+
+        self.instance_buffer
+            .replace_all(device, &self.instance_buffer_tmp)
+            .await
+            .unwrap()
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.buffer().slice(..));
+        render_pass.draw(0..6, 0..self.instance_buffer.len() as u32);
+    }
+}
@@ -0,0 +1,565 @@
+//! Draws frame times, counts and the spatial grid's chunks over the scene, for
+//! debugging.
+//!
+//! Everything is drawn as flat-colored triangles built on the CPU, with text
+//! from the embedded bitmap font, so it needs no textures or storage buffers
+//! and works within WebGL2's limits.
+
+use crate::{
+    buffer::BufferWrapper,
+    flow::WindowSize,
+    font,
+    grid::{ChunkLayout, Grid},
+    neonet::{config::OverlayConfig, simulation::Point},
+    util::least_power_of_2_greater,
+};
+use bytemuck::{Pod, Zeroable};
+use std::{borrow::Cow, mem::size_of, time::Duration};
+use wgpu::{
+    AdapterInfo, BlendState, BufferAddress, BufferUsages, ColorTargetState, ColorWrites,
+    CommandBuffer, Device, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, TextureFormat, VertexAttribute,
+    VertexBufferLayout, VertexState, VertexStepMode,
+};
+
+pub(crate) const OVERLAY_SHADER_SRC: &str = include_str!("overlay.wgsl");
+
+const PANEL_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const TEXT_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const CHUNK_COLOR: [f32; 3] = [1.0, 0.5, 0.0];
+const CHUNK_LINE_ALPHA: f32 = 0.4;
+/// How opaque the fullest chunk is filled. Emptier chunks are fainter.
+const CHUNK_FILL_ALPHA: f32 = 0.2;
+/// How far the panel is from the top-left of the screen, in font pixels.
+const MARGIN: f32 = 4.0;
+/// The space between the edges of the panel and the text, in font pixels.
+const PADDING: f32 = 3.0;
+/// How far each new measurement moves the averages shown, so that they change
+/// slowly enough to read.
+const SMOOTHING: f32 = 0.05;
+
+/// What the overlay shows about the scene, gathered by the app each update.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct OverlayCounts {
+    pub points: usize,
+    pub connections: usize,
+    /// How many points are uploaded, and how many the buffer has room for.
+    pub point_buffer: (u64, u64),
+    /// How many line entries are uploaded, and how many the buffer has room
+    /// for.
+    pub line_buffer: (u64, u64),
+}
+
+/// Running averages of how long frames take, in seconds.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct FrameTimes {
+    frame: Option<f32>,
+    update: Option<f32>,
+    render: Option<f32>,
+}
+
+/// Blends a measurement into a running average.
+fn smooth(average: &mut Option<f32>, duration: Duration) {
+    let seconds = duration.as_secs_f32();
+    *average = Some(match *average {
+        Some(average) => average + (seconds - average) * SMOOTHING,
+        None => seconds,
+    });
+}
+
+pub struct Overlay {
+    config: OverlayConfig,
+    visible: bool,
+    /// The lines describing the adapter, which don't change.
+    adapter: String,
+    times: FrameTimes,
+    pipeline: RenderPipeline,
+    chunk_lens: Vec<usize>,
+    vertex_buffer_tmp: Vec<OverlayVertex>,
+    vertex_buffer: Option<BufferWrapper<OverlayVertex>>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct OverlayVertex {
+    /// The position in clip space.
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+unsafe impl Zeroable for OverlayVertex {}
+unsafe impl Pod for OverlayVertex {}
+
+impl OverlayVertex {
+    const ATTRIBS: [VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4];
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: size_of::<OverlayVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+impl Overlay {
+    pub fn new(
+        device: &Device,
+        frame_format: TextureFormat,
+        multisample: MultisampleState,
+        config: OverlayConfig,
+        adapter_info: &AdapterInfo,
+    ) -> Overlay {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Overlay Shader Module"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(OVERLAY_SHADER_SRC)),
+        });
+
+        // The overlay is drawn over the 3D mode too, whose bind group doesn't
+        // match the flat mode's, so it binds nothing at all.
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Overlay Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Overlay Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vert_main",
+                buffers: &[OverlayVertex::desc()],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "frag_main",
+                targets: &[Some(ColorTargetState {
+                    format: frame_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample,
+            multiview: None,
+        });
+
+        Overlay {
+            visible: config.enabled,
+            config,
+            adapter: format!(
+                "ADAPTER: {}\nBACKEND: {:?}, {:?}",
+                adapter_info.name, adapter_info.backend, adapter_info.device_type
+            ),
+            times: FrameTimes::default(),
+            pipeline,
+            chunk_lens: vec![],
+            vertex_buffer_tmp: vec![],
+            vertex_buffer: None,
+        }
+    }
+
+    /// Shows the overlay if it is hidden, or hides it if it is shown.
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Records the time between the last two frames.
+    pub fn record_frame(&mut self, delta: Duration) {
+        if !delta.is_zero() {
+            smooth(&mut self.times.frame, delta);
+        }
+    }
+
+    /// Records how long the last update took.
+    pub fn record_update(&mut self, duration: Duration) {
+        smooth(&mut self.times.update, duration);
+    }
+
+    /// Records how long the last frame took to encode and submit. This is the
+    /// time spent on the CPU, rather than how long the GPU took to draw it.
+    pub fn record_render(&mut self, duration: Duration) {
+        smooth(&mut self.times.render, duration);
+    }
+
+    /// Rebuilds the overlay for the latest counts and uploads it, if it is
+    /// shown. The chunks are only described when the points are in a grid.
+    pub async fn update(
+        &mut self,
+        device: &Device,
+        size: WindowSize,
+        counts: &OverlayCounts,
+        grid: Option<&Grid<Point>>,
+    ) -> Option<CommandBuffer> {
+        if !self.visible {
+            if let Some(buffer) = self.vertex_buffer.as_mut() {
+                buffer.clear();
+            }
+            return None;
+        }
+
+        let layout = grid.map(|grid| {
+            self.chunk_lens.clear();
+            self.chunk_lens.extend(grid.chunk_lens());
+            grid.chunk_layout()
+        });
+        let chunks = layout.map(|layout| (layout, &self.chunk_lens[..]));
+        let text = stats_text(&self.times, counts, chunks, &self.adapter);
+        build_vertices(
+            &mut self.vertex_buffer_tmp,
+            size,
+            self.config.scale,
+            &text,
+            chunks.filter(|_| self.config.chunks),
+        );
+
+        // Make sure the buffer is large enough
+        if self.vertex_buffer.is_none()
+            || self.vertex_buffer.as_ref().unwrap().capacity()
+                < self.vertex_buffer_tmp.len() as BufferAddress
+        {
+            self.vertex_buffer = Some(BufferWrapper::new(
+                device,
+                least_power_of_2_greater(self.vertex_buffer_tmp.len() as u64),
+                BufferUsages::VERTEX,
+            ));
+        }
+
+        let buffer = self.vertex_buffer.as_mut().unwrap();
+        Some(
+            buffer
+                .replace_all(device, &self.vertex_buffer_tmp)
+                .await
+                .unwrap(),
+        )
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if let Some(buffer) = self.vertex_buffer.as_ref() {
+            if self.visible && buffer.len() > 0 {
+                render_pass.set_pipeline(&self.pipeline);
+                render_pass.set_vertex_buffer(0, buffer.buffer().slice(..));
+                render_pass.draw(0..buffer.len() as u32, 0..1);
+            }
+        }
+    }
+}
+
+/// Formats an average time in milliseconds.
+fn millis(seconds: Option<f32>) -> String {
+    match seconds {
+        Some(seconds) => format!("{:.2} MS", seconds * 1000.0),
+        None => "-".to_string(),
+    }
+}
+
+/// Writes out everything the overlay shows, one item per line.
+fn stats_text(
+    times: &FrameTimes,
+    counts: &OverlayCounts,
+    chunks: Option<(ChunkLayout, &[usize])>,
+    adapter: &str,
+) -> String {
+    let fps = match times.frame {
+        Some(frame) => format!("{:.1}", 1.0 / frame),
+        None => "-".to_string(),
+    };
+    let mut lines = vec![
+        format!("FPS: {} ({})", fps, millis(times.frame)),
+        format!("UPDATE: {}", millis(times.update)),
+        format!("RENDER: {}", millis(times.render)),
+        format!("POINTS: {}", counts.points),
+        format!("CONNECTIONS: {}", counts.connections),
+        format!(
+            "POINT BUFFER: {} / {}",
+            counts.point_buffer.0, counts.point_buffer.1
+        ),
+        format!(
+            "LINE BUFFER: {} / {}",
+            counts.line_buffer.0, counts.line_buffer.1
+        ),
+    ];
+
+    if let Some((layout, lens)) = chunks {
+        let occupied: Vec<usize> = lens.iter().copied().filter(|&len| len > 0).collect();
+        let average = if occupied.is_empty() {
+            0.0
+        } else {
+            occupied.iter().sum::<usize>() as f32 / occupied.len() as f32
+        };
+        lines.push(format!(
+            "CHUNKS: {}X{}, {} EMPTY",
+            layout.x_chunks,
+            layout.y_chunks,
+            lens.len() - occupied.len()
+        ));
+        lines.push(format!(
+            "PER CHUNK: MAX {}, AVG {:.1}",
+            occupied.iter().copied().max().unwrap_or(0),
+            average
+        ));
+    }
+
+    lines.push(adapter.to_string());
+    lines.join("\n")
+}
+
+/// Adds two triangles covering a rectangle, given by its bottom-left and
+/// top-right corners in pixels from the bottom-left of the screen.
+fn push_rect(
+    vertices: &mut Vec<OverlayVertex>,
+    size: WindowSize,
+    (left, bottom): (f32, f32),
+    (right, top): (f32, f32),
+    color: [f32; 4],
+) {
+    let clip = |x: f32, y: f32| [x / size.width * 2.0 - 1.0, y / size.height * 2.0 - 1.0];
+    let corners = [
+        clip(left, bottom),
+        clip(right, bottom),
+        clip(right, top),
+        clip(left, top),
+    ];
+    for corner in [0, 1, 2, 0, 2, 3] {
+        vertices.push(OverlayVertex { position: corners[corner], color });
+    }
+}
+
+/// Builds the overlay's triangles, with `text` on a panel at the top-left of
+/// the screen, over the chunks if they are given.
+fn build_vertices(
+    vertices: &mut Vec<OverlayVertex>,
+    size: WindowSize,
+    scale: f32,
+    text: &str,
+    chunks: Option<(ChunkLayout, &[usize])>,
+) {
+    vertices.clear();
+
+    if let Some((layout, lens)) = chunks {
+        push_chunks(vertices, size, layout, lens);
+    }
+
+    let (text_width, text_height) = font::text_size(text);
+    let left = MARGIN * scale;
+    let top = size.height - MARGIN * scale;
+    push_rect(
+        vertices,
+        size,
+        (left, top - (text_height as f32 + PADDING * 2.0) * scale),
+        (left + (text_width as f32 + PADDING * 2.0) * scale, top),
+        PANEL_COLOR,
+    );
+
+    // Neighboring pixels along a row are drawn as one rectangle, which takes
+    // far fewer triangles than drawing each of them.
+    let text_left = left + PADDING * scale;
+    let text_top = top - PADDING * scale;
+    let push_run = |vertices: &mut Vec<OverlayVertex>, (x, y, length): (usize, usize, usize)| {
+        push_rect(
+            vertices,
+            size,
+            (
+                text_left + x as f32 * scale,
+                text_top - (y + 1) as f32 * scale,
+            ),
+            (
+                text_left + (x + length) as f32 * scale,
+                text_top - y as f32 * scale,
+            ),
+            TEXT_COLOR,
+        )
+    };
+    let mut run: Option<(usize, usize, usize)> = None;
+    font::render(text, |x, y| match run.as_mut() {
+        Some((run_x, run_y, length)) if *run_y == y && *run_x + *length == x => *length += 1,
+        _ => {
+            if let Some(finished) = run.replace((x, y, 1)) {
+                push_run(vertices, finished);
+            }
+        },
+    });
+    if let Some(finished) = run {
+        push_run(vertices, finished);
+    }
+}
+
+/// Adds the chunks' outlines, filling each one more brightly the more points
+/// it holds compared to the fullest.
+fn push_chunks(
+    vertices: &mut Vec<OverlayVertex>,
+    size: WindowSize,
+    layout: ChunkLayout,
+    lens: &[usize],
+) {
+    let (origin_x, origin_y) = layout.origin;
+    let [r, g, b] = CHUNK_COLOR;
+
+    let fullest = lens.iter().copied().max().unwrap_or(0);
+    for (chunk, &len) in lens.iter().enumerate().filter(|(_, &len)| len > 0) {
+        let x = origin_x + (chunk % layout.x_chunks) as f32 * layout.chunk_width;
+        let y = origin_y + (chunk / layout.x_chunks) as f32 * layout.chunk_height;
+        let alpha = CHUNK_FILL_ALPHA * len as f32 / fullest as f32;
+        push_rect(
+            vertices,
+            size,
+            (x, y),
+            (x + layout.chunk_width, y + layout.chunk_height),
+            [r, g, b, alpha],
+        );
+    }
+
+    let right = origin_x + layout.x_chunks as f32 * layout.chunk_width;
+    let top = origin_y + layout.y_chunks as f32 * layout.chunk_height;
+    let line_color = [r, g, b, CHUNK_LINE_ALPHA];
+    for column in 0..=layout.x_chunks {
+        let x = origin_x + column as f32 * layout.chunk_width;
+        push_rect(
+            vertices,
+            size,
+            (x - 0.5, origin_y),
+            (x + 0.5, top),
+            line_color,
+        );
+    }
+    for row in 0..=layout.y_chunks {
+        let y = origin_y + row as f32 * layout.chunk_height;
+        push_rect(
+            vertices,
+            size,
+            (origin_x, y - 0.5),
+            (right, y + 0.5),
+            line_color,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flow::WindowSize,
+        grid::ChunkLayout,
+        neonet::overlay::{
+            build_vertices, smooth, stats_text, FrameTimes, OverlayCounts, PANEL_COLOR, SMOOTHING,
+            TEXT_COLOR,
+        },
+    };
+    use std::time::Duration;
+
+    const SIZE: WindowSize = WindowSize { width: 800.0, height: 600.0 };
+
+    #[test]
+    fn test_smooth() {
+        let mut average = None;
+        smooth(&mut average, Duration::from_millis(10));
+        assert_eq!(average, Some(0.01));
+        smooth(&mut average, Duration::from_millis(30));
+        assert!((average.unwrap() - (0.01 + 0.02 * SMOOTHING)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_stats_text() {
+        let mut times = FrameTimes::default();
+        smooth(&mut times.frame, Duration::from_millis(20));
+        smooth(&mut times.update, Duration::from_millis(4));
+        let counts = OverlayCounts {
+            points: 200,
+            connections: 345,
+            point_buffer: (200, 200),
+            line_buffer: (690, 1024),
+        };
+        let layout = ChunkLayout {
+            origin: (-200.0, -200.0),
+            chunk_width: 200.0,
+            chunk_height: 200.0,
+            x_chunks: 3,
+            y_chunks: 2,
+        };
+        let text = stats_text(
+            &times,
+            &counts,
+            Some((layout, &[4, 0, 1, 0, 0, 2])),
+            "ADAPTER",
+        );
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "FPS: 50.0 (20.00 MS)",
+                "UPDATE: 4.00 MS",
+                "RENDER: -",
+                "POINTS: 200",
+                "CONNECTIONS: 345",
+                "POINT BUFFER: 200 / 200",
+                "LINE BUFFER: 690 / 1024",
+                "CHUNKS: 3X2, 3 EMPTY",
+                "PER CHUNK: MAX 4, AVG 2.3",
+                "ADAPTER",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_on_panel() {
+        let mut vertices = vec![];
+        build_vertices(&mut vertices, SIZE, 2.0, "-", None);
+
+        // The dash's pixels are drawn as one rectangle on top of the panel.
+        assert_eq!(vertices.len(), 12);
+        assert!(vertices[..6].iter().all(|v| v.color == PANEL_COLOR));
+        assert!(vertices[6..].iter().all(|v| v.color == TEXT_COLOR));
+        let panel_left = vertices[..6]
+            .iter()
+            .map(|v| v.position[0])
+            .fold(1.0, f32::min);
+        let text_left = vertices[6..]
+            .iter()
+            .map(|v| v.position[0])
+            .fold(1.0, f32::min);
+        let text_right = vertices[6..]
+            .iter()
+            .map(|v| v.position[0])
+            .fold(-1.0, f32::max);
+        assert!(panel_left < text_left);
+        // Five font pixels, two screen pixels each.
+        assert!((text_right - text_left - 10.0 / SIZE.width * 2.0).abs() < 1e-6);
+        assert!(vertices
+            .iter()
+            .all(|v| v.position.iter().all(|c| (-1.0..=1.0).contains(c))));
+    }
+
+    #[test]
+    fn test_chunks() {
+        let layout = ChunkLayout {
+            origin: (-200.0, -200.0),
+            chunk_width: 200.0,
+            chunk_height: 200.0,
+            x_chunks: 6,
+            y_chunks: 5,
+        };
+        let mut lens = vec![0; 30];
+        lens[7] = 2;
+        lens[8] = 4;
+
+        let mut vertices = vec![];
+        build_vertices(&mut vertices, SIZE, 2.0, "", Some((layout, &lens)));
+
+        // Two filled chunks, 7 + 6 lines and the panel.
+        assert_eq!(vertices.len(), (2 + 7 + 6 + 1) * 6);
+        let alphas: Vec<f32> = vertices[..12].iter().map(|v| v.color[3]).collect();
+        assert_eq!(alphas[0] * 2.0, alphas[6]);
+        // The chunk at (1, 1) starts at the bottom-left corner of the screen.
+        assert_eq!(vertices[0].position, [-1.0, -1.0]);
+    }
+}
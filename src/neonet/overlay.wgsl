@@ -0,0 +1,24 @@
+// Neonet debug overlay shader.
+
+struct OverlayVertex {
+    @location(0) position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vert_main(vertex: OverlayVertex) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(vertex.position, 0.0, 1.0);
+    output.color = vertex.color;
+    return output;
+}
+
+@fragment
+fn frag_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,403 @@
+//! A reference rasterizer that draws a frame on the CPU the way the GPU would,
+//! so frames can be checked without a GPU.
+//!
+//! It reads the points through the same memory layout the shaders see and
+//! repeats their math, so a change to either `GPUPoint` or the shaders shows
+//! up as a difference from the golden images in the tests. Triangles and the
+//! 3D mode aren't drawn.
+
+use crate::{
+    neonet::{
+        config::NodeConfig, nodes::node_radius, simulation::Simulation, GPUPoint, PointIndex,
+        UniformData, BACKGROUND_COLOR, LINE_COLOR_RGB,
+    },
+    util::linear_to_srgb,
+};
+use bytemuck::Zeroable;
+use std::io::{self, Write};
+
+/// The size of each `VertexData` in a WGSL uniform array. Array elements in
+/// uniforms are aligned to 16 bytes.
+const VERTEX_STRIDE: usize = 32;
+/// Where `VertexData.position` is within each vertex.
+const POSITION_OFFSET: usize = 0;
+/// Where `VertexData.color` is, aligned to 16 bytes as a `vec3<f32>`.
+const COLOR_OFFSET: usize = 16;
+/// Where `VertexData.life` is, packed in after the color.
+const LIFE_OFFSET: usize = 28;
+
+/// A frame drawn on the CPU, as 8-bit sRGB RGBA pixels from the top-left.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    /// Encodes the frame as a PNG image.
+    pub fn write_png<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(out, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(png::Compression::Best);
+        encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        Ok(writer.finish()?)
+    }
+
+    /// Decodes a frame from an RGBA PNG image, like the ones `write_png`
+    /// writes.
+    pub fn read_png(bytes: &[u8]) -> Result<Frame, png::DecodingError> {
+        let mut reader = png::Decoder::new(bytes).read_info()?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels)?;
+        pixels.truncate(info.buffer_size());
+        Ok(Frame {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    /// Counts the pixels that have a channel more than `tolerance` away from
+    /// the same pixel in `other`. Frames of different sizes differ everywhere.
+    pub fn count_differences(&self, other: &Frame, tolerance: u8) -> usize {
+        if (self.width, self.height) != (other.width, other.height) {
+            return self.pixels.len().max(other.pixels.len()) / 4;
+        }
+
+        self.pixels
+            .chunks(4)
+            .zip(other.pixels.chunks(4))
+            .filter(|(a, b)| {
+                a.iter()
+                    .zip(b.iter())
+                    .any(|(a, b)| a.abs_diff(*b) > tolerance)
+            })
+            .count()
+    }
+}
+
+/// Draws the simulation's current frame at the size of its screen: the
+/// background, the lines between points, and the glowing nodes if `nodes` is
+/// given.
+pub fn render_frame(simulation: &mut Simulation, nodes: Option<&NodeConfig>) -> Frame {
+    let size = simulation.size();
+    let uniforms = UniformData {
+        screen_width: size.width,
+        screen_height: size.height,
+        line_length: simulation.line_length(),
+        _padding2: 0,
+    };
+
+    // Collect the buffers the same way `NeonetApp::update` does.
+    let mut vertices = vec![GPUPoint::zeroed(); simulation.point_count()];
+    for point in simulation.points() {
+        vertices[point.index] = GPUPoint::new(*point, LINE_COLOR_RGB);
+    }
+    let mut indices = vec![];
+    let mut connection_counts = vec![0; vertices.len()];
+    simulation.pairs(|point, other, distance_sqr, offset| {
+        PointIndex::push_line(&mut indices, point, other, distance_sqr, offset);
+        connection_counts[point.index] += 1;
+        connection_counts[other.index] += 1;
+    });
+
+    let mut target = Target::new(size.width as u32, size.height as u32);
+    let vertex_bytes: &[u8] = bytemuck::cast_slice(&vertices);
+    for line in indices.chunks_exact(2) {
+        let start = line_vertex(&uniforms, vertex_bytes, &line[0]);
+        let end = line_vertex(&uniforms, vertex_bytes, &line[1]);
+        target.line(start, end);
+    }
+
+    if let Some(config) = nodes {
+        for (index, &connections) in connection_counts.iter().enumerate() {
+            let (position, _, life) = read_vertex(vertex_bytes, index);
+            let radius = node_radius(config, connections);
+            target.node(&uniforms, position, radius, config.color.map(|c| c * life));
+        }
+    }
+
+    target.finish()
+}
+
+/// Reads the position, color and life of a vertex from the vertices uniform.
+fn read_vertex(bytes: &[u8], index: usize) -> ([f32; 2], [f32; 3], f32) {
+    let float = |offset: usize| {
+        let start = index * VERTEX_STRIDE + offset;
+        f32::from_ne_bytes(bytes[start..start + 4].try_into().unwrap())
+    };
+    (
+        [float(POSITION_OFFSET), float(POSITION_OFFSET + 4)],
+        [
+            float(COLOR_OFFSET),
+            float(COLOR_OFFSET + 4),
+            float(COLOR_OFFSET + 8),
+        ],
+        float(LIFE_OFFSET),
+    )
+}
+
+/// Mirrors `vert_main` in `shader.wgsl`, giving the vertex's position in clip
+/// space and its color.
+fn line_vertex(
+    uniforms: &UniformData,
+    vertices: &[u8],
+    index: &PointIndex,
+) -> ([f32; 2], [f32; 4]) {
+    let (position, color, life) = read_vertex(vertices, index.me as usize);
+    let (_, _, other_life) = read_vertex(vertices, index.other as usize);
+
+    let x = (position[0] + index.offset[0]) / uniforms.screen_width * 2.0 - 1.0;
+    let y = (position[1] + index.offset[1]) / uniforms.screen_height * 2.0 - 1.0;
+    let distance = index.distance_sqr.sqrt();
+    let alpha = (1.0 - distance / uniforms.line_length) * life * other_life;
+    ([x, y], [color[0], color[1], color[2], alpha])
+}
+
+/// A linear color render target, cleared to the background color.
+struct Target {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Target {
+    fn new(width: u32, height: u32) -> Target {
+        let background = [
+            BACKGROUND_COLOR.r as f32,
+            BACKGROUND_COLOR.g as f32,
+            BACKGROUND_COLOR.b as f32,
+            BACKGROUND_COLOR.a as f32,
+        ];
+        Target {
+            width,
+            height,
+            pixels: vec![background; (width * height) as usize],
+        }
+    }
+
+    /// Converts a position in clip space to one in pixels from the top-left.
+    fn to_pixels(&self, [x, y]: [f32; 2]) -> (f32, f32) {
+        (
+            (x + 1.0) / 2.0 * self.width as f32,
+            (1.0 - y) / 2.0 * self.height as f32,
+        )
+    }
+
+    fn pixel_mut(&mut self, x: f32, y: f32) -> Option<&mut [f32; 4]> {
+        if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+            return None;
+        }
+        let index = y as usize * self.width as usize + x as usize;
+        Some(&mut self.pixels[index])
+    }
+
+    /// Draws a one pixel wide line, interpolating the color between its ends
+    /// and blending it with `BlendState::ALPHA_BLENDING`. Like a GPU, the last
+    /// pixel is left for whatever line continues from it.
+    fn line(
+        &mut self,
+        (start, start_color): ([f32; 2], [f32; 4]),
+        (end, end_color): ([f32; 2], [f32; 4]),
+    ) {
+        let (x1, y1) = self.to_pixels(start);
+        let (x2, y2) = self.to_pixels(end);
+        let steps = (x2 - x1).abs().max((y2 - y1).abs()).round() as usize;
+        for step in 0..steps {
+            let t = (step as f32 + 0.5) / steps as f32;
+            let mut color = [0.0; 4];
+            for (channel, (a, b)) in color.iter_mut().zip(start_color.iter().zip(end_color)) {
+                *channel = a + (b - a) * t;
+            }
+
+            if let Some(pixel) = self.pixel_mut(x1 + (x2 - x1) * t, y1 + (y2 - y1) * t) {
+                let alpha = color[3];
+                for channel in 0..3 {
+                    pixel[channel] = color[channel] * alpha + pixel[channel] * (1.0 - alpha);
+                }
+                pixel[3] = alpha + pixel[3] * (1.0 - alpha);
+            }
+        }
+    }
+
+    /// Mirrors `node.wgsl` and its additive blending, drawing a glow that
+    /// fades out to `radius` pixels from the node's position.
+    fn node(&mut self, uniforms: &UniformData, position: [f32; 2], radius: f32, color: [f32; 3]) {
+        let clip = |x: f32, y: f32| {
+            [
+                x / uniforms.screen_width * 2.0 - 1.0,
+                y / uniforms.screen_height * 2.0 - 1.0,
+            ]
+        };
+        let (left, top) = self.to_pixels(clip(position[0] - radius, position[1] + radius));
+        let (right, bottom) = self.to_pixels(clip(position[0] + radius, position[1] - radius));
+        let (center_x, center_y) = self.to_pixels(clip(position[0], position[1]));
+        let (scale_x, scale_y) = ((right - left) / 2.0, (bottom - top) / 2.0);
+
+        for y in top.max(0.0).floor() as u32..bottom.min(self.height as f32).ceil() as u32 {
+            for x in left.max(0.0).floor() as u32..right.min(self.width as f32).ceil() as u32 {
+                // Sample at the center of each pixel, like a GPU does.
+                let dx = (x as f32 + 0.5 - center_x) / scale_x;
+                let dy = (y as f32 + 0.5 - center_y) / scale_y;
+                if dx.abs() > 1.0 || dy.abs() > 1.0 {
+                    continue;
+                }
+
+                let falloff = (1.0 - (dx * dx + dy * dy).sqrt()).max(0.0);
+                let alpha = falloff * falloff;
+                let pixel = &mut self.pixels[(y * self.width + x) as usize];
+                for channel in 0..3 {
+                    pixel[channel] += color[channel] * alpha;
+                }
+                pixel[3] = alpha + pixel[3] * (1.0 - alpha);
+            }
+        }
+    }
+
+    /// Encodes the target as an sRGB frame, like an sRGB surface does.
+    fn finish(self) -> Frame {
+        let mut pixels = Vec::with_capacity(self.pixels.len() * 4);
+        for [r, g, b, a] in self.pixels {
+            pixels.push(linear_to_srgb(r as f64));
+            pixels.push(linear_to_srgb(g as f64));
+            pixels.push(linear_to_srgb(b as f64));
+            pixels.push((a.clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+        Frame {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flow::WindowSize,
+        neonet::{
+            config::{BoundaryMode, NeonetConfig, NodeConfig},
+            raster::{render_frame, Frame},
+            simulation::Simulation,
+            with_point_count, GPUPoint, POINT_COUNT, SHADER_SRC,
+        },
+    };
+    use naga::TypeInner;
+    use std::{
+        fs,
+        mem::{offset_of, size_of},
+        path::Path,
+        time::Duration,
+    };
+
+    /// At most this many pixels may differ by more than the tolerance, to
+    /// allow for floating point differences between platforms.
+    const MAX_DIFFERENT_PIXELS: usize = 200;
+    const TOLERANCE: u8 = 4;
+
+    /// How long the lines in golden frames are. With shorter lines than usual,
+    /// and few points, frames are sparse enough for the images to stay small.
+    const GOLDEN_LINE_LENGTH: f32 = 100.0;
+
+    /// Renders a seeded frame with up to `point_count` points and compares it
+    /// against a golden image. Setting `NEONET_UPDATE_GOLDEN` writes the image
+    /// instead.
+    fn check_golden(
+        name: &str,
+        config: NeonetConfig,
+        nodes: Option<&NodeConfig>,
+        point_count: usize,
+    ) {
+        let size = WindowSize { width: 320.0, height: 240.0 };
+        let mut simulation = Simulation::new(&config, size, 40, point_count);
+        simulation.set_line_length(GOLDEN_LINE_LENGTH);
+        let frame = Duration::from_secs_f32(1.0 / 60.0);
+        simulation.step(Duration::ZERO, |_| {});
+        for _ in 0..30 {
+            simulation.step(frame, |_| {});
+        }
+        let frame = render_frame(&mut simulation, nodes);
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/golden")
+            .join(format!("{}.png", name));
+        if std::env::var_os("NEONET_UPDATE_GOLDEN").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            frame.write_png(fs::File::create(&path).unwrap()).unwrap();
+            return;
+        }
+
+        let golden = Frame::read_png(&fs::read(&path).unwrap()).unwrap();
+        let differences = frame.count_differences(&golden, TOLERANCE);
+        assert!(
+            differences <= MAX_DIFFERENT_PIXELS,
+            "{} pixels differ from {}",
+            differences,
+            path.display()
+        );
+    }
+
+    #[test]
+    fn test_golden_lines() {
+        // Most points start off screen, in the border they wrap around in.
+        check_golden("lines", NeonetConfig::default(), None, POINT_COUNT);
+    }
+
+    #[test]
+    fn test_golden_torus_nodes() {
+        let config = NeonetConfig {
+            boundary: BoundaryMode::Torus,
+            ..Default::default()
+        };
+        let nodes = NodeConfig { enabled: true, ..Default::default() };
+        check_golden("torus_nodes", config, Some(&nodes), 40);
+    }
+
+    #[test]
+    fn test_layout_matches_shader() {
+        // The rasterizer reads vertices with the layout WGSL gives
+        // `VertexData`, so it must still be what the shader declares.
+        let module =
+            naga::front::wgsl::parse_str(&with_point_count(SHADER_SRC, POINT_COUNT)).unwrap();
+        let (members, span) = module
+            .types
+            .iter()
+            .find_map(|(_, ty)| match &ty.inner {
+                TypeInner::Struct { members, span } if ty.name.as_deref() == Some("VertexData") => {
+                    Some((members, *span))
+                },
+                _ => None,
+            })
+            .unwrap();
+
+        let offsets: Vec<(&str, usize)> = members
+            .iter()
+            .map(|member| (member.name.as_deref().unwrap(), member.offset as usize))
+            .collect();
+        assert_eq!(
+            offsets,
+            [
+                ("position", offset_of!(GPUPoint, position)),
+                ("color", offset_of!(GPUPoint, color)),
+                ("life", offset_of!(GPUPoint, life)),
+            ]
+        );
+        assert_eq!(span as usize, size_of::<GPUPoint>());
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let frame = Frame {
+            width: 2,
+            height: 1,
+            pixels: vec![0, 16, 25, 255, 0, 170, 203, 128],
+        };
+        let mut bytes = vec![];
+        frame.write_png(&mut bytes).unwrap();
+        assert_eq!(Frame::read_png(&bytes).unwrap(), frame);
+    }
+}
@@ -0,0 +1,104 @@
+//! Turns the analysed audio into changes to the line length, speed and color
+//! of the network.
+
+use crate::{
+    audio::{AudioAnalyzer, AudioFeatures},
+    neonet::{config::AudioConfig, LINE_COLOR, LINE_LENGTH},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::audio::{AudioClip, FFT_SIZE};
+
+pub struct AudioReactive {
+    config: AudioConfig,
+    analyzer: AudioAnalyzer,
+    features: AudioFeatures,
+    #[cfg(not(target_arch = "wasm32"))]
+    clip: Option<AudioClip>,
+    #[cfg(not(target_arch = "wasm32"))]
+    window: Vec<f32>,
+}
+
+impl AudioReactive {
+    pub fn new(config: AudioConfig) -> AudioReactive {
+        #[cfg(not(target_arch = "wasm32"))]
+        let clip = config
+            .file
+            .as_ref()
+            .and_then(|path| match AudioClip::load(path) {
+                Ok(clip) => Some(clip),
+                Err(err) => {
+                    error!("Error loading audio file {:?}: {:?}", path, err);
+                    None
+                },
+            });
+
+        AudioReactive {
+            analyzer: AudioAnalyzer::new(config.smoothing),
+            config,
+            features: AudioFeatures::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            clip,
+            #[cfg(not(target_arch = "wasm32"))]
+            window: vec![0.0; FFT_SIZE],
+        }
+    }
+
+    /// Analyses the audio file at `time` seconds into the simulation, so that
+    /// the audio stays in step with the simulation however fast it runs.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn update(&mut self, time: f32) {
+        if let Some(clip) = self.clip.as_ref() {
+            clip.window_at(time, &mut self.window);
+            self.features = self.analyzer.analyze(&self.window, clip.sample_rate());
+        }
+    }
+
+    /// Analyses samples captured elsewhere, such as by the browser.
+    pub fn feed(&mut self, samples: &[f32], sample_rate: u32) {
+        self.features = self.analyzer.analyze(samples, sample_rate);
+    }
+
+    /// Gets how long lines may be, shrinking when the audio is quiet.
+    pub fn line_length(&self) -> f32 {
+        let scale = self.config.min_line_scale;
+        LINE_LENGTH * (scale + (1.0 - scale) * self.features.loudness)
+    }
+
+    /// Gets how much faster than normal points move, speeding up when the
+    /// audio is loud.
+    pub fn speed(&self) -> f32 {
+        1.0 + (self.config.max_speed_scale - 1.0) * self.features.loudness
+    }
+
+    /// Gets the color of the lines, blending towards the colors of the
+    /// loudest frequency bands.
+    pub fn color(&self) -> [f32; 3] {
+        let bands = [
+            (self.features.bass, self.config.bass_color),
+            (self.features.mid, self.config.mid_color),
+            (self.features.treble, self.config.treble_color),
+        ];
+        let total: f32 = bands.iter().map(|&(level, _)| level).sum();
+        let base = [
+            LINE_COLOR.r as f32,
+            LINE_COLOR.g as f32,
+            LINE_COLOR.b as f32,
+        ];
+        if total <= 0.0 {
+            return base;
+        }
+
+        let intensity = bands.iter().map(|&(level, _)| level).fold(0.0, f32::max);
+        let mut color = [0.0; 3];
+        for (i, channel) in color.iter_mut().enumerate() {
+            let blend: f32 = bands
+                .iter()
+                .map(|&(level, band_color)| level * band_color[i])
+                .sum::<f32>()
+                / total;
+            *channel = base[i] + (blend - base[i]) * intensity;
+        }
+        color
+    }
+}
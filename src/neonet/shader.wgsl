@@ -1,14 +1,11 @@
 // Neonet shader.
 
-let point_count: u32 = 200u;
-let line_length: f32 = 200.0;
+let point_count: u32 = {{point_count}};
 
 struct VertexData {
     position: vec2<f32>,
     color: vec3<f32>,
-    _padding1: u32,
-    _padding2: u32,
-    _padding3: u32,
+    life: f32,
 };
 
 struct Vertices {
@@ -18,7 +15,7 @@ struct Vertices {
 struct UniformData {
     screen_width: f32,
     screen_height: f32,
-    _padding1: u32,
+    line_length: f32,
     _padding2: u32,
 };
 
@@ -26,6 +23,7 @@ struct VertexIndex {
     @location(0) me: u32,
     @location(1) other: u32,
     @location(2) distance_sqr: f32,
+    @location(3) offset: vec2<f32>,
 };
 
 struct VertexOutput {
@@ -45,11 +43,14 @@ var<uniform> vertices: Vertices;
 fn vert_main(index: VertexIndex) -> VertexOutput {
     var output: VertexOutput;
     var me = vertices.vertices[index.me];
-    var x = me.position.x / uniform_data.screen_width * 2.0 - 1.0;
-    var y = me.position.y / uniform_data.screen_height * 2.0 - 1.0;
+    var position = me.position + index.offset;
+    var x = position.x / uniform_data.screen_width * 2.0 - 1.0;
+    var y = position.y / uniform_data.screen_height * 2.0 - 1.0;
     output.position = vec4<f32>(x, y, 0.0, 1.0);
     var distance = sqrt(index.distance_sqr);
-    output.color = vec4<f32>(me.color, 1.0 - distance / line_length);
+    var other = vertices.vertices[index.other];
+    var alpha = (1.0 - distance / uniform_data.line_length) * me.life * other.life;
+    output.color = vec4<f32>(me.color, alpha);
     return output;
 }
 
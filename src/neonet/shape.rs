@@ -0,0 +1,435 @@
+//! Gathering points into a shape, such as some text or a logo, and letting
+//! them drift apart again.
+
+use crate::{
+    flow::WindowSize,
+    font,
+    neonet::{config::ShapeConfig, simulation::Point},
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+
+/// The pixels of an image or some text that points gather on.
+#[derive(Debug, Clone)]
+pub struct Mask {
+    width: usize,
+    height: usize,
+    /// The filled pixels, with the origin at the bottom-left of the mask.
+    filled: Vec<(usize, usize)>,
+}
+
+impl Mask {
+    /// Creates a mask from text drawn with the embedded font.
+    pub fn from_text(text: &str) -> Mask {
+        let (width, height) = font::text_size(text);
+        let mut filled = vec![];
+        font::render(text, |x, y| filled.push((x, height - 1 - y)));
+        Mask { width, height, filled }
+    }
+
+    /// Creates a mask from a PNG image, filled where the image is bright and
+    /// opaque, or where it is dark when `invert` is set.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_png(bytes: &[u8], invert: bool) -> Result<Mask, png::DecodingError> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+
+        let channels = info.color_type.samples();
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut filled = vec![];
+        for (y, row) in buffer.chunks(info.line_size).take(height).enumerate() {
+            for (x, pixel) in row.chunks(channels).take(width).enumerate() {
+                let (brightness, alpha) = match pixel {
+                    [gray] => (*gray, 255),
+                    [gray, alpha] => (*gray, *alpha),
+                    [r, g, b] => (luminance(*r, *g, *b), 255),
+                    [r, g, b, alpha, ..] => (luminance(*r, *g, *b), *alpha),
+                    [] => (0, 0),
+                };
+                let bright = brightness >= 128;
+                if alpha >= 128 && bright != invert {
+                    filled.push((x, height - 1 - y));
+                }
+            }
+        }
+
+        Ok(Mask { width, height, filled })
+    }
+
+    /// Loads a mask from a PNG file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str, invert: bool) -> Result<Mask, MaskError> {
+        Ok(Mask::from_png(&fs::read(path)?, invert)?)
+    }
+
+    /// Picks `count` positions within the mask's filled pixels, with the mask
+    /// centered on the screen and scaled to cover `scale` of it.
+    ///
+    /// Points are spread over every filled pixel before any pixel gets a
+    /// second point.
+    pub fn targets(
+        &self,
+        count: usize,
+        size: WindowSize,
+        scale: f32,
+        rng: &mut StdRng,
+    ) -> Vec<(f32, f32)> {
+        if self.filled.is_empty() {
+            return vec![];
+        }
+
+        let pixel_size =
+            (size.width * scale / self.width as f32).min(size.height * scale / self.height as f32);
+        let left = (size.width - self.width as f32 * pixel_size) / 2.0;
+        let bottom = (size.height - self.height as f32 * pixel_size) / 2.0;
+
+        let mut pixels = vec![];
+        while pixels.len() < count {
+            let mut round = self.filled.clone();
+            round.shuffle(rng);
+            pixels.extend(round);
+        }
+
+        pixels
+            .into_iter()
+            .take(count)
+            .map(|(x, y)| {
+                (
+                    left + (x as f32 + rng.gen_range(0.0..1.0)) * pixel_size,
+                    bottom + (y as f32 + rng.gen_range(0.0..1.0)) * pixel_size,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn luminance(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Error)]
+pub enum MaskError {
+    #[error("IO Error")]
+    IOError(#[from] std::io::Error),
+    #[error("PNG Error")]
+    PngError(#[from] png::DecodingError),
+}
+
+/// Something that should happen to the points as the shape's cycle goes on.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ShapeEvent {
+    /// The points should start gathering on the mask.
+    Assemble,
+    /// The points should let go of their targets and drift again.
+    Dissolve,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Phase {
+    Drifting,
+    Assembled,
+    Done,
+}
+
+/// Times the cycle of drifting, assembling into the mask and dissolving.
+pub struct Shape {
+    config: ShapeConfig,
+    mask: Mask,
+    phase: Phase,
+    phase_time: f32,
+}
+
+impl Shape {
+    pub fn new(config: ShapeConfig, mask: Mask) -> Shape {
+        Shape {
+            config,
+            mask,
+            phase: Phase::Drifting,
+            phase_time: 0.0,
+        }
+    }
+
+    pub fn mask(&self) -> &Mask {
+        &self.mask
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.config.scale
+    }
+
+    /// Advances the cycle, returning what the points should do now, if
+    /// anything.
+    pub fn advance(&mut self, delta: f32) -> Option<ShapeEvent> {
+        self.phase_time += delta;
+        match self.phase {
+            Phase::Drifting if self.phase_time >= self.config.drift_time => {
+                self.phase = Phase::Assembled;
+                self.phase_time = 0.0;
+                Some(ShapeEvent::Assemble)
+            },
+            Phase::Assembled if self.phase_time >= self.config.hold_time => {
+                self.phase = if self.config.repeat {
+                    Phase::Drifting
+                } else {
+                    Phase::Done
+                };
+                self.phase_time = 0.0;
+                Some(ShapeEvent::Dissolve)
+            },
+            _ => None,
+        }
+    }
+
+    /// Pulls a point towards its target like a damped spring.
+    pub fn apply(&self, point: &mut Point, delta: f32) {
+        if let Some((x, y)) = point.target {
+            let ax = (x - point.x) * self.config.stiffness - point.vx * self.config.damping;
+            let ay = (y - point.y) * self.config.stiffness - point.vy * self.config.damping;
+            point.vx += ax * delta;
+            point.vy += ay * delta;
+        }
+    }
+}
+
+/// Matches every position with a target, returning the index of the target for
+/// each position. There must be at least as many targets as positions.
+///
+/// This greedily gives each target the nearest position that doesn't have one
+/// yet, which keeps the paths short without solving the full assignment
+/// problem. Positions are sorted into buckets, so each search only looks
+/// around the target.
+pub fn assign_targets(positions: &[(f32, f32)], targets: &[(f32, f32)]) -> Vec<usize> {
+    let mut assigned = vec![usize::MAX; positions.len()];
+    let mut buckets = Buckets::new(positions);
+    for (target_index, &(tx, ty)) in targets.iter().enumerate() {
+        match buckets.take_nearest(positions, tx, ty) {
+            Some(index) => assigned[index] = target_index,
+            None => break,
+        }
+    }
+    assigned
+}
+
+/// The indices of positions sorted into square buckets, holding about one
+/// position each, for finding the nearest one left.
+struct Buckets {
+    left: f32,
+    bottom: f32,
+    bucket_size: f32,
+    columns: usize,
+    rows: usize,
+    buckets: Vec<Vec<usize>>,
+    remaining: usize,
+}
+
+impl Buckets {
+    fn new(positions: &[(f32, f32)]) -> Buckets {
+        let (mut left, mut bottom) = (f32::INFINITY, f32::INFINITY);
+        let (mut right, mut top) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for &(x, y) in positions {
+            left = left.min(x);
+            bottom = bottom.min(y);
+            right = right.max(x);
+            top = top.max(y);
+        }
+        if positions.is_empty() {
+            (left, bottom, right, top) = (0.0, 0.0, 0.0, 0.0);
+        }
+
+        // Buckets are no narrower than a thin strip of positions needs, so
+        // there are only a few for each position.
+        let (width, height) = (right - left, top - bottom);
+        let count = positions.len().max(1) as f32;
+        let bucket_size = (width * height / count)
+            .sqrt()
+            .max(width.max(height) / count)
+            .max(1.0);
+        let columns = (width / bucket_size) as usize + 1;
+        let rows = (height / bucket_size) as usize + 1;
+
+        let mut buckets = Buckets {
+            left,
+            bottom,
+            bucket_size,
+            columns,
+            rows,
+            buckets: vec![vec![]; columns * rows],
+            remaining: positions.len(),
+        };
+        for (index, &(x, y)) in positions.iter().enumerate() {
+            let (column, row) = buckets.bucket(x, y);
+            buckets.buckets[row * columns + column].push(index);
+        }
+        buckets
+    }
+
+    /// Gets the bucket holding a position, or the nearest one to it.
+    fn bucket(&self, x: f32, y: f32) -> (usize, usize) {
+        let column = ((x - self.left) / self.bucket_size).max(0.0) as usize;
+        let row = ((y - self.bottom) / self.bucket_size).max(0.0) as usize;
+        (column.min(self.columns - 1), row.min(self.rows - 1))
+    }
+
+    /// Removes the position nearest to `x` and `y`, returning its index.
+    fn take_nearest(&mut self, positions: &[(f32, f32)], x: f32, y: f32) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let (column, row) = self.bucket(x, y);
+        // The bucket and slot in it of the nearest position so far, and its
+        // squared distance.
+        let mut nearest: Option<(usize, usize, f32)> = None;
+        for ring in 0..self.columns.max(self.rows) {
+            let min_column = column.saturating_sub(ring);
+            let max_column = (column + ring).min(self.columns - 1);
+            let min_row = row.saturating_sub(ring);
+            let max_row = (row + ring).min(self.rows - 1);
+            for bucket_row in min_row..=max_row {
+                for bucket_column in min_column..=max_column {
+                    // Only the buckets on the edge of the ring are new.
+                    if bucket_column.abs_diff(column).max(bucket_row.abs_diff(row)) != ring {
+                        continue;
+                    }
+                    let bucket = bucket_row * self.columns + bucket_column;
+                    for (slot, &index) in self.buckets[bucket].iter().enumerate() {
+                        let (px, py) = positions[index];
+                        let distance_sqr = (px - x) * (px - x) + (py - y) * (py - y);
+                        let is_nearer = match nearest {
+                            Some((_, _, nearest_sqr)) => distance_sqr < nearest_sqr,
+                            None => true,
+                        };
+                        if is_nearer {
+                            nearest = Some((bucket, slot, distance_sqr));
+                        }
+                    }
+                }
+            }
+
+            // Positions in further rings are at least this far away.
+            let reach = ring as f32 * self.bucket_size;
+            if matches!(nearest, Some((_, _, nearest_sqr)) if nearest_sqr <= reach * reach) {
+                break;
+            }
+        }
+
+        let (bucket, slot, _) = nearest?;
+        self.remaining -= 1;
+        Some(self.buckets[bucket].swap_remove(slot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flow::WindowSize,
+        neonet::shape::{assign_targets, Mask},
+    };
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    fn total_distance(positions: &[(f32, f32)], targets: &[(f32, f32)], order: &[usize]) -> f32 {
+        positions
+            .iter()
+            .zip(order)
+            .map(|(&(x, y), &target)| {
+                let (tx, ty) = targets[target];
+                ((x - tx) * (x - tx) + (y - ty) * (y - ty)).sqrt()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_assign_targets() {
+        let mut rng = StdRng::seed_from_u64(37);
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        let positions: Vec<(f32, f32)> = (0..200)
+            .map(|_| (rng.gen_range(0.0..800.0), rng.gen_range(0.0..600.0)))
+            .collect();
+        let targets = Mask::from_text("NEONET").targets(200, size, 0.6, &mut rng);
+        assert_eq!(targets.len(), 200);
+        for &(x, y) in targets.iter() {
+            assert!((80.0..=720.0).contains(&x) && (0.0..=600.0).contains(&y));
+        }
+
+        let assigned = assign_targets(&positions, &targets);
+        let mut sorted = assigned.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..200).collect::<Vec<_>>());
+
+        let mut random = sorted;
+        random.shuffle(&mut rng);
+        assert!(
+            total_distance(&positions, &targets, &assigned)
+                < total_distance(&positions, &targets, &random) * 0.75
+        );
+    }
+
+    /// The greedy assignment, searching every position for each target.
+    fn brute_force(positions: &[(f32, f32)], targets: &[(f32, f32)]) -> Vec<usize> {
+        let mut assigned = vec![usize::MAX; positions.len()];
+        for (target_index, &(tx, ty)) in targets.iter().enumerate() {
+            let nearest = positions
+                .iter()
+                .enumerate()
+                .filter(|&(index, _)| assigned[index] == usize::MAX)
+                .map(|(index, &(x, y))| (index, (x - tx) * (x - tx) + (y - ty) * (y - ty)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+            match nearest {
+                Some((index, _)) => assigned[index] = target_index,
+                None => break,
+            }
+        }
+        assigned
+    }
+
+    #[test]
+    fn test_assign_targets_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(38);
+        let size = WindowSize { width: 1920.0, height: 1080.0 };
+        for (count, extra) in [(0, 3), (1, 0), (500, 0), (1000, 200)] {
+            // Some positions are off screen, and some are bunched in a line.
+            let positions: Vec<(f32, f32)> = (0..count)
+                .map(|index| {
+                    if index % 5 == 0 {
+                        (rng.gen_range(0.0..1920.0), 540.0)
+                    } else {
+                        (rng.gen_range(-100.0..2020.0), rng.gen_range(-100.0..1180.0))
+                    }
+                })
+                .collect();
+            let targets = Mask::from_text("NEONET").targets(count + extra, size, 0.6, &mut rng);
+            assert_eq!(
+                assign_targets(&positions, &targets),
+                brute_force(&positions, &targets),
+                "{} positions",
+                count
+            );
+        }
+    }
+
+    #[test]
+    fn test_mask_from_png() {
+        // A 3x2 grayscale image with a bright top row.
+        let mut bytes = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, 3, 2);
+            encoder.set_color(png::ColorType::Grayscale);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[255, 255, 0, 0, 0, 0]).unwrap();
+        }
+
+        let mask = Mask::from_png(&bytes, false).unwrap();
+        assert_eq!((mask.width, mask.height), (3, 2));
+        assert_eq!(mask.filled, vec![(0, 1), (1, 1)]);
+
+        let inverted = Mask::from_png(&bytes, true).unwrap();
+        assert_eq!(inverted.filled, vec![(2, 1), (0, 0), (1, 0), (2, 0)]);
+    }
+}
@@ -0,0 +1,995 @@
+//! The CPU-side simulation of the moving points, independent of rendering.
+
+use crate::{
+    flow::WindowSize,
+    grid::{Grid, Positioned},
+    neonet::{
+        attractor::Attractor,
+        config::{
+            BoundaryMode, FlockingConfig, FlowFieldConfig, MotionMode, NeonetConfig, ShapeConfig,
+        },
+        control::ControlCommand,
+        lifecycle::{random_point, Lifecycle},
+        shape::{assign_targets, Mask, Shape, ShapeEvent},
+        LINE_LENGTH, POINT_COUNT,
+    },
+    noise::Perlin,
+    spatial::{AnySpatialIndex, KdTree, SpatialIndex, SpatialIndexKind},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{f32::consts::PI, time::Duration};
+
+#[cfg(feature = "timer")]
+use crate::timer::Timer;
+
+pub struct Simulation {
+    size: WindowSize,
+    motion: MotionMode,
+    boundary: BoundaryMode,
+    flow_field: FlowFieldConfig,
+    flocking: FlockingConfig,
+    noise: Perlin,
+    rng: StdRng,
+    time: f32,
+    speed: f32,
+    line_length: f32,
+    points: AnySpatialIndex<Point>,
+    point_count: usize,
+    max_points: usize,
+    points_per_megapixel: Option<f32>,
+    max_connections: Option<usize>,
+    /// The indices of each point's nearest points, `max_connections` per
+    /// point, padded with `usize::MAX` when there are fewer nearby.
+    nearest: Vec<usize>,
+    accelerations: Vec<(f32, f32)>,
+    attractors: Vec<Attractor>,
+    lifecycle: Option<Lifecycle>,
+    shape: Option<Shape>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Point {
+    pub index: usize,
+    pub x: f32,
+    pub y: f32,
+    pub vx: f32,
+    pub vy: f32,
+    /// How long the point has been alive, in seconds.
+    pub age: f32,
+    /// How long the point will live, in seconds.
+    pub lifetime: f32,
+    /// How faded in the point is, from 0 to 1.
+    pub life: f32,
+    /// Where the point is being pulled to while gathering into a shape.
+    pub target: Option<(f32, f32)>,
+}
+
+impl Default for Point {
+    fn default() -> Self {
+        Point {
+            index: 0,
+            x: 0.0,
+            y: 0.0,
+            vx: 0.0,
+            vy: 0.0,
+            age: 0.0,
+            lifetime: f32::INFINITY,
+            life: 1.0,
+            target: None,
+        }
+    }
+}
+
+impl Positioned for Point {
+    fn x(&self) -> f32 {
+        self.x
+    }
+
+    fn y(&self) -> f32 {
+        self.y
+    }
+
+    fn x_mut(&mut self) -> &mut f32 {
+        &mut self.x
+    }
+
+    fn y_mut(&mut self) -> &mut f32 {
+        &mut self.y
+    }
+}
+
+impl Simulation {
+    /// Creates a simulation whose initial points and noise field are derived
+    /// entirely from `seed`.
+    ///
+    /// There are never more than `max_points` points, even when the point
+    /// density asks for more.
+    pub fn new(
+        config: &NeonetConfig,
+        size: WindowSize,
+        seed: u64,
+        max_points: usize,
+    ) -> Simulation {
+        let boundary = config.boundary;
+        let point_count = target_point_count(config.points_per_megapixel, size, max_points);
+        let torus = boundary == BoundaryMode::Torus;
+        let mut points = match config.spatial_index {
+            SpatialIndexKind::Grid if torus => {
+                AnySpatialIndex::Grid(Grid::new_torus(LINE_LENGTH, size.width, size.height))
+            },
+            SpatialIndexKind::Grid => AnySpatialIndex::Grid(Grid::new(
+                LINE_LENGTH,
+                LINE_LENGTH,
+                size.width + LINE_LENGTH * 2.0,
+                size.height + LINE_LENGTH * 2.0,
+            )),
+            SpatialIndexKind::KdTree if torus => {
+                AnySpatialIndex::KdTree(KdTree::new_torus(LINE_LENGTH, size.width, size.height))
+            },
+            SpatialIndexKind::KdTree => AnySpatialIndex::KdTree(KdTree::new(LINE_LENGTH)),
+        };
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut lifecycle = if config.lifecycle.enabled {
+            Some(Lifecycle::new(config.lifecycle.clone()))
+        } else {
+            None
+        };
+        let mut initial = Vec::with_capacity(point_count);
+        for i in 0..point_count {
+            match lifecycle.as_mut() {
+                Some(lifecycle) if !lifecycle.starts_populated() => lifecycle.free(i),
+                lifecycle => {
+                    let mut point = random_point(i, &mut rng, size);
+                    keep_in_bounds(&mut point, boundary, size);
+                    if let Some(lifecycle) = lifecycle {
+                        lifecycle.birth(&mut point, &mut rng, true);
+                    }
+                    initial.push(point);
+                },
+            }
+        }
+        points.extend(initial);
+
+        Simulation {
+            size,
+            motion: config.motion.mode,
+            boundary,
+            flow_field: config.motion.flow_field.clone(),
+            flocking: config.motion.flocking.clone(),
+            noise: Perlin::new(seed),
+            rng,
+            time: 0.0,
+            speed: 1.0,
+            line_length: LINE_LENGTH,
+            points,
+            point_count,
+            max_points,
+            points_per_megapixel: config.points_per_megapixel,
+            max_connections: config.max_connections,
+            nearest: vec![],
+            accelerations: vec![(0.0, 0.0); point_count],
+            attractors: config
+                .attractors
+                .iter()
+                .cloned()
+                .map(Attractor::new)
+                .collect(),
+            lifecycle,
+            shape: if config.shape.enabled {
+                shape_mask(&config.shape).map(|mask| Shape::new(config.shape.clone(), mask))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// How long the simulation has been running, in seconds.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Scales how far points move each step, without changing how fast time
+    /// passes.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// Limits how far apart connected points may be. This can't be longer
+    /// than `LINE_LENGTH`.
+    pub fn set_line_length(&mut self, line_length: f32) {
+        self.line_length = line_length.min(LINE_LENGTH);
+    }
+
+    pub fn size(&self) -> WindowSize {
+        self.size
+    }
+
+    /// How far apart connected points may be.
+    pub fn line_length(&self) -> f32 {
+        self.line_length
+    }
+
+    /// The number of points, whose indices are all below this.
+    pub fn point_count(&self) -> usize {
+        self.point_count
+    }
+
+    pub fn resize(&mut self, size: WindowSize) {
+        let old_size = self.size;
+        self.size = size;
+
+        if self.points_per_megapixel.is_some() {
+            // Drop the points in the area that was lost, rather than folding
+            // them into what is left.
+            let boundary = self.boundary;
+            self.points
+                .retain(|point| in_bounds(point.x, point.y, boundary, size));
+        }
+
+        if self.boundary == BoundaryMode::Torus {
+            self.points.set_size(size.width, size.height);
+        } else {
+            self.points.set_size(
+                size.width + LINE_LENGTH * 2.0,
+                size.height + LINE_LENGTH * 2.0,
+            );
+        }
+
+        if self.points_per_megapixel.is_some() {
+            self.repopulate(old_size);
+        }
+    }
+
+    /// Adds or removes points until there are as many as the point density
+    /// asks for, adding new points in the area outside `old_size` where
+    /// possible. Points are renumbered so that their indices stay below the
+    /// point count.
+    fn repopulate(&mut self, old_size: WindowSize) {
+        let target = target_point_count(self.points_per_megapixel, self.size, self.max_points);
+
+        let mut points: Vec<Point> = self.points.iter().copied().collect();
+        self.points.clear();
+
+        while points.len() > target {
+            let index = self.rng.gen_range(0..points.len());
+            points.swap_remove(index);
+        }
+
+        // Keep the surviving points in the same order
+        points.sort_unstable_by_key(|point| point.index);
+        for (index, point) in points.iter_mut().enumerate() {
+            point.index = index;
+        }
+
+        let living = points.len();
+        let emitting =
+            matches!(self.lifecycle.as_ref(), Some(lifecycle) if !lifecycle.starts_populated());
+        if let Some(lifecycle) = self.lifecycle.as_mut() {
+            // New points come out of the emitters in their own time, otherwise
+            // every index is filled right away.
+            let free = if emitting { living } else { target };
+            lifecycle.set_free(free..target);
+        }
+
+        if !emitting {
+            for index in living..target {
+                let mut point = random_point(index, &mut self.rng, self.size);
+                // Prefer the newly uncovered area, giving up eventually in case
+                // there is hardly any.
+                for _ in 0..16 {
+                    if !in_bounds(point.x, point.y, self.boundary, old_size) {
+                        break;
+                    }
+                    point = random_point(index, &mut self.rng, self.size);
+                }
+                keep_in_bounds(&mut point, self.boundary, self.size);
+                if let Some(lifecycle) = self.lifecycle.as_ref() {
+                    lifecycle.birth(&mut point, &mut self.rng, true);
+                }
+                points.push(point);
+            }
+        }
+
+        self.points.extend(points);
+        self.point_count = target;
+        self.accelerations.resize(target, (0.0, 0.0));
+    }
+
+    /// Applies a command sent from outside the app.
+    pub fn handle(&mut self, command: ControlCommand) {
+        match command {
+            ControlCommand::MoveAttractor { index, x, y } => match self.attractors.get_mut(index) {
+                Some(attractor) => attractor.move_to(x, y),
+                None => warn!("No attractor {} to move", index),
+            },
+            ControlCommand::SetAttractorStrength { index, strength } => {
+                match self.attractors.get_mut(index) {
+                    Some(attractor) => attractor.set_strength(strength),
+                    None => warn!("No attractor {} to change", index),
+                }
+            },
+            // This is for the app rather than the simulation.
+            ControlCommand::ToggleOverlay => {},
+        }
+    }
+
+    /// Advances the simulation by `delta`, calling `f` with each point after
+    /// it has moved.
+    pub fn step<F: FnMut(&Point)>(&mut self, delta: Duration, mut f: F) {
+        #[cfg(feature = "timer")]
+        let _timer = Timer::from_str("Simulation::step");
+
+        let delta = delta.as_secs_f32();
+        self.time += delta;
+
+        for attractor in self.attractors.iter_mut() {
+            attractor.update(self.time);
+        }
+
+        if let Some(event) = self.shape.as_mut().and_then(|shape| shape.advance(delta)) {
+            self.reshape(event);
+        }
+
+        if self.motion == MotionMode::Flocking {
+            flocking_accelerations(&self.points, &self.flocking, &mut self.accelerations);
+        }
+
+        let size = self.size;
+        let motion = self.motion;
+        let boundary = self.boundary;
+        let speed = self.speed;
+        let rng = &mut self.rng;
+        let flow_field = &self.flow_field;
+        let flocking = &self.flocking;
+        let accelerations = &self.accelerations;
+        let attractors = &self.attractors;
+        let lifecycle = &self.lifecycle;
+        let shape = &self.shape;
+        let noise = &self.noise;
+        let time = self.time;
+
+        self.points.all_mut(|point| {
+            if let Some(lifecycle) = lifecycle {
+                lifecycle.age(point, delta);
+            }
+
+            match motion {
+                MotionMode::Linear => {},
+                MotionMode::FlowField => steer_by_flow_field(point, noise, flow_field, time, delta),
+                MotionMode::Flocking => {
+                    let (ax, ay) = accelerations[point.index];
+                    point.vx += ax * delta;
+                    point.vy += ay * delta;
+                },
+            }
+
+            for attractor in attractors.iter() {
+                attractor.apply(point, delta);
+            }
+
+            if let Some(shape) = shape {
+                shape.apply(point, delta);
+            }
+
+            // Points settling into a shape have to be allowed to stop.
+            if motion == MotionMode::Flocking && point.target.is_none() {
+                clamp_speed(point, flocking.min_speed, flocking.max_speed);
+            }
+
+            point.x += point.vx * delta * speed;
+            point.y += point.vy * delta * speed;
+
+            if !keep_in_bounds(point, boundary, size) {
+                *point = edge_point(point.index, rng, size);
+                if let Some(lifecycle) = lifecycle {
+                    lifecycle.birth(point, rng, false);
+                }
+            }
+
+            f(point);
+        });
+
+        if let Some(lifecycle) = self.lifecycle.as_mut() {
+            self.points.retain(|point| {
+                if lifecycle.is_dead(point) {
+                    lifecycle.free(point.index);
+                    false
+                } else {
+                    true
+                }
+            });
+
+            let mut spawned = vec![];
+            lifecycle.spawn(delta, &mut self.rng, size, |mut point| {
+                keep_in_bounds(&mut point, boundary, size);
+                f(&point);
+                spawned.push(point);
+            });
+            self.points.extend(spawned);
+        }
+    }
+
+    /// Gives every point a place in the shape, or lets them all go again.
+    fn reshape(&mut self, event: ShapeEvent) {
+        match event {
+            ShapeEvent::Assemble => {
+                let shape = self.shape.as_ref().unwrap();
+                let (indices, positions): (Vec<usize>, Vec<(f32, f32)>) = self
+                    .points
+                    .iter()
+                    .map(|point| (point.index, (point.x, point.y)))
+                    .unzip();
+
+                let targets =
+                    shape
+                        .mask()
+                        .targets(positions.len(), self.size, shape.scale(), &mut self.rng);
+                if targets.is_empty() {
+                    return;
+                }
+
+                let mut point_targets = vec![None; self.point_count];
+                for (index, target) in indices
+                    .into_iter()
+                    .zip(assign_targets(&positions, &targets))
+                {
+                    point_targets[index] = Some(targets[target]);
+                }
+                for point in self.points.iter_mut() {
+                    point.target = point_targets[point.index];
+                }
+            },
+            ShapeEvent::Dissolve => {
+                for point in self.points.iter_mut() {
+                    if point.target.take().is_some() {
+                        // Points have all but stopped in the shape, so send
+                        // them off again.
+                        let angle = self.rng.gen_range(0.0..(PI * 2.0));
+                        let speed = self.rng.gen_range(20.0..100.0f32);
+                        point.vx += angle.cos() * speed;
+                        point.vy += angle.sin() * speed;
+                    }
+                }
+            },
+        }
+    }
+
+    /// Iterates over every point, in no particular order.
+    pub fn points(&self) -> impl Iterator<Item = &Point> + '_ {
+        self.points.iter()
+    }
+
+    /// Gets the grid the points are indexed in, unless another spatial index
+    /// is used.
+    pub fn grid(&self) -> Option<&Grid<Point>> {
+        match &self.points {
+            AnySpatialIndex::Grid(grid) => Some(grid),
+            AnySpatialIndex::KdTree(_) => None,
+        }
+    }
+
+    /// Calls `f` for every pair of points closer than the line length, along
+    /// with how far the second point has to be moved to be drawn next to the
+    /// first. That offset is only non-zero for pairs connected across the seam
+    /// of a torus.
+    ///
+    /// With `max_connections` set, only pairs of points that are each among the
+    /// other's nearest points are found.
+    pub fn pairs<F: FnMut(&Point, &Point, f32, (f32, f32))>(&mut self, mut f: F) {
+        if let Some(max_connections) = self.max_connections {
+            self.find_nearest(max_connections);
+        }
+
+        let max_distance_sqr = self.line_length * self.line_length;
+        let nearest = self.max_connections.map(|max| (max, &self.nearest));
+        let is_near = |point: &Point, other: &Point| match nearest {
+            Some((max, nearest)) => nearest[point.index * max..][..max].contains(&other.index),
+            None => true,
+        };
+        let pair = |point: &Point, other: &Point, distance_sqr: f32, offset: (f32, f32)| {
+            if distance_sqr >= max_distance_sqr || !(is_near(point, other) && is_near(other, point))
+            {
+                return;
+            }
+            f(point, other, distance_sqr, offset);
+        };
+
+        self.points.par_pairs(pair);
+    }
+
+    /// Finds the `max_connections` nearest points within the line length of
+    /// every point, for `pairs` to pick from.
+    fn find_nearest(&mut self, max_connections: usize) {
+        #[cfg(feature = "timer")]
+        let _timer = Timer::from_str("Simulation::find_nearest");
+
+        self.nearest.clear();
+        self.nearest
+            .resize(self.point_count * max_connections, usize::MAX);
+        // Every search reuses the same buffer for the points it finds.
+        let mut nearest = vec![];
+        for point in self.points.iter() {
+            // The point itself is the nearest one, so one more is needed.
+            self.points.k_nearest(
+                point.x,
+                point.y,
+                max_connections + 1,
+                self.line_length,
+                &mut nearest,
+            );
+            let start = point.index * max_connections;
+            let others = nearest
+                .iter()
+                .filter(|(other, _)| other.index != point.index);
+            for (slot, (other, _)) in self.nearest[start..start + max_connections]
+                .iter_mut()
+                .zip(others)
+            {
+                *slot = other.index;
+            }
+        }
+    }
+}
+
+/// Creates the mask for the shape from its image or text.
+fn shape_mask(config: &ShapeConfig) -> Option<Mask> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = config.image.as_ref() {
+        match Mask::load(path, config.invert) {
+            Ok(mask) => return Some(mask),
+            Err(err) => error!("Error loading shape image {:?}: {:?}", path, err),
+        }
+    }
+
+    match config.text.as_ref() {
+        Some(text) => Some(Mask::from_text(text)),
+        None => {
+            warn!("The shape has no text or image to gather into");
+            None
+        },
+    }
+}
+
+/// Gets how many points there should be on a screen of the given size.
+fn target_point_count(
+    points_per_megapixel: Option<f32>,
+    size: WindowSize,
+    max_points: usize,
+) -> usize {
+    match points_per_megapixel {
+        Some(density) => {
+            ((size.width * size.height / 1_000_000.0 * density).round() as usize).min(max_points)
+        },
+        None => POINT_COUNT.min(max_points),
+    }
+}
+
+/// Whether a position is within the area points are kept in by the boundary
+/// mode.
+fn in_bounds(x: f32, y: f32, boundary: BoundaryMode, size: WindowSize) -> bool {
+    let border = match boundary {
+        BoundaryMode::Wrap | BoundaryMode::Respawn => LINE_LENGTH,
+        BoundaryMode::Bounce | BoundaryMode::Torus => 0.0,
+    };
+    x >= -border && x <= size.width + border && y >= -border && y <= size.height + border
+}
+
+/// Applies the boundary mode to a point that may have left the screen,
+/// returning false when the point should be replaced instead.
+fn keep_in_bounds(point: &mut Point, boundary: BoundaryMode, size: WindowSize) -> bool {
+    match boundary {
+        BoundaryMode::Wrap => {
+            if point.x < -LINE_LENGTH {
+                point.x += size.width + LINE_LENGTH * 2.0;
+            } else if point.x > size.width + LINE_LENGTH {
+                point.x -= size.width + LINE_LENGTH * 2.0;
+            }
+
+            if point.y < -LINE_LENGTH {
+                point.y += size.height + LINE_LENGTH * 2.0;
+            } else if point.y > size.height + LINE_LENGTH {
+                point.y -= size.height + LINE_LENGTH * 2.0;
+            }
+        },
+        BoundaryMode::Bounce => {
+            if point.x < 0.0 {
+                point.x = (-point.x).min(size.width);
+                point.vx = point.vx.abs();
+            } else if point.x > size.width {
+                point.x = (size.width * 2.0 - point.x).max(0.0);
+                point.vx = -point.vx.abs();
+            }
+
+            if point.y < 0.0 {
+                point.y = (-point.y).min(size.height);
+                point.vy = point.vy.abs();
+            } else if point.y > size.height {
+                point.y = (size.height * 2.0 - point.y).max(0.0);
+                point.vy = -point.vy.abs();
+            }
+        },
+        BoundaryMode::Torus => {
+            point.x = point.x.rem_euclid(size.width);
+            point.y = point.y.rem_euclid(size.height);
+        },
+        BoundaryMode::Respawn => return in_bounds(point.x, point.y, boundary, size),
+    }
+
+    true
+}
+
+/// Creates a point on a random edge of the border around the screen, heading
+/// inwards.
+fn edge_point(index: usize, rng: &mut StdRng, size: WindowSize) -> Point {
+    let x = rng.gen_range(-LINE_LENGTH..size.width + LINE_LENGTH);
+    let y = rng.gen_range(-LINE_LENGTH..size.height + LINE_LENGTH);
+    // The direction pointing into the screen from each edge.
+    let ((x, y), inwards) = match rng.gen_range(0..4) {
+        0 => ((-LINE_LENGTH, y), 0.0),
+        1 => ((x, -LINE_LENGTH), PI * 0.5),
+        2 => ((size.width + LINE_LENGTH, y), PI),
+        _ => ((x, size.height + LINE_LENGTH), PI * 1.5),
+    };
+    let angle = inwards + rng.gen_range(-0.4..0.4) * PI;
+    let speed = rng.gen_range(20.0..100.0f32);
+    Point {
+        index,
+        x,
+        y,
+        vx: angle.cos() * speed,
+        vy: angle.sin() * speed,
+        ..Point::default()
+    }
+}
+
+/// Turns a point's velocity towards the direction of the noise field at its
+/// position, keeping its speed.
+fn steer_by_flow_field(
+    point: &mut Point,
+    noise: &Perlin,
+    config: &FlowFieldConfig,
+    time: f32,
+    delta: f32,
+) {
+    let speed = (point.vx * point.vx + point.vy * point.vy).sqrt();
+    // Perlin noise rarely strays far from 0, so stretch it to cover every angle.
+    let angle = noise.get(
+        point.x * config.scale,
+        point.y * config.scale,
+        time * config.time_speed,
+    ) * PI
+        * 2.0;
+
+    let steer = (config.strength * delta).min(1.0);
+    point.vx += (angle.cos() * speed - point.vx) * steer;
+    point.vy += (angle.sin() * speed - point.vy) * steer;
+
+    // Steering towards a rotated velocity shortens it, so restore the speed.
+    let new_speed = (point.vx * point.vx + point.vy * point.vy).sqrt();
+    if new_speed > 0.0 {
+        point.vx *= speed / new_speed;
+        point.vy *= speed / new_speed;
+    }
+}
+
+/// Computes the separation, alignment and cohesion acceleration of every
+/// point, indexed by the point's index.
+fn flocking_accelerations(
+    points: &impl SpatialIndex<Point>,
+    config: &FlockingConfig,
+    accelerations: &mut [(f32, f32)],
+) {
+    #[cfg(feature = "timer")]
+    let _timer = Timer::from_str("Simulation::flocking_accelerations");
+
+    for point in points.iter() {
+        let mut separation = (0.0, 0.0);
+        let mut velocity_sum = (0.0, 0.0);
+        let mut position_sum = (0.0, 0.0);
+        let mut neighbors = 0;
+
+        let others = points
+            .within(point.x, point.y, config.perception_radius)
+            .filter(|(other, _)| other.index != point.index);
+        for (other, distance_sqr) in others {
+            let distance = distance_sqr.sqrt();
+            if distance < config.separation_radius && distance > 0.0 {
+                // Push away harder the closer the neighbor is.
+                let push = (1.0 - distance / config.separation_radius) / distance;
+                separation.0 += (point.x - other.x) * push;
+                separation.1 += (point.y - other.y) * push;
+            }
+
+            velocity_sum.0 += other.vx;
+            velocity_sum.1 += other.vy;
+            position_sum.0 += other.x;
+            position_sum.1 += other.y;
+            neighbors += 1;
+        }
+
+        let mut acceleration = (
+            separation.0 * config.max_speed * config.separation_weight,
+            separation.1 * config.max_speed * config.separation_weight,
+        );
+        if neighbors > 0 {
+            let neighbors = neighbors as f32;
+            acceleration.0 += (velocity_sum.0 / neighbors - point.vx) * config.alignment_weight;
+            acceleration.1 += (velocity_sum.1 / neighbors - point.vy) * config.alignment_weight;
+            acceleration.0 += (position_sum.0 / neighbors - point.x) * config.cohesion_weight;
+            acceleration.1 += (position_sum.1 / neighbors - point.y) * config.cohesion_weight;
+        }
+
+        accelerations[point.index] = acceleration;
+    }
+}
+
+/// Keeps a point's speed between `min_speed` and `max_speed`, keeping its
+/// direction. Unlike `f32::clamp`, this doesn't panic on bounds from a config
+/// that are backwards or NaN: `max_speed` wins, and NaN bounds are ignored.
+fn clamp_speed(point: &mut Point, min_speed: f32, max_speed: f32) {
+    let speed = (point.vx * point.vx + point.vy * point.vy).sqrt();
+    if speed > 0.0 {
+        let clamped = speed.max(min_speed).min(max_speed);
+        point.vx *= clamped / speed;
+        point.vy *= clamped / speed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flow::WindowSize,
+        neonet::{
+            config::{BoundaryMode, MotionMode, NeonetConfig},
+            simulation::{clamp_speed, Point, Simulation},
+            LINE_LENGTH, POINT_COUNT,
+        },
+        spatial::{SpatialIndex, SpatialIndexKind},
+    };
+    use std::time::Duration;
+
+    fn positions(config: &NeonetConfig, seed: u64) -> Vec<(f32, f32)> {
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        let mut simulation = Simulation::new(config, size, seed, POINT_COUNT);
+        let mut positions = vec![(0.0, 0.0); POINT_COUNT];
+        for _ in 0..120 {
+            simulation.step(Duration::from_millis(16), |point| {
+                positions[point.index] = (point.x, point.y)
+            });
+        }
+        positions
+    }
+
+    #[test]
+    fn test_flow_field_deterministic() {
+        let mut config = NeonetConfig::default();
+        config.motion.mode = MotionMode::FlowField;
+
+        let flow_field = positions(&config, 30);
+        assert_eq!(flow_field, positions(&config, 30));
+        assert_ne!(flow_field, positions(&config, 31));
+
+        config.motion.mode = MotionMode::Linear;
+        assert_ne!(flow_field, positions(&config, 30));
+    }
+
+    #[test]
+    fn test_lifecycle_recycles_indices() {
+        let mut config = NeonetConfig::default();
+        config.lifecycle.enabled = true;
+        config.lifecycle.min_lifetime = 0.5;
+        config.lifecycle.max_lifetime = 1.0;
+
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        let mut simulation = Simulation::new(&config, size, 33, POINT_COUNT);
+        let mut spawned = 0;
+        for _ in 0..120 {
+            let mut seen = [false; POINT_COUNT];
+            simulation.step(Duration::from_millis(16), |point| {
+                if point.age == 0.0 {
+                    spawned += 1;
+                }
+                seen[point.index] = true;
+            });
+            assert!(seen.iter().all(|&seen| seen));
+
+            assert!(simulation.points().all(|point| point.age < point.lifetime));
+            assert_eq!(simulation.points.iter().count(), POINT_COUNT);
+        }
+
+        // Every point lives at most a second, so they have all been replaced.
+        assert!(spawned >= POINT_COUNT);
+    }
+
+    #[test]
+    fn test_spatial_indexes_find_same_pairs() {
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        for boundary in [BoundaryMode::Bounce, BoundaryMode::Torus] {
+            let mut pairs = vec![];
+            for spatial_index in [SpatialIndexKind::Grid, SpatialIndexKind::KdTree] {
+                let config = NeonetConfig {
+                    boundary,
+                    spatial_index,
+                    ..Default::default()
+                };
+                let mut simulation = Simulation::new(&config, size, 45, POINT_COUNT);
+                for _ in 0..60 {
+                    simulation.step(Duration::from_millis(16), |_| {});
+                }
+
+                let mut found = vec![];
+                simulation.pairs(|point, other, _, (offset_x, offset_y)| {
+                    // The offset is from the first point to the second, so it
+                    // flips when they are found the other way around.
+                    if point.index < other.index {
+                        found.push((point.index, other.index, (offset_x, offset_y)));
+                    } else {
+                        found.push((other.index, point.index, (-offset_x, -offset_y)));
+                    }
+                });
+                found.sort_by_key(|&(a, b, _)| (a, b));
+                pairs.push(found);
+            }
+            assert!(!pairs[0].is_empty());
+            assert_eq!(pairs[0], pairs[1], "{:?}", boundary);
+        }
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        for boundary in [BoundaryMode::Bounce, BoundaryMode::Torus] {
+            let pairs = |max_connections| {
+                let config = NeonetConfig {
+                    boundary,
+                    max_connections,
+                    points_per_megapixel: Some(2000.0),
+                    ..Default::default()
+                };
+                let mut simulation = Simulation::new(&config, size, 47, 2000);
+                simulation.step(Duration::ZERO, |_| {});
+                let mut found = vec![];
+                simulation.pairs(|point, other, _, _| {
+                    found.push((point.index.min(other.index), point.index.max(other.index)))
+                });
+                found.sort_unstable();
+                found
+            };
+
+            let all = pairs(None);
+            let limited = pairs(Some(3));
+            assert!(!limited.is_empty());
+            assert!(limited.len() < all.len());
+            assert!(limited.iter().all(|pair| all.binary_search(pair).is_ok()));
+
+            let mut connections = vec![0; 2000];
+            for &(a, b) in limited.iter() {
+                connections[a] += 1;
+                connections[b] += 1;
+            }
+            assert!(
+                connections.iter().all(|&count| count <= 3),
+                "{:?}",
+                boundary
+            );
+        }
+    }
+
+    #[test]
+    fn test_boundary_modes_keep_points_on_screen() {
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        for (boundary, min, max) in [
+            (BoundaryMode::Bounce, (0.0, 0.0), (size.width, size.height)),
+            (BoundaryMode::Torus, (0.0, 0.0), (size.width, size.height)),
+            (
+                BoundaryMode::Respawn,
+                (-LINE_LENGTH, -LINE_LENGTH),
+                (size.width + LINE_LENGTH, size.height + LINE_LENGTH),
+            ),
+        ] {
+            let config = NeonetConfig { boundary, ..Default::default() };
+            let mut simulation = Simulation::new(&config, size, 34, POINT_COUNT);
+            for _ in 0..600 {
+                simulation.step(Duration::from_millis(16), |point| {
+                    assert!(point.x >= min.0 && point.x <= max.0, "{:?}", boundary);
+                    assert!(point.y >= min.1 && point.y <= max.1, "{:?}", boundary);
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn test_density_kept_across_resizes() {
+        let config = NeonetConfig {
+            points_per_megapixel: Some(100.0),
+            ..Default::default()
+        };
+        let size = WindowSize { width: 1000.0, height: 1000.0 };
+        let mut simulation = Simulation::new(&config, size, 35, 1000);
+        assert_eq!(simulation.point_count(), 100);
+
+        for (width, height, expected) in [
+            (2000.0, 1500.0, 300),
+            (500.0, 400.0, 20),
+            (4000.0, 4000.0, 1000),
+        ] {
+            let size = WindowSize { width, height };
+            simulation.resize(size);
+            assert_eq!(simulation.point_count(), expected);
+
+            let mut seen = vec![false; expected];
+            simulation.step(Duration::from_millis(16), |point| {
+                assert!(!seen[point.index]);
+                seen[point.index] = true;
+            });
+            assert!(seen.iter().all(|&seen| seen));
+        }
+    }
+
+    #[test]
+    fn test_shape_assembles_and_dissolves() {
+        let mut config = NeonetConfig::default();
+        config.shape.enabled = true;
+        config.shape.text = Some("HI".to_string());
+        config.shape.drift_time = 1.0;
+        config.shape.hold_time = 8.0;
+        config.shape.repeat = false;
+
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        let mut simulation = Simulation::new(&config, size, 37, POINT_COUNT);
+
+        // Runs the simulation for `duration` in fixed frames, returning how far
+        // the point furthest from its target is at the end.
+        let mut run = |duration: Duration| {
+            const FRAME: Duration = Duration::from_millis(16);
+            let mut distance = 0.0f32;
+            for _ in 0..duration.as_millis() / FRAME.as_millis() {
+                distance = 0.0;
+                simulation.step(FRAME, |point| {
+                    if let Some((x, y)) = point.target {
+                        distance =
+                            distance.max(((x - point.x).powi(2) + (y - point.y).powi(2)).sqrt());
+                    }
+                });
+            }
+            distance
+        };
+
+        // Drifting freely, then gathering and settling into the shape, then
+        // drifting again once the hold time is up.
+        assert_eq!(run(Duration::from_millis(496)), 0.0);
+        assert!(run(Duration::from_millis(640)) > 50.0);
+        let settled = run(Duration::from_millis(7_520));
+        assert!(settled < 1.0 && settled > 0.0);
+        run(Duration::from_millis(320));
+        assert_eq!(run(Duration::from_millis(480)), 0.0);
+    }
+
+    #[test]
+    fn test_flocking_speed_clamped() {
+        let mut config = NeonetConfig::default();
+        config.motion.mode = MotionMode::Flocking;
+        let flocking = config.motion.flocking.clone();
+
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        let mut simulation = Simulation::new(&config, size, 31, POINT_COUNT);
+        for _ in 0..120 {
+            simulation.step(Duration::from_millis(16), |point| {
+                let speed = (point.vx * point.vx + point.vy * point.vy).sqrt();
+                assert!(speed >= flocking.min_speed - 0.01);
+                assert!(speed <= flocking.max_speed + 0.01);
+            });
+        }
+    }
+
+    #[test]
+    fn test_clamp_speed_with_bad_bounds() {
+        let mut point = Point { vx: 3.0, vy: 4.0, ..Default::default() };
+        clamp_speed(&mut point, 20.0, 10.0);
+        assert!((point.vx - 6.0).abs() < 1e-4 && (point.vy - 8.0).abs() < 1e-4);
+
+        clamp_speed(&mut point, f32::NAN, f32::NAN);
+        assert!((point.vx - 6.0).abs() < 1e-4 && (point.vy - 8.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,140 @@
+//! Exports the current frame of the network as an SVG image, without needing a
+//! GPU.
+
+use crate::{
+    neonet::{simulation::Simulation, BACKGROUND_COLOR, LINE_COLOR},
+    util::linear_to_srgb,
+};
+use std::io::{self, Write};
+
+/// Writes the simulation's current frame as an SVG image the size of the
+/// screen. Each connection is a `<line>` that fades with distance like it does
+/// on screen, drawn over a rectangle of the background color.
+pub fn write_svg<W: Write>(simulation: &mut Simulation, mut out: W) -> io::Result<()> {
+    let size = simulation.size();
+    let line_length = simulation.line_length();
+
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = size.width,
+        h = size.height
+    )?;
+    writeln!(
+        out,
+        r#"<rect width="{}" height="{}" fill="{}"/>"#,
+        size.width,
+        size.height,
+        css_color(BACKGROUND_COLOR.r, BACKGROUND_COLOR.g, BACKGROUND_COLOR.b)
+    )?;
+    writeln!(
+        out,
+        r#"<g stroke="{}" stroke-width="1" stroke-linecap="round">"#,
+        css_color(LINE_COLOR.r, LINE_COLOR.g, LINE_COLOR.b)
+    )?;
+
+    let mut result = Ok(());
+    simulation.pairs(|point, other, distance_sqr, (offset_x, offset_y)| {
+        if result.is_err() {
+            return;
+        }
+
+        let opacity = (1.0 - distance_sqr.sqrt() / line_length) * point.life * other.life;
+        let mut line = |x1: f32, y1: f32, x2: f32, y2: f32| {
+            // SVG's origin is at the top-left, rather than the bottom-left.
+            writeln!(
+                out,
+                r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" stroke-opacity="{:.3}"/>"#,
+                x1,
+                size.height - y1,
+                x2,
+                size.height - y2,
+                opacity
+            )
+        };
+
+        result = line(point.x, point.y, other.x + offset_x, other.y + offset_y);
+        if result.is_ok() && (offset_x != 0.0 || offset_y != 0.0) {
+            // Draw the line again from the other side of the seam
+            result = line(point.x - offset_x, point.y - offset_y, other.x, other.y);
+        }
+    });
+    result?;
+
+    writeln!(out, "</g>")?;
+    writeln!(out, "</svg>")
+}
+
+/// Converts a linear color, as used for rendering, to an sRGB CSS color.
+fn css_color(r: f64, g: f64, b: f64) -> String {
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        linear_to_srgb(r),
+        linear_to_srgb(g),
+        linear_to_srgb(b)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flow::WindowSize,
+        neonet::{
+            config::{BoundaryMode, NeonetConfig},
+            simulation::Simulation,
+            svg::{css_color, write_svg},
+            POINT_COUNT,
+        },
+    };
+    use std::time::Duration;
+
+    fn export(config: &NeonetConfig) -> (String, usize, usize) {
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        let mut simulation = Simulation::new(config, size, 38, POINT_COUNT);
+        simulation.step(Duration::ZERO, |_| {});
+
+        let mut pairs = 0;
+        let mut wrapped = 0;
+        simulation.pairs(|_, _, _, offset| {
+            pairs += 1;
+            if offset != (0.0, 0.0) {
+                wrapped += 1;
+            }
+        });
+
+        let mut out = vec![];
+        write_svg(&mut simulation, &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), pairs, wrapped)
+    }
+
+    #[test]
+    fn test_line_per_connection() {
+        let (svg, pairs, _) = export(&NeonetConfig::default());
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<rect ").count(), 1);
+        assert!(pairs > 0);
+        assert_eq!(svg.matches("<line ").count(), pairs);
+
+        for opacity in svg.split("stroke-opacity=\"").skip(1) {
+            let opacity: f32 = opacity[..opacity.find('"').unwrap()].parse().unwrap();
+            assert!((0.0..=1.0).contains(&opacity));
+        }
+    }
+
+    #[test]
+    fn test_torus_seam_drawn_twice() {
+        let config = NeonetConfig {
+            boundary: BoundaryMode::Torus,
+            ..Default::default()
+        };
+        let (svg, pairs, wrapped) = export(&config);
+        assert!(wrapped > 0);
+        assert_eq!(svg.matches("<line ").count(), pairs + wrapped);
+    }
+
+    #[test]
+    fn test_css_color() {
+        assert_eq!(css_color(0.0, 0.5, 1.0), "#00bcff");
+    }
+}
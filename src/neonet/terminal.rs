@@ -0,0 +1,240 @@
+//! Draws the network into a terminal with Unicode characters and truecolor
+//! escape codes, without needing a GPU.
+
+use crate::{
+    neonet::{simulation::Simulation, BACKGROUND_COLOR, LINE_COLOR},
+    util::linear_to_srgb,
+};
+use std::io::{self, Write};
+
+/// How bright a braille dot must be to be drawn.
+const DOT_THRESHOLD: f32 = 0.05;
+
+/// The characters used to draw pixels into the terminal.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TerminalStyle {
+    /// Braille characters, with 2x4 dots per character that all share a color.
+    Braille,
+    /// Half-block characters, with 1x2 pixels per character that each have
+    /// their own color.
+    HalfBlock,
+}
+
+impl TerminalStyle {
+    /// Gets how many pixels wide and tall each character is.
+    fn cell_size(self) -> (usize, usize) {
+        match self {
+            TerminalStyle::Braille => (2, 4),
+            TerminalStyle::HalfBlock => (1, 2),
+        }
+    }
+}
+
+/// A grid of pixels covering the terminal, which lines are drawn into before
+/// being written out as characters.
+pub struct TerminalCanvas {
+    style: TerminalStyle,
+    columns: usize,
+    rows: usize,
+    width: usize,
+    height: usize,
+    /// How much of the line color each pixel has, from the top-left.
+    intensity: Vec<f32>,
+}
+
+impl TerminalCanvas {
+    pub fn new(style: TerminalStyle, columns: usize, rows: usize) -> TerminalCanvas {
+        let (cell_width, cell_height) = style.cell_size();
+        let (width, height) = (columns * cell_width, rows * cell_height);
+        TerminalCanvas {
+            style,
+            columns,
+            rows,
+            width,
+            height,
+            intensity: vec![0.0; width * height],
+        }
+    }
+
+    /// Gets the width and height of the canvas in pixels.
+    pub fn pixel_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Clears the canvas and draws the simulation's current frame, stretched
+    /// to fill it.
+    pub fn draw(&mut self, simulation: &mut Simulation) {
+        self.intensity.fill(0.0);
+
+        let size = simulation.size();
+        let line_length = simulation.line_length();
+        let scale_x = self.width as f32 / size.width;
+        let scale_y = self.height as f32 / size.height;
+
+        simulation.pairs(|point, other, distance_sqr, (offset_x, offset_y)| {
+            let opacity = (1.0 - distance_sqr.sqrt() / line_length) * point.life * other.life;
+            let mut line = |x1: f32, y1: f32, x2: f32, y2: f32| {
+                // The terminal's origin is at the top-left, rather than the
+                // bottom-left.
+                self.line(
+                    (x1 * scale_x, (size.height - y1) * scale_y),
+                    (x2 * scale_x, (size.height - y2) * scale_y),
+                    opacity,
+                );
+            };
+
+            line(point.x, point.y, other.x + offset_x, other.y + offset_y);
+            if offset_x != 0.0 || offset_y != 0.0 {
+                // Draw the line again from the other side of the seam
+                line(point.x - offset_x, point.y - offset_y, other.x, other.y);
+            }
+        });
+    }
+
+    /// Adds a line between two positions in pixels, stepping one pixel at a
+    /// time along its longer axis.
+    fn line(&mut self, (x1, y1): (f32, f32), (x2, y2): (f32, f32), opacity: f32) {
+        let steps = (x2 - x1).abs().max((y2 - y1).abs()).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let x = (x1 + (x2 - x1) * t).floor();
+            let y = (y1 + (y2 - y1) * t).floor();
+            if x >= 0.0 && y >= 0.0 && (x as usize) < self.width && (y as usize) < self.height {
+                let pixel = &mut self.intensity[y as usize * self.width + x as usize];
+                *pixel = (*pixel + opacity).min(1.0);
+            }
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> f32 {
+        self.intensity[y * self.width + x]
+    }
+
+    /// Writes the canvas as rows of characters with truecolor escape codes,
+    /// leaving the cursor at the end of the last row.
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for row in 0..self.rows {
+            if row > 0 {
+                write!(out, "\r\n")?;
+            }
+            write!(out, "\x1b[48;2;{}m", color(0.0))?;
+
+            let mut last_color = None;
+            for column in 0..self.columns {
+                match self.style {
+                    TerminalStyle::Braille => {
+                        let (c, brightness) = self.braille_cell(column, row);
+                        if c != ' ' && last_color != Some(brightness) {
+                            write!(out, "\x1b[38;2;{}m", color(brightness))?;
+                            last_color = Some(brightness);
+                        }
+                        write!(out, "{}", c)?;
+                    },
+                    TerminalStyle::HalfBlock => {
+                        let top = self.pixel(column, row * 2);
+                        let bottom = self.pixel(column, row * 2 + 1);
+                        write!(
+                            out,
+                            "\x1b[38;2;{}m\x1b[48;2;{}m\u{2580}",
+                            color(top),
+                            color(bottom)
+                        )?;
+                    },
+                }
+            }
+            write!(out, "\x1b[0m")?;
+        }
+        out.flush()
+    }
+
+    /// Gets the braille character for a cell, and how bright its brightest
+    /// dot is.
+    fn braille_cell(&self, column: usize, row: usize) -> (char, f32) {
+        // The bit of each dot in the character, by its position in the cell.
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+        let mut bits = 0;
+        let mut brightness: f32 = 0.0;
+        for (dy, dot_row) in DOTS.iter().enumerate() {
+            for (dx, bit) in dot_row.iter().enumerate() {
+                let intensity = self.pixel(column * 2 + dx, row * 4 + dy);
+                if intensity >= DOT_THRESHOLD {
+                    bits |= bit;
+                    brightness = brightness.max(intensity);
+                }
+            }
+        }
+
+        if bits == 0 {
+            (' ', 0.0)
+        } else {
+            (char::from_u32(0x2800 + bits).unwrap(), brightness)
+        }
+    }
+}
+
+/// Gets the `r;g;b` escape code parameters for a pixel with `intensity` of the
+/// line color over the background.
+fn color(intensity: f32) -> String {
+    let mix = |background: f64, line: f64| {
+        linear_to_srgb(background + (line - background) * intensity as f64)
+    };
+    format!(
+        "{};{};{}",
+        mix(BACKGROUND_COLOR.r, LINE_COLOR.r),
+        mix(BACKGROUND_COLOR.g, LINE_COLOR.g),
+        mix(BACKGROUND_COLOR.b, LINE_COLOR.b)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flow::WindowSize,
+        neonet::{
+            config::NeonetConfig,
+            simulation::Simulation,
+            terminal::{TerminalCanvas, TerminalStyle},
+            POINT_COUNT,
+        },
+    };
+    use std::time::Duration;
+
+    fn write(canvas: &TerminalCanvas) -> String {
+        let mut out = vec![];
+        canvas.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_braille_line() {
+        let mut canvas = TerminalCanvas::new(TerminalStyle::Braille, 3, 2);
+        assert_eq!(canvas.pixel_size(), (6, 8));
+
+        // A full brightness line along the top row of dots.
+        canvas.line((0.0, 0.5), (5.5, 0.5), 1.0);
+        let text = write(&canvas);
+        let rows: Vec<&str> = text.split("\r\n").collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].matches('\u{2809}').count(), 3);
+        assert!(rows[0].contains("\x1b[38;2;0;170;203m"));
+        assert!(!rows[1].contains('\u{2809}'));
+    }
+
+    #[test]
+    fn test_draws_simulation() {
+        let size = WindowSize { width: 800.0, height: 600.0 };
+        let mut simulation = Simulation::new(&NeonetConfig::default(), size, 39, POINT_COUNT);
+        simulation.step(Duration::ZERO, |_| {});
+
+        for style in [TerminalStyle::Braille, TerminalStyle::HalfBlock] {
+            let mut canvas = TerminalCanvas::new(style, 80, 24);
+            canvas.draw(&mut simulation);
+            assert!(canvas.intensity.iter().any(|&intensity| intensity > 0.0));
+
+            let text = write(&canvas);
+            assert_eq!(text.split("\r\n").count(), 24);
+            assert!(text.ends_with("\x1b[0m"));
+        }
+    }
+}
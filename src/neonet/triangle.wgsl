@@ -0,0 +1,56 @@
+// Neonet triangle fill shader.
+
+let point_count: u32 = {{point_count}};
+
+struct VertexData {
+    position: vec2<f32>,
+    color: vec3<f32>,
+    life: f32,
+};
+
+struct Vertices {
+    vertices: array<VertexData, point_count>,
+};
+
+struct UniformData {
+    screen_width: f32,
+    screen_height: f32,
+    line_length: f32,
+    _padding2: u32,
+};
+
+struct TriangleVertex {
+    @location(0) me: u32,
+    @location(1) longest_sqr: f32,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@group(0)
+@binding(0)
+var<uniform> uniform_data: UniformData;
+
+@group(0)
+@binding(1)
+var<uniform> vertices: Vertices;
+
+@vertex
+fn vert_main(vertex: TriangleVertex) -> VertexOutput {
+    var output: VertexOutput;
+    var me = vertices.vertices[vertex.me];
+    var x = me.position.x / uniform_data.screen_width * 2.0 - 1.0;
+    var y = me.position.y / uniform_data.screen_height * 2.0 - 1.0;
+    output.position = vec4<f32>(x, y, 0.0, 1.0);
+    var longest = sqrt(vertex.longest_sqr);
+    output.color = vec4<f32>(vertex.color.rgb, vertex.color.a * (1.0 - longest / uniform_data.line_length) * me.life);
+    return output;
+}
+
+@fragment
+fn frag_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,268 @@
+//! Fills the triangles formed by mutually-connected triples of points.
+
+use crate::{
+    buffer::BufferWrapper,
+    neonet::{config::TriangleConfig, with_point_count},
+    util::least_power_of_2_greater,
+};
+use bytemuck::{Pod, Zeroable};
+use std::{borrow::Cow, mem::size_of};
+use wgpu::{
+    BlendState, BufferAddress, BufferUsages, ColorTargetState, ColorWrites, CommandBuffer, Device,
+    FragmentState, FrontFace, MultisampleState, PipelineLayout, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPass, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, TextureFormat, VertexAttribute, VertexBufferLayout,
+    VertexState, VertexStepMode,
+};
+
+pub(crate) const TRIANGLE_SHADER_SRC: &str = include_str!("triangle.wgsl");
+
+pub struct Triangles {
+    config: TriangleConfig,
+    pipeline: RenderPipeline,
+    neighbors: Vec<Vec<(u32, f32)>>,
+    vertex_buffer_tmp: Vec<TriangleVertex>,
+    vertex_buffer: Option<BufferWrapper<TriangleVertex>>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct TriangleVertex {
+    me: u32,
+    longest_sqr: f32,
+    color: [f32; 4],
+}
+
+unsafe impl Zeroable for TriangleVertex {}
+unsafe impl Pod for TriangleVertex {}
+
+impl TriangleVertex {
+    const ATTRIBS: [VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Uint32, 1 => Float32, 2 => Float32x4];
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: size_of::<TriangleVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+impl Triangles {
+    pub fn new(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        frame_format: TextureFormat,
+        multisample: MultisampleState,
+        config: TriangleConfig,
+        point_count: usize,
+    ) -> Triangles {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Triangle Shader Module"),
+            source: ShaderSource::Wgsl(Cow::Owned(with_point_count(
+                TRIANGLE_SHADER_SRC,
+                point_count,
+            ))),
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Triangle Render Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vert_main",
+                buffers: &[TriangleVertex::desc()],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "frag_main",
+                targets: &[Some(ColorTargetState {
+                    format: frame_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample,
+            multiview: None,
+        });
+
+        Triangles {
+            config,
+            pipeline,
+            neighbors: vec![vec![]; point_count],
+            vertex_buffer_tmp: vec![],
+            vertex_buffer: None,
+        }
+    }
+
+    /// Forgets the connections recorded during the previous update.
+    pub fn clear_connections(&mut self) {
+        for neighbors in self.neighbors.iter_mut() {
+            neighbors.clear();
+        }
+    }
+
+    /// Records a connection between two points.
+    pub fn connect(&mut self, me: usize, other: usize, distance_sqr: f32) {
+        self.neighbors[me].push((other as u32, distance_sqr));
+        self.neighbors[other].push((me as u32, distance_sqr));
+    }
+
+    /// Finds every triangle among the recorded connections and uploads them.
+    pub async fn update(&mut self, device: &Device) -> Option<CommandBuffer> {
+        self.vertex_buffer_tmp.clear();
+        let color = self.config.color;
+        let alpha = self.config.alpha;
+        let vertex_buffer_tmp = &mut self.vertex_buffer_tmp;
+        find_triangles(&mut self.neighbors, |triangle, longest_sqr| {
+            for me in triangle {
+                vertex_buffer_tmp.push(TriangleVertex {
+                    me,
+                    longest_sqr,
+                    color: [color[0], color[1], color[2], alpha],
+                });
+            }
+        });
+
+        if self.vertex_buffer_tmp.is_empty() {
+            if let Some(buffer) = self.vertex_buffer.as_mut() {
+                buffer.clear();
+            }
+            return None;
+        }
+
+        // Make sure the buffer is large enough
+        if self.vertex_buffer.is_none()
+            || self.vertex_buffer.as_ref().unwrap().capacity()
+                < self.vertex_buffer_tmp.len() as BufferAddress
+        {
+            self.vertex_buffer = Some(BufferWrapper::new(
+                device,
+                least_power_of_2_greater(self.vertex_buffer_tmp.len() as u64),
+                BufferUsages::VERTEX,
+            ));
+        }
+
+        let buffer = self.vertex_buffer.as_mut().unwrap();
+        Some(
+            buffer
+                .replace_all(device, &self.vertex_buffer_tmp)
+                .await
+                .unwrap(),
+        )
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if let Some(buffer) = self.vertex_buffer.as_ref() {
+            if buffer.len() > 0 {
+                render_pass.set_pipeline(&self.pipeline);
+                render_pass.set_vertex_buffer(0, buffer.buffer().slice(..));
+                render_pass.draw(0..buffer.len() as u32, 0..1);
+            }
+        }
+    }
+}
+
+/// Calls `f` once for every triangle of mutually-connected points, along with
+/// the squared length of the triangle's longest edge.
+///
+/// `neighbors` holds each point's connections and their squared distances. The
+/// lists are sorted in place so that each triangle can be found by
+/// intersecting the neighbor lists of its edges' endpoints, rather than by
+/// trying every triple.
+fn find_triangles<F: FnMut([u32; 3], f32)>(neighbors: &mut [Vec<(u32, f32)>], mut f: F) {
+    for list in neighbors.iter_mut() {
+        list.sort_unstable_by_key(|&(index, _)| index);
+    }
+
+    for (a, a_neighbors) in neighbors.iter().enumerate() {
+        let a = a as u32;
+        for (b_offset, &(b, ab_sqr)) in a_neighbors.iter().enumerate() {
+            if b <= a {
+                continue;
+            }
+
+            // Every c > b connected to both a and b closes a triangle.
+            let b_neighbors = &neighbors[b as usize];
+            let mut a_iter = a_neighbors[b_offset + 1..].iter().peekable();
+            let mut b_iter = b_neighbors.iter().skip_while(|&&(c, _)| c <= b).peekable();
+            while let (Some(&&(ac, ac_sqr)), Some(&&(bc, bc_sqr))) = (a_iter.peek(), b_iter.peek())
+            {
+                if ac < bc {
+                    a_iter.next();
+                } else if bc < ac {
+                    b_iter.next();
+                } else {
+                    f([a, b, ac], ab_sqr.max(ac_sqr).max(bc_sqr));
+                    a_iter.next();
+                    b_iter.next();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::neonet::triangles::find_triangles;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_find_triangles_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(27);
+        let points: Vec<(f32, f32)> = (0..80)
+            .map(|_| (rng.gen_range(0.0..500.0), rng.gen_range(0.0..500.0)))
+            .collect();
+        let max_distance_sqr = 120.0f32 * 120.0;
+        let distance_sqr = |a: usize, b: usize| {
+            let x = points[a].0 - points[b].0;
+            let y = points[a].1 - points[b].1;
+            x * x + y * y
+        };
+        let connected = |a: usize, b: usize| distance_sqr(a, b) < max_distance_sqr;
+
+        let mut neighbors = vec![vec![]; points.len()];
+        for a in 0..points.len() {
+            for b in (a + 1)..points.len() {
+                if connected(a, b) {
+                    neighbors[a].push((b as u32, distance_sqr(a, b)));
+                    neighbors[b].push((a as u32, distance_sqr(a, b)));
+                }
+            }
+        }
+
+        let mut found = vec![];
+        find_triangles(&mut neighbors, |triangle, longest_sqr| {
+            found.push((triangle, longest_sqr))
+        });
+
+        let mut expected = vec![];
+        for a in 0..points.len() {
+            for b in (a + 1)..points.len() {
+                for c in (b + 1)..points.len() {
+                    if connected(a, b) && connected(a, c) && connected(b, c) {
+                        let longest_sqr = distance_sqr(a, b)
+                            .max(distance_sqr(a, c))
+                            .max(distance_sqr(b, c));
+                        expected.push(([a as u32, b as u32, c as u32], longest_sqr));
+                    }
+                }
+            }
+        }
+
+        found.sort_by_key(|&(triangle, _)| triangle);
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,374 @@
+//! 3D mode, where points drift inside a box viewed through a perspective
+//! camera.
+
+use crate::{
+    buffer::BufferWrapper,
+    flow::WindowSize,
+    grid3::{Grid3, Positioned3},
+    neonet::{
+        config::VolumeConfig, fill_placeholder, overlay::OverlayCounts, with_point_count,
+        PointIndex, LINE_COLOR, LINE_LENGTH, POINT_COUNT,
+    },
+    util::least_power_of_2_greater,
+};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{borrow::Cow, f32::consts::PI, time::Duration};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource, BlendState,
+    BufferAddress, BufferUsages, ColorTargetState, ColorWrites, CommandBuffer, Device,
+    FragmentState, FrontFace, MultisampleState, PipelineLayout, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPass, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, TextureFormat, VertexBufferLayout, VertexState,
+    VertexStepMode,
+};
+
+pub(crate) const VOLUME_SHADER_SRC: &str = include_str!("volume.wgsl");
+
+/// Gets the volume shader with its point count and line length filled in.
+/// Volume mode always has `POINT_COUNT` points.
+pub(crate) fn volume_shader_src() -> String {
+    fill_placeholder(
+        &with_point_count(VOLUME_SHADER_SRC, POINT_COUNT),
+        "line_length",
+        &format!("{:?}", LINE_LENGTH),
+    )
+}
+
+pub struct Volume {
+    config: VolumeConfig,
+    size: WindowSize,
+    orbit_angle: f32,
+    points: Grid3<Point3>,
+    camera_buffer: BufferWrapper<CameraData>,
+    vertex_buffer_tmp: Vec<GPUPoint3>,
+    vertex_buffer: BufferWrapper<GPUPoint3>,
+    line_buffer_tmp: Vec<PointIndex>,
+    line_buffer: Option<BufferWrapper<PointIndex>>,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Point3 {
+    index: usize,
+    position: Vec3,
+    velocity: Vec3,
+}
+
+impl Positioned3 for Point3 {
+    fn x(&self) -> f32 {
+        self.position.x
+    }
+
+    fn y(&self) -> f32 {
+        self.position.y
+    }
+
+    fn z(&self) -> f32 {
+        self.position.z
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct GPUPoint3 {
+    position: [f32; 3],
+    _padding1: u32,
+    color: [f32; 3],
+    _padding2: u32,
+}
+
+unsafe impl Zeroable for GPUPoint3 {}
+unsafe impl Pod for GPUPoint3 {}
+
+impl GPUPoint3 {
+    fn from(point: Point3) -> GPUPoint3 {
+        GPUPoint3 {
+            position: point.position.to_array(),
+            _padding1: 0,
+            color: [
+                LINE_COLOR.r as f32,
+                LINE_COLOR.g as f32,
+                LINE_COLOR.b as f32,
+            ],
+            _padding2: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct CameraData {
+    view_proj: [[f32; 4]; 4],
+    screen_size: [f32; 2],
+    line_width: f32,
+    reference_distance: f32,
+}
+
+unsafe impl Zeroable for CameraData {}
+unsafe impl Pod for CameraData {}
+
+impl Volume {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        pipeline_layout: &PipelineLayout,
+        frame_format: TextureFormat,
+        multisample: MultisampleState,
+        config: VolumeConfig,
+        size: WindowSize,
+        seed: u64,
+    ) -> (Volume, Vec<CommandBuffer>) {
+        let mut points = Grid3::new(LINE_LENGTH, size.width, size.height, config.depth);
+        let mut vertex_buffer_tmp = Vec::with_capacity(POINT_COUNT);
+        let mut rng = StdRng::seed_from_u64(seed);
+        for index in 0..POINT_COUNT {
+            // Pick a uniformly random direction on the unit sphere.
+            let angle = rng.gen_range(0.0..(PI * 2.0));
+            let z = rng.gen_range(-1.0..1.0f32);
+            let radius = (1.0 - z * z).sqrt();
+            let speed = rng.gen_range(20.0..100.0f32);
+            let point = Point3 {
+                index,
+                position: Vec3::new(
+                    rng.gen_range(0.0..size.width),
+                    rng.gen_range(0.0..size.height),
+                    rng.gen_range(0.0..config.depth),
+                ),
+                velocity: Vec3::new(angle.cos() * radius, angle.sin() * radius, z) * speed,
+            };
+            points.insert(point);
+            vertex_buffer_tmp.push(GPUPoint3::from(point));
+        }
+
+        let mut cbs = vec![];
+
+        let (camera_buffer, cb) = BufferWrapper::from_data(
+            device,
+            &[camera_data(&config, size, 0.0)],
+            BufferUsages::UNIFORM,
+        );
+        cbs.push(cb);
+
+        let (vertex_buffer, cb) =
+            BufferWrapper::from_data(device, &vertex_buffer_tmp, BufferUsages::UNIFORM);
+        cbs.push(cb);
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Volume Bind Group"),
+            layout: bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(
+                        camera_buffer.buffer().as_entire_buffer_binding(),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Buffer(
+                        vertex_buffer.buffer().as_entire_buffer_binding(),
+                    ),
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Volume Shader Module"),
+            source: ShaderSource::Wgsl(Cow::Owned(volume_shader_src())),
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Volume Render Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vert_main",
+                buffers: &[VertexBufferLayout {
+                    step_mode: VertexStepMode::Instance,
+                    ..PointIndex::desc()
+                }],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "frag_main",
+                targets: &[Some(ColorTargetState {
+                    format: frame_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample,
+            multiview: None,
+        });
+
+        (
+            Volume {
+                config,
+                size,
+                orbit_angle: 0.0,
+                points,
+                camera_buffer,
+                vertex_buffer_tmp,
+                vertex_buffer,
+                line_buffer_tmp: Vec::with_capacity(POINT_COUNT),
+                line_buffer: None,
+                bind_group,
+                pipeline,
+            },
+            cbs,
+        )
+    }
+
+    pub fn resize(&mut self, size: WindowSize) {
+        self.size = size;
+        self.points
+            .set_size(size.width, size.height, self.config.depth);
+    }
+
+    pub async fn update(&mut self, device: &Device, delta: Duration) -> Vec<CommandBuffer> {
+        let mut cbs = vec![];
+        let delta = delta.as_secs_f32();
+        let bounds = Vec3::new(self.size.width, self.size.height, self.config.depth);
+
+        // Move the points, bouncing them off the walls of the box
+
+        self.points.all_mut(|point| {
+            point.position += point.velocity * delta;
+
+            for axis in 0..3 {
+                if point.position[axis] < 0.0 {
+                    point.position[axis] = -point.position[axis];
+                    point.velocity[axis] = point.velocity[axis].abs();
+                } else if point.position[axis] > bounds[axis] {
+                    point.position[axis] = 2.0 * bounds[axis] - point.position[axis];
+                    point.velocity[axis] = -point.velocity[axis].abs();
+                }
+                point.position[axis] = point.position[axis].clamp(0.0, bounds[axis]);
+            }
+
+            self.vertex_buffer_tmp[point.index] = GPUPoint3::from(*point);
+        });
+
+        cbs.push(
+            self.vertex_buffer
+                .replace_all(device, &self.vertex_buffer_tmp)
+                .await
+                .unwrap(),
+        );
+
+        // Move the camera
+
+        if self.config.orbit {
+            self.orbit_angle =
+                (self.orbit_angle + self.config.orbit_speed * delta).rem_euclid(PI * 2.0);
+        }
+        cbs.push(
+            self.camera_buffer
+                .replace_all(
+                    device,
+                    &[camera_data(&self.config, self.size, self.orbit_angle)],
+                )
+                .await
+                .unwrap(),
+        );
+
+        // Find the lines
+
+        self.line_buffer_tmp.clear();
+        self.points.pairs(|point, other, distance_sqr| {
+            self.line_buffer_tmp.push(PointIndex {
+                me: point.index as u32,
+                other: other.index as u32,
+                distance_sqr,
+                offset: [0.0, 0.0],
+            });
+        });
+
+        // Make sure the buffer is large enough
+        if self.line_buffer.is_none()
+            || self.line_buffer.as_ref().unwrap().capacity()
+                < self.line_buffer_tmp.len() as BufferAddress
+        {
+            self.line_buffer = Some(BufferWrapper::new(
+                device,
+                least_power_of_2_greater(self.line_buffer_tmp.len() as u64),
+                BufferUsages::VERTEX,
+            ));
+        }
+
+        cbs.push(
+            self.line_buffer
+                .as_mut()
+                .unwrap()
+                .replace_all(device, &self.line_buffer_tmp)
+                .await
+                .unwrap(),
+        );
+
+        cbs
+    }
+
+    /// Gets the counts shown on the debug overlay. Each line is one entry in
+    /// the line buffer.
+    pub fn overlay_counts(&self) -> OverlayCounts {
+        let line_buffer = self.line_buffer.as_ref().unwrap();
+        OverlayCounts {
+            points: self.vertex_buffer_tmp.len(),
+            connections: self.line_buffer_tmp.len(),
+            point_buffer: (self.vertex_buffer.len(), self.vertex_buffer.capacity()),
+            line_buffer: (line_buffer.len(), line_buffer.capacity()),
+        }
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        let line_buffer = self.line_buffer.as_ref().unwrap();
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, line_buffer.buffer().slice(..));
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..6, 0..line_buffer.len() as u32);
+    }
+}
+
+/// Builds the camera looking at the center of the box, orbiting around its
+/// vertical axis by `orbit_angle`.
+fn camera_data(config: &VolumeConfig, size: WindowSize, orbit_angle: f32) -> CameraData {
+    let center = Vec3::new(size.width / 2.0, size.height / 2.0, config.depth / 2.0);
+    let fov = config.field_of_view.to_radians();
+
+    // Back the camera off far enough that the front face of the box fills the
+    // screen vertically.
+    let reference_distance = size.height / 2.0 / (fov / 2.0).tan();
+    let distance = reference_distance + config.depth / 2.0;
+    let eye = center
+        + Vec3::new(
+            orbit_angle.sin() * distance,
+            0.0,
+            orbit_angle.cos() * distance,
+        );
+
+    let aspect = size.width / size.height.max(1.0);
+    let far = distance + size.width.max(size.height).max(config.depth) * 2.0;
+    let view = Mat4::look_at_rh(eye, center, Vec3::Y);
+    let proj = Mat4::perspective_rh(fov, aspect, 1.0, far);
+
+    CameraData {
+        view_proj: (proj * view).to_cols_array_2d(),
+        screen_size: [size.width, size.height],
+        line_width: config.line_width,
+        reference_distance,
+    }
+}
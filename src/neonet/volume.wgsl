@@ -0,0 +1,80 @@
+// Neonet 3D volume shader.
+
+let point_count: u32 = {{point_count}};
+let line_length: f32 = {{line_length}};
+
+struct VertexData {
+    position: vec3<f32>,
+    color: vec3<f32>,
+};
+
+struct Vertices {
+    vertices: array<VertexData, point_count>,
+};
+
+struct CameraData {
+    view_proj: mat4x4<f32>,
+    screen_size: vec2<f32>,
+    line_width: f32,
+    reference_distance: f32,
+};
+
+struct LineInstance {
+    @location(0) me: u32,
+    @location(1) other: u32,
+    @location(2) distance_sqr: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@group(0)
+@binding(0)
+var<uniform> camera: CameraData;
+
+@group(0)
+@binding(1)
+var<uniform> vertices: Vertices;
+
+// Each line is drawn as a screen-space quad so that its width can shrink with
+// distance from the camera.
+@vertex
+fn vert_main(@builtin(vertex_index) vertex_index: u32, instance: LineInstance) -> VertexOutput {
+    var ends = array<f32, 6>(0.0, 0.0, 1.0, 0.0, 1.0, 1.0);
+    var sides = array<f32, 6>(-1.0, 1.0, 1.0, -1.0, 1.0, -1.0);
+    var end = ends[vertex_index % 6u];
+    var side = sides[vertex_index % 6u];
+
+    var me = vertices.vertices[instance.me];
+    var other = vertices.vertices[instance.other];
+    var me_clip = camera.view_proj * vec4<f32>(me.position, 1.0);
+    var other_clip = camera.view_proj * vec4<f32>(other.position, 1.0);
+
+    var me_screen = me_clip.xy / me_clip.w * camera.screen_size;
+    var other_screen = other_clip.xy / other_clip.w * camera.screen_size;
+    var direction = other_screen - me_screen;
+    if (length(direction) < 0.0001) {
+        direction = vec2<f32>(1.0, 0.0);
+    }
+    direction = normalize(direction);
+    var normal = vec2<f32>(-direction.y, direction.x);
+
+    var clip = mix(me_clip, other_clip, end);
+    var perspective = camera.reference_distance / clip.w;
+    var width = camera.line_width * perspective;
+    var offset = normal * side * width / camera.screen_size;
+
+    var output: VertexOutput;
+    output.position = clip + vec4<f32>(offset * clip.w, 0.0, 0.0);
+    var distance = sqrt(instance.distance_sqr);
+    var alpha = (1.0 - distance / line_length) * clamp(perspective, 0.0, 1.0);
+    output.color = vec4<f32>(mix(me.color, other.color, end), alpha);
+    return output;
+}
+
+@fragment
+fn frag_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,115 @@
+//! Seeded 3D Perlin noise.
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+/// Ken Perlin's improved noise, with a permutation table shuffled from a seed
+/// so that the same seed always produces the same field.
+pub struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(&mut StdRng::seed_from_u64(seed));
+
+        let mut permutation = [0; 512];
+        for (i, p) in permutation.iter_mut().enumerate() {
+            *p = table[i & 255];
+        }
+
+        Perlin { permutation }
+    }
+
+    /// Samples the noise field, returning a value roughly in `-1.0..=1.0`.
+    pub fn get(&self, x: f32, y: f32, z: f32) -> f32 {
+        let p = &self.permutation;
+
+        let (xi, yi, zi) = (
+            x.floor() as i32 as usize & 255,
+            y.floor() as i32 as usize & 255,
+            z.floor() as i32 as usize & 255,
+        );
+        let (x, y, z) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let a = p[xi] as usize + yi;
+        let aa = p[a] as usize + zi;
+        let ab = p[a + 1] as usize + zi;
+        let b = p[xi + 1] as usize + yi;
+        let ba = p[b] as usize + zi;
+        let bb = p[b + 1] as usize + zi;
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p[aa], x, y, z), grad(p[ba], x - 1.0, y, z)),
+                lerp(
+                    u,
+                    grad(p[ab], x, y - 1.0, z),
+                    grad(p[bb], x - 1.0, y - 1.0, z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(p[aa + 1], x, y, z - 1.0),
+                    grad(p[ba + 1], x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(p[ab + 1], x, y - 1.0, z - 1.0),
+                    grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+fn grad(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::noise::Perlin;
+
+    #[test]
+    fn test_perlin_deterministic_and_bounded() {
+        let a = Perlin::new(30);
+        let b = Perlin::new(30);
+        let c = Perlin::new(31);
+
+        let mut differs = false;
+        for i in 0..1000 {
+            let (x, y, z) = (i as f32 * 0.37, i as f32 * -0.11, i as f32 * 0.05);
+            let value = a.get(x, y, z);
+            assert_eq!(value, b.get(x, y, z));
+            assert!((-1.0..=1.0).contains(&value));
+            differs |= value != c.get(x, y, z);
+        }
+        assert!(differs);
+
+        // Noise is zero on lattice points.
+        assert_eq!(a.get(3.0, 4.0, 5.0), 0.0);
+    }
+}
@@ -0,0 +1,551 @@
+//! A k-d tree that splits the points in half along alternating axes, so that
+//! it stays balanced however the points are spread out.
+
+use crate::{
+    grid::Positioned,
+    spatial::{self, SpatialIndex},
+};
+use std::cmp::Ordering;
+
+#[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+use rayon::prelude::*;
+
+/// How many points can be inserted after the tree was built before it is
+/// built again. Until then, they are checked one by one.
+const MAX_UNSORTED: usize = 64;
+
+/// How many subtrees a search can have left to visit at once. Each subtree is
+/// at most half the size of its parent, so there are at most `usize::BITS`
+/// levels, and the search keeps at most one subtree from each level above the
+/// one it is visiting, along with the two it just reached.
+const STACK_SIZE: usize = usize::BITS as usize + 2;
+
+/// A k-d tree kept in a flat array. Each range of the array is a subtree whose
+/// root is in the middle, with the points before it on one side of the root
+/// along the subtree's axis, and the points after it on the other side.
+///
+/// The tree is built again whenever points move, which is quick enough to do
+/// every frame.
+pub struct KdTree<P: Positioned + Clone> {
+    max_distance: f32,
+    /// The size of the torus, when the edges are joined.
+    torus: Option<(f32, f32)>,
+    points: Vec<P>,
+    /// How many of `points` are arranged as a tree. The rest were inserted
+    /// since it was built.
+    tree_len: usize,
+}
+
+impl<P: Positioned + Clone> KdTree<P> {
+    /// Creates a tree whose `pairs` finds points closer than `max_distance`.
+    pub fn new(max_distance: f32) -> KdTree<P> {
+        KdTree {
+            max_distance,
+            torus: None,
+            points: vec![],
+            tree_len: 0,
+        }
+    }
+
+    /// Creates a tree covering `0..width` and `0..height` whose opposite edges
+    /// are joined. Points are expected to already be wrapped into it.
+    pub fn new_torus(max_distance: f32, width: f32, height: f32) -> KdTree<P> {
+        KdTree {
+            torus: Some((width, height)),
+            ..KdTree::new(max_distance)
+        }
+    }
+
+    fn rebuild(&mut self) {
+        build(&mut self.points, 0);
+        self.tree_len = self.points.len();
+    }
+
+    /// Iterates over the indices of the points closer than `distance` to `x`
+    /// and `y`, along with their squared distance and how far each has to be
+    /// moved to get its image nearest to `x` and `y`, skipping indices before
+    /// `first`.
+    fn within_indices(&self, x: f32, y: f32, distance: f32, first: usize) -> Within<'_, P> {
+        // Searching around several images of the query on a small torus could
+        // find the same point twice, so then every point is checked instead.
+        let small_torus = matches!(
+            self.torus,
+            Some((width, height)) if distance * 2.0 >= width.min(height)
+        );
+
+        let mut images = [(0.0, 0.0); 4];
+        let mut image_count = 0;
+        if !small_torus {
+            let x_offsets = self.image_offsets(x, distance, self.torus.map(|(width, _)| width));
+            let y_offsets = self.image_offsets(y, distance, self.torus.map(|(_, height)| height));
+            for &x_offset in x_offsets.iter().flatten() {
+                for &y_offset in y_offsets.iter().flatten() {
+                    images[image_count] = (x_offset, y_offset);
+                    image_count += 1;
+                }
+            }
+        }
+
+        Within {
+            tree: self,
+            x,
+            y,
+            max_distance_sqr: distance * distance,
+            distance,
+            first,
+            images,
+            image_count,
+            image: 0,
+            stack: [(0, self.tree_len, 0); STACK_SIZE],
+            stack_len: 1,
+            unsorted: if small_torus {
+                first
+            } else {
+                first.max(self.tree_len)
+            },
+        }
+    }
+
+    /// Gets the offsets of the images of a query along one axis that may be
+    /// near points on the other side of a torus.
+    fn image_offsets(&self, value: f32, distance: f32, length: Option<f32>) -> [Option<f32>; 2] {
+        match length {
+            Some(length) if value - distance < 0.0 => [Some(0.0), Some(length)],
+            Some(length) if value + distance > length => [Some(0.0), Some(-length)],
+            _ => [Some(0.0), None],
+        }
+    }
+
+    /// Gets how far a point has to be moved to get its image nearest to `x`
+    /// and `y`.
+    fn image_offset(&self, x: f32, y: f32, p: &P) -> (f32, f32) {
+        match self.torus {
+            Some((width, height)) => spatial::image_offset(p.x() - x, p.y() - y, width, height),
+            None => (0.0, 0.0),
+        }
+    }
+
+    /// Finds the pairs whose first point is `points[index]`.
+    fn pairs_from<'a, F: FnMut(&'a P, &'a P, f32, (f32, f32))>(&'a self, index: usize, f: &mut F) {
+        let p = &self.points[index];
+        for (other, distance_sqr, offset) in
+            self.within_indices(p.x(), p.y(), self.max_distance, index + 1)
+        {
+            f(p, &self.points[other], distance_sqr, offset);
+        }
+    }
+}
+
+/// Arranges points into a k-d tree, splitting first along `axis`, which is 0
+/// for x and 1 for y.
+fn build<P: Positioned>(points: &mut [P], axis: usize) {
+    if points.len() <= 1 {
+        return;
+    }
+
+    let middle = points.len() / 2;
+    points.select_nth_unstable_by(middle, |a, b| {
+        coordinate(a, axis)
+            .partial_cmp(&coordinate(b, axis))
+            .unwrap_or(Ordering::Equal)
+    });
+    let (before, after) = points.split_at_mut(middle);
+    build(before, 1 - axis);
+    build(&mut after[1..], 1 - axis);
+}
+
+fn coordinate<P: Positioned>(p: &P, axis: usize) -> f32 {
+    if axis == 0 {
+        p.x()
+    } else {
+        p.y()
+    }
+}
+
+/// Walks the tree around each image of a query, then checks the points that
+/// aren't in the tree yet.
+struct Within<'a, P: Positioned + Clone> {
+    tree: &'a KdTree<P>,
+    x: f32,
+    y: f32,
+    distance: f32,
+    max_distance_sqr: f32,
+    first: usize,
+    /// The offsets from the query to each of its images to search around.
+    images: [(f32, f32); 4],
+    image_count: usize,
+    image: usize,
+    /// The subtrees left to visit around the current image, as the range of
+    /// the array they cover and the axis they split along. Only the first
+    /// `stack_len` are used, so searching doesn't allocate.
+    stack: [(usize, usize, usize); STACK_SIZE],
+    stack_len: usize,
+    /// The next point to check one by one, once the tree is done.
+    unsorted: usize,
+}
+
+impl<'a, P: Positioned + Clone> Within<'a, P> {
+    fn push(&mut self, subtree: (usize, usize, usize)) {
+        self.stack[self.stack_len] = subtree;
+        self.stack_len += 1;
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize, usize)> {
+        self.stack_len = self.stack_len.checked_sub(1)?;
+        Some(self.stack[self.stack_len])
+    }
+}
+
+impl<'a, P: Positioned + Clone> Iterator for Within<'a, P> {
+    type Item = (usize, f32, (f32, f32));
+
+    fn next(&mut self) -> Option<(usize, f32, (f32, f32))> {
+        while self.image < self.image_count {
+            let (offset_x, offset_y) = self.images[self.image];
+            let (x, y) = (self.x + offset_x, self.y + offset_y);
+            let Some((start, end, axis)) = self.pop() else {
+                self.image += 1;
+                if self.image < self.image_count {
+                    self.push((0, self.tree.tree_len, 0));
+                }
+                continue;
+            };
+            if start >= end || end <= self.first {
+                continue;
+            }
+
+            let middle = (start + end) / 2;
+            let p = &self.tree.points[middle];
+            let (split, query) = if axis == 0 { (p.x(), x) } else { (p.y(), y) };
+            if query - self.distance <= split {
+                self.push((start, middle, 1 - axis));
+            }
+            if query + self.distance >= split {
+                self.push((middle + 1, end, 1 - axis));
+            }
+
+            let (dx, dy) = (p.x() - x, p.y() - y);
+            let distance_sqr = dx * dx + dy * dy;
+            if middle >= self.first && distance_sqr < self.max_distance_sqr {
+                return Some((middle, distance_sqr, (-offset_x, -offset_y)));
+            }
+        }
+
+        while self.unsorted < self.tree.points.len() {
+            let index = self.unsorted;
+            self.unsorted += 1;
+            let p = &self.tree.points[index];
+            let offset = self.tree.image_offset(self.x, self.y, p);
+            let (dx, dy) = (p.x() + offset.0 - self.x, p.y() + offset.1 - self.y);
+            let distance_sqr = dx * dx + dy * dy;
+            if distance_sqr < self.max_distance_sqr {
+                return Some((index, distance_sqr, offset));
+            }
+        }
+
+        None
+    }
+}
+
+impl<P: Positioned + Clone> SpatialIndex<P> for KdTree<P> {
+    fn insert(&mut self, p: P) {
+        self.points.push(p);
+        if self.points.len() - self.tree_len > MAX_UNSORTED {
+            self.rebuild();
+        }
+    }
+
+    fn extend<I: IntoIterator<Item = P>>(&mut self, points: I) {
+        self.points.extend(points);
+        if self.points.len() - self.tree_len > MAX_UNSORTED {
+            self.rebuild();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.points.clear();
+        self.tree_len = 0;
+    }
+
+    fn set_size(&mut self, width: f32, height: f32) {
+        if self.torus.is_some() {
+            self.torus = Some((width, height));
+        }
+    }
+
+    fn all_mut<F: FnMut(&mut P)>(&mut self, f: F) {
+        self.points.iter_mut().for_each(f);
+        self.rebuild();
+    }
+
+    fn retain<F: FnMut(&P) -> bool>(&mut self, f: F) {
+        self.points.retain(f);
+        self.rebuild();
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a P> + 'a
+    where
+        P: 'a,
+    {
+        self.points.iter()
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut P> + 'a
+    where
+        P: 'a,
+    {
+        self.points.iter_mut()
+    }
+
+    fn within<'a>(
+        &'a self,
+        x: f32,
+        y: f32,
+        distance: f32,
+    ) -> impl Iterator<Item = (&'a P, f32)> + 'a
+    where
+        P: 'a,
+    {
+        self.within_indices(x, y, distance, 0)
+            .map(|(index, distance_sqr, _)| (&self.points[index], distance_sqr))
+    }
+
+    fn pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, mut f: F) {
+        for index in 0..self.points.len() {
+            self.pairs_from(index, &mut f);
+        }
+    }
+
+    #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+    fn par_pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, mut f: F)
+    where
+        P: Sync,
+    {
+        // Each thread gathers the pairs of a run of points into one list, and
+        // the lists are collected in order.
+        let found: Vec<Vec<spatial::FoundPair<P>>> = (0..self.points.len())
+            .into_par_iter()
+            .fold(Vec::new, |mut found, index| {
+                self.pairs_from(index, &mut |p, op, distance_sqr, offset| {
+                    found.push((p, op, distance_sqr, offset))
+                });
+                found
+            })
+            .collect();
+
+        for (p, op, distance_sqr, offset) in found.into_iter().flatten() {
+            f(p, op, distance_sqr, offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        grid::Positioned,
+        spatial::{KdTree, SpatialIndex},
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[derive(Debug, Clone)]
+    struct TestPoint {
+        index: usize,
+        x: f32,
+        y: f32,
+    }
+
+    impl Positioned for TestPoint {
+        fn x(&self) -> f32 {
+            self.x
+        }
+
+        fn y(&self) -> f32 {
+            self.y
+        }
+
+        fn x_mut(&mut self) -> &mut f32 {
+            &mut self.x
+        }
+
+        fn y_mut(&mut self) -> &mut f32 {
+            &mut self.y
+        }
+    }
+
+    const MAX_DISTANCE: f32 = 100.0;
+
+    /// Picks points either spread evenly or gathered into a few tight
+    /// clusters, with some exactly on top of each other.
+    fn random_points(
+        rng: &mut StdRng,
+        width: f32,
+        height: f32,
+        count: usize,
+        clustered: bool,
+    ) -> Vec<TestPoint> {
+        let centers: Vec<(f32, f32)> = (0..4)
+            .map(|_| (rng.gen_range(0.0..width), rng.gen_range(0.0..height)))
+            .collect();
+        (0..count)
+            .map(|index| {
+                let (x, y) = if index % 10 == 0 && index > 0 {
+                    (0.0, 0.0)
+                } else if clustered {
+                    let (x, y) = centers[index % centers.len()];
+                    (
+                        (x + rng.gen_range(-30.0..30.0)).rem_euclid(width),
+                        (y + rng.gen_range(-30.0..30.0)).rem_euclid(height),
+                    )
+                } else {
+                    (rng.gen_range(0.0..width), rng.gen_range(0.0..height))
+                };
+                TestPoint { index, x, y }
+            })
+            .collect()
+    }
+
+    fn distance_sqr(a: &TestPoint, b: &TestPoint, torus: Option<(f32, f32)>) -> f32 {
+        let (mut x, mut y) = ((a.x - b.x).abs(), (a.y - b.y).abs());
+        if let Some((width, height)) = torus {
+            x = x.min(width - x);
+            y = y.min(height - y);
+        }
+        x * x + y * y
+    }
+
+    /// Checks `pairs` and `within` against comparing every point with every
+    /// other, querying `within` around positions in `0..width` and
+    /// `0..height`.
+    fn assert_matches_brute_force(
+        tree: &KdTree<TestPoint>,
+        (width, height): (f32, f32),
+        rng: &mut StdRng,
+        context: &str,
+    ) {
+        let mut points: Vec<TestPoint> = tree.iter().cloned().collect();
+        points.sort_unstable_by_key(|p| p.index);
+
+        let mut found = vec![];
+        tree.pairs(|p, op, found_sqr, (offset_x, offset_y)| {
+            let expected = distance_sqr(p, op, tree.torus);
+            assert!(
+                (found_sqr - expected).abs() <= expected * 1e-5 + 1e-3,
+                "distance with {}",
+                context
+            );
+            let image = TestPoint {
+                x: op.x + offset_x,
+                y: op.y + offset_y,
+                ..op.clone()
+            };
+            let image_sqr = distance_sqr(p, &image, None);
+            assert!(
+                (image_sqr - expected).abs() <= expected * 1e-5 + 1e-3,
+                "offset with {}",
+                context
+            );
+            found.push((p.index.min(op.index), p.index.max(op.index)));
+        });
+        found.sort_unstable();
+
+        let mut expected = vec![];
+        for (i, p) in points.iter().enumerate() {
+            for op in &points[i + 1..] {
+                if distance_sqr(p, op, tree.torus) < MAX_DISTANCE * MAX_DISTANCE {
+                    expected.push((p.index, op.index));
+                }
+            }
+        }
+        assert_eq!(found, expected, "pairs with {}", context);
+
+        for _ in 0..20 {
+            let query = TestPoint {
+                index: usize::MAX,
+                x: rng.gen_range(0.0..width),
+                y: rng.gen_range(0.0..height),
+            };
+            let distance = rng.gen_range(1.0..200.0);
+            let mut found: Vec<usize> = tree
+                .within(query.x, query.y, distance)
+                .map(|(p, _)| p.index)
+                .collect();
+            found.sort_unstable();
+            let expected: Vec<usize> = points
+                .iter()
+                .filter(|p| distance_sqr(p, &query, tree.torus) < distance * distance)
+                .map(|p| p.index)
+                .collect();
+            assert_eq!(found, expected, "within {} with {}", distance, context);
+        }
+    }
+
+    #[test]
+    fn test_pairs_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(45);
+        for clustered in [false, true] {
+            for torus in [false, true] {
+                let mut tree = if torus {
+                    KdTree::new_torus(MAX_DISTANCE, 1000.0, 700.0)
+                } else {
+                    KdTree::new(MAX_DISTANCE)
+                };
+                for p in random_points(&mut rng, 1000.0, 700.0, 500, clustered) {
+                    tree.insert(p);
+                }
+                let context = format!("clustered {}, torus {}", clustered, torus);
+                assert_matches_brute_force(&tree, (1000.0, 700.0), &mut rng, &context);
+
+                // A few more points than are checked one by one, so some are
+                // outside the tree.
+                for mut p in random_points(&mut rng, 1000.0, 700.0, 30, clustered) {
+                    p.index += 500;
+                    tree.insert(p);
+                }
+                assert!(tree.tree_len < tree.points.len());
+                assert_matches_brute_force(&tree, (1000.0, 700.0), &mut rng, &context);
+
+                tree.all_mut(|p| {
+                    p.x = (p.x + 35.0).rem_euclid(1000.0);
+                    p.y = (p.y + 12.0).rem_euclid(700.0);
+                });
+                assert_matches_brute_force(&tree, (1000.0, 700.0), &mut rng, &context);
+
+                tree.retain(|p| p.index % 3 != 0);
+                assert_matches_brute_force(&tree, (1000.0, 700.0), &mut rng, &context);
+            }
+        }
+    }
+
+    #[test]
+    fn test_small_torus() {
+        let mut rng = StdRng::seed_from_u64(46);
+        let mut tree = KdTree::new_torus(MAX_DISTANCE, 150.0, 400.0);
+        for p in random_points(&mut rng, 150.0, 400.0, 200, false) {
+            tree.insert(p);
+        }
+        assert_matches_brute_force(&tree, (150.0, 400.0), &mut rng, "a small torus");
+    }
+
+    #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_par_pairs_match_serial() {
+        let mut rng = StdRng::seed_from_u64(48);
+        for torus in [false, true] {
+            let mut tree = if torus {
+                KdTree::new_torus(MAX_DISTANCE, 1000.0, 700.0)
+            } else {
+                KdTree::new(MAX_DISTANCE)
+            };
+            for p in random_points(&mut rng, 1000.0, 700.0, 500, true) {
+                tree.insert(p);
+            }
+
+            let mut serial = vec![];
+            tree.pairs(|p, op, distance, _| serial.push((p.index, op.index, distance)));
+            let mut parallel = vec![];
+            tree.par_pairs(|p, op, distance, _| parallel.push((p.index, op.index, distance)));
+            assert!(!serial.is_empty());
+            assert_eq!(parallel, serial, "torus {}", torus);
+        }
+    }
+}
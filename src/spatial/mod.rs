@@ -0,0 +1,327 @@
+//! Structures for finding points near each other, which can be swapped for one
+//! another depending on how points are spread out.
+
+mod kd_tree;
+
+pub use kd_tree::KdTree;
+
+use crate::grid::{Grid, Positioned};
+use serde::{Deserialize, Serialize};
+
+/// Gets how far a point `dx` and `dy` away from another has to be moved to get
+/// its image nearest to the other on a torus of the given size.
+pub(crate) fn image_offset(dx: f32, dy: f32, width: f32, height: f32) -> (f32, f32) {
+    (
+        -(dx / width).round() * width,
+        -(dy / height).round() * height,
+    )
+}
+
+/// A pair found by `par_pairs`, waiting to be passed on in order.
+#[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+pub(crate) type FoundPair<'a, P> = (&'a P, &'a P, f32, (f32, f32));
+
+/// Sorts found points by their squared distance, keeping only the `k`
+/// nearest.
+pub(crate) fn nearest<P>(found: &mut Vec<(&P, f32)>, k: usize) {
+    let by_distance = |a: &(&P, f32), b: &(&P, f32)| a.1.total_cmp(&b.1);
+    if k < found.len() {
+        found.select_nth_unstable_by(k, by_distance);
+        found.truncate(k);
+    }
+    found.sort_unstable_by(by_distance);
+}
+
+/// The operations needed to move points around and find the ones near each
+/// other.
+///
+/// Every index has a maximum distance, set when it is created, that `pairs`
+/// finds pairs within. On a torus, distances are measured to the nearest
+/// images of points.
+pub trait SpatialIndex<P: Positioned + Clone> {
+    fn insert(&mut self, p: P);
+
+    /// Adds many points, which may be quicker than inserting them one by one.
+    fn extend<I: IntoIterator<Item = P>>(&mut self, points: I) {
+        for p in points {
+            self.insert(p);
+        }
+    }
+
+    fn clear(&mut self);
+
+    /// Changes the area the points are in, for indexes that depend on it.
+    fn set_size(&mut self, width: f32, height: f32);
+
+    /// Calls `f` on every point, which may move it.
+    fn all_mut<F: FnMut(&mut P)>(&mut self, f: F);
+
+    /// Removes every point for which `f` returns false.
+    fn retain<F: FnMut(&P) -> bool>(&mut self, f: F);
+
+    /// Iterates over every point, in no particular order.
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a P> + 'a
+    where
+        P: 'a;
+
+    /// Iterates mutably over every point, in no particular order. Points must
+    /// not be moved through this; use `all_mut` for that.
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut P> + 'a
+    where
+        P: 'a;
+
+    /// Iterates over every point closer than `distance` to `x` and `y`, along
+    /// with its squared distance.
+    fn within<'a>(
+        &'a self,
+        x: f32,
+        y: f32,
+        distance: f32,
+    ) -> impl Iterator<Item = (&'a P, f32)> + 'a
+    where
+        P: 'a;
+
+    /// Finds the `k` points nearest to `x` and `y` that are closer than
+    /// `max_distance`, nearest first, along with their squared distances. They
+    /// replace what `found` held, so that it can be reused between searches.
+    fn k_nearest<'a>(
+        &'a self,
+        x: f32,
+        y: f32,
+        k: usize,
+        max_distance: f32,
+        found: &mut Vec<(&'a P, f32)>,
+    ) where
+        P: 'a,
+    {
+        found.clear();
+        found.extend(self.within(x, y, max_distance));
+        nearest(found, k);
+    }
+
+    /// Calls `f` for every pair of points closer than the maximum distance,
+    /// along with their squared distance and how far the second point has to
+    /// be moved to get its image nearest to the first, which is zero unless
+    /// the edges are joined.
+    fn pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, f: F);
+
+    /// Like `pairs`, but searching on several threads when the `rayon`
+    /// feature is enabled. The pairs are passed to `f` on this thread, in the
+    /// same order `pairs` finds them.
+    fn par_pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, f: F)
+    where
+        P: Sync,
+    {
+        self.pairs(f)
+    }
+}
+
+impl<P: Positioned + Clone> SpatialIndex<P> for Grid<P> {
+    fn insert(&mut self, p: P) {
+        Grid::insert(self, p);
+    }
+
+    fn extend<I: IntoIterator<Item = P>>(&mut self, points: I) {
+        Grid::extend(self, points)
+    }
+
+    fn clear(&mut self) {
+        Grid::clear(self)
+    }
+
+    fn set_size(&mut self, width: f32, height: f32) {
+        Grid::set_size(self, width, height)
+    }
+
+    fn all_mut<F: FnMut(&mut P)>(&mut self, f: F) {
+        Grid::all_mut(self, f)
+    }
+
+    fn retain<F: FnMut(&P) -> bool>(&mut self, f: F) {
+        Grid::retain(self, f)
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a P> + 'a
+    where
+        P: 'a,
+    {
+        Grid::iter(self)
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut P> + 'a
+    where
+        P: 'a,
+    {
+        Grid::iter_mut(self)
+    }
+
+    fn within<'a>(
+        &'a self,
+        x: f32,
+        y: f32,
+        distance: f32,
+    ) -> impl Iterator<Item = (&'a P, f32)> + 'a
+    where
+        P: 'a,
+    {
+        Grid::within(self, x, y, distance)
+    }
+
+    fn k_nearest<'a>(
+        &'a self,
+        x: f32,
+        y: f32,
+        k: usize,
+        max_distance: f32,
+        found: &mut Vec<(&'a P, f32)>,
+    ) where
+        P: 'a,
+    {
+        Grid::k_nearest(self, x, y, k, max_distance, found)
+    }
+
+    fn pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, f: F) {
+        Grid::pairs(self, f)
+    }
+
+    #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+    fn par_pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, f: F)
+    where
+        P: Sync,
+    {
+        Grid::par_pairs(self, f)
+    }
+}
+
+/// Which kind of spatial index to keep points in.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpatialIndexKind {
+    /// A grid of square chunks as large as the maximum distance. This is
+    /// fastest when points are spread evenly.
+    #[default]
+    Grid,
+    /// A k-d tree, which adapts to where the points are, for when they gather
+    /// in clusters and leave most of the screen empty.
+    KdTree,
+}
+
+/// Any of the spatial indexes, picked when the program runs.
+pub enum AnySpatialIndex<P: Positioned + Clone> {
+    Grid(Grid<P>),
+    KdTree(KdTree<P>),
+}
+
+/// An iterator that is one of two kinds of iterator.
+enum Either<A, B> {
+    A(A),
+    B(B),
+}
+
+impl<T, A: Iterator<Item = T>, B: Iterator<Item = T>> Iterator for Either<A, B> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            Either::A(a) => a.next(),
+            Either::B(b) => b.next(),
+        }
+    }
+}
+
+macro_rules! delegate {
+    ($self:ident, $index:ident => $call:expr) => {
+        match $self {
+            AnySpatialIndex::Grid($index) => $call,
+            AnySpatialIndex::KdTree($index) => $call,
+        }
+    };
+}
+
+impl<P: Positioned + Clone> SpatialIndex<P> for AnySpatialIndex<P> {
+    fn insert(&mut self, p: P) {
+        delegate!(self, index => SpatialIndex::insert(index, p))
+    }
+
+    fn extend<I: IntoIterator<Item = P>>(&mut self, points: I) {
+        delegate!(self, index => SpatialIndex::extend(index, points))
+    }
+
+    fn clear(&mut self) {
+        delegate!(self, index => SpatialIndex::clear(index))
+    }
+
+    fn set_size(&mut self, width: f32, height: f32) {
+        delegate!(self, index => SpatialIndex::set_size(index, width, height))
+    }
+
+    fn all_mut<F: FnMut(&mut P)>(&mut self, f: F) {
+        delegate!(self, index => SpatialIndex::all_mut(index, f))
+    }
+
+    fn retain<F: FnMut(&P) -> bool>(&mut self, f: F) {
+        delegate!(self, index => SpatialIndex::retain(index, f))
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a P> + 'a
+    where
+        P: 'a,
+    {
+        match self {
+            AnySpatialIndex::Grid(index) => Either::A(SpatialIndex::iter(index)),
+            AnySpatialIndex::KdTree(index) => Either::B(SpatialIndex::iter(index)),
+        }
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut P> + 'a
+    where
+        P: 'a,
+    {
+        match self {
+            AnySpatialIndex::Grid(index) => Either::A(SpatialIndex::iter_mut(index)),
+            AnySpatialIndex::KdTree(index) => Either::B(SpatialIndex::iter_mut(index)),
+        }
+    }
+
+    fn within<'a>(
+        &'a self,
+        x: f32,
+        y: f32,
+        distance: f32,
+    ) -> impl Iterator<Item = (&'a P, f32)> + 'a
+    where
+        P: 'a,
+    {
+        match self {
+            AnySpatialIndex::Grid(index) => Either::A(SpatialIndex::within(index, x, y, distance)),
+            AnySpatialIndex::KdTree(index) => {
+                Either::B(SpatialIndex::within(index, x, y, distance))
+            },
+        }
+    }
+
+    fn k_nearest<'a>(
+        &'a self,
+        x: f32,
+        y: f32,
+        k: usize,
+        max_distance: f32,
+        found: &mut Vec<(&'a P, f32)>,
+    ) where
+        P: 'a,
+    {
+        delegate!(self, index => SpatialIndex::k_nearest(index, x, y, k, max_distance, found))
+    }
+
+    fn pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, f: F) {
+        delegate!(self, index => SpatialIndex::pairs(index, f))
+    }
+
+    #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+    fn par_pairs<F: FnMut(&P, &P, f32, (f32, f32))>(&self, f: F)
+    where
+        P: Sync,
+    {
+        delegate!(self, index => SpatialIndex::par_pairs(index, f))
+    }
+}
@@ -1,18 +1,62 @@
+//! Times scopes of code for profiling, when the `timer` feature is enabled.
+//!
+//! On the web, scopes are passed to `console.time`, so they show up in the
+//! browser's profiler. On desktop, they are recorded here instead. The time
+//! spent in each scope is summed per frame into min/avg/max statistics, and
+//! every scope can be saved as a Chrome trace to load into a trace viewer such
+//! as `chrome://tracing` or Perfetto.
+//!
+//! Each thread records its scopes on its own, so threads timing scopes at the
+//! same time don't wait on each other. What they recorded is gathered at the
+//! end of each frame.
+
+#[cfg(not(target_arch = "wasm32"))]
+use serde::Serialize;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Separates the names of nested scopes in their paths.
+#[cfg(not(target_arch = "wasm32"))]
+const PATH_SEPARATOR: &str = " > ";
+
+/// The most scopes kept for the trace. Statistics are still gathered once it
+/// is full, which takes several minutes with every timer in the grid running.
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_TRACE_EVENTS: usize = 1_000_000;
+
+/// Times from when it is created until it is dropped.
 pub struct Timer {
+    #[cfg(target_arch = "wasm32")]
     name: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    start: Instant,
 }
 
 impl Timer {
     pub fn new(name: String) -> Timer {
         #[cfg(target_arch = "wasm32")]
         web_sys::console::time_with_label(&name);
-        Timer { name }
+        #[cfg(not(target_arch = "wasm32"))]
+        RECORDS.with(|records| records.lock().unwrap().open(&name));
+        Timer {
+            #[cfg(target_arch = "wasm32")]
+            name,
+            #[cfg(not(target_arch = "wasm32"))]
+            start: Instant::now(),
+        }
     }
 
     pub fn from_str(name: &str) -> Timer {
-        #[cfg(target_arch = "wasm32")]
-        web_sys::console::time_with_label(name);
-        Timer { name: name.to_string() }
+        Timer::new(name.to_string())
     }
 }
 
@@ -20,5 +64,449 @@ impl Drop for Timer {
     fn drop(&mut self) {
         #[cfg(target_arch = "wasm32")]
         web_sys::console::time_end_with_label(&self.name);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let duration = self.start.elapsed();
+            RECORDS.with(|records| records.lock().unwrap().close(self.start, duration));
+        }
+    }
+}
+
+/// How long a scope took per frame, over every frame it ran in.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ScopeStats {
+    /// How many frames the scope ran in.
+    pub frames: u32,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ScopeStats {
+    fn new(duration: Duration) -> ScopeStats {
+        ScopeStats {
+            frames: 1,
+            min: duration,
+            max: duration,
+            total: duration,
+        }
+    }
+
+    fn add(&mut self, duration: Duration) {
+        self.frames += 1;
+        self.min = self.min.min(duration);
+        self.max = self.max.max(duration);
+        self.total += duration;
+    }
+
+    /// Gets the average time per frame the scope ran in.
+    pub fn average(&self) -> Duration {
+        self.total / self.frames
+    }
+}
+
+/// An event in the Chrome trace format.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Serialize)]
+struct TraceEvent {
+    name: String,
+    /// The kind of event, which is `X` for a scope and `i` for an instant.
+    ph: &'static str,
+    /// When the event started, in microseconds.
+    ts: f64,
+    /// How long a scope took, in microseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: u32,
+    tid: u64,
+    /// What an instant applies to, which is `g` for the whole trace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Serialize)]
+struct Trace<'a> {
+    #[serde(rename = "traceEvents")]
+    trace_events: &'a [TraceEvent],
+}
+
+/// A scope that has been opened on a thread, nested in the scopes that were
+/// open around it.
+#[cfg(not(target_arch = "wasm32"))]
+struct Scope {
+    name: String,
+    /// The names of the scopes it is nested in and its own, separated by
+    /// `PATH_SEPARATOR`.
+    path: String,
+    /// The scopes that have been opened within this one.
+    children: Vec<usize>,
+    /// The time spent in the scope since it was last collected, if it ran.
+    frame: Option<Duration>,
+}
+
+/// The scopes timed on one thread since they were last collected.
+///
+/// Scopes are kept across frames, so timing one that has been seen before
+/// doesn't allocate.
+#[cfg(not(target_arch = "wasm32"))]
+struct ThreadRecords {
+    tid: u64,
+    scopes: Vec<Scope>,
+    /// The scopes that aren't nested in any other.
+    roots: Vec<usize>,
+    /// The scopes open on this thread, outermost first.
+    open: Vec<usize>,
+    /// The scope, start and duration of every scope closed for the trace.
+    events: Vec<(usize, Instant, Duration)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ThreadRecords {
+    fn new(tid: u64) -> ThreadRecords {
+        ThreadRecords {
+            tid,
+            scopes: vec![],
+            roots: vec![],
+            open: vec![],
+            events: vec![],
+        }
+    }
+
+    fn open(&mut self, name: &str) {
+        let parent = self.open.last().copied();
+        let siblings = match parent {
+            Some(parent) => &self.scopes[parent].children,
+            None => &self.roots,
+        };
+        let scope = siblings
+            .iter()
+            .copied()
+            .find(|&scope| self.scopes[scope].name == name);
+        let scope = match scope {
+            Some(scope) => scope,
+            None => self.add_scope(parent, name),
+        };
+        self.open.push(scope);
+    }
+
+    fn add_scope(&mut self, parent: Option<usize>, name: &str) -> usize {
+        let path = match parent {
+            Some(parent) => format!("{}{}{}", self.scopes[parent].path, PATH_SEPARATOR, name),
+            None => name.to_string(),
+        };
+        let scope = self.scopes.len();
+        self.scopes.push(Scope {
+            name: name.to_string(),
+            path,
+            children: vec![],
+            frame: None,
+        });
+        match parent {
+            Some(parent) => self.scopes[parent].children.push(scope),
+            None => self.roots.push(scope),
+        }
+        scope
+    }
+
+    /// Closes the innermost open scope.
+    fn close(&mut self, start: Instant, duration: Duration) {
+        if let Some(scope) = self.open.pop() {
+            *self.scopes[scope].frame.get_or_insert_default() += duration;
+            if !TRACE_FULL.load(Ordering::Relaxed) {
+                self.events.push((scope, start, duration));
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct Profiler {
+    start: Instant,
+    /// The time spent in each scope so far this frame, by the path of names of
+    /// the scopes it is nested in.
+    frame: HashMap<String, Duration>,
+    stats: BTreeMap<String, ScopeStats>,
+    events: Vec<TraceEvent>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Profiler {
+    fn new() -> Profiler {
+        Profiler {
+            start: Instant::now(),
+            frame: HashMap::new(),
+            stats: BTreeMap::new(),
+            events: vec![],
+        }
+    }
+
+    /// Takes the scopes one thread has timed since they were last merged.
+    fn merge(&mut self, records: &mut ThreadRecords) {
+        for (scope, start, duration) in records.events.drain(..) {
+            if self.events.len() < MAX_TRACE_EVENTS {
+                self.events.push(TraceEvent {
+                    name: records.scopes[scope].name.clone(),
+                    ph: "X",
+                    ts: self.micros_since_start(start),
+                    dur: Some(duration.as_secs_f64() * 1_000_000.0),
+                    pid: 1,
+                    tid: records.tid,
+                    s: None,
+                });
+            }
+        }
+        for scope in &mut records.scopes {
+            if let Some(duration) = scope.frame.take() {
+                *self.frame.entry(scope.path.clone()).or_default() += duration;
+            }
+        }
+    }
+
+    /// Takes the scopes every thread has timed since they were last collected.
+    fn collect(&mut self) {
+        THREADS.lock().unwrap().retain(|records| {
+            self.merge(&mut records.lock().unwrap());
+            // Threads that have exited won't time any more scopes.
+            Arc::strong_count(records) > 1
+        });
+        if self.events.len() >= MAX_TRACE_EVENTS {
+            TRACE_FULL.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn end_frame(&mut self) {
+        for (path, duration) in self.frame.drain() {
+            self.stats
+                .entry(path)
+                .and_modify(|stats| stats.add(duration))
+                .or_insert_with(|| ScopeStats::new(duration));
+        }
+
+        if self.events.len() < MAX_TRACE_EVENTS {
+            let ts = self.micros_since_start(Instant::now());
+            self.events.push(TraceEvent {
+                name: "Frame".to_string(),
+                ph: "i",
+                ts,
+                dur: None,
+                pid: 1,
+                tid: THREAD_ID.with(|&id| id),
+                s: Some("g"),
+            });
+        }
+    }
+
+    fn write_chrome_trace<W: Write>(&self, out: W) -> io::Result<()> {
+        serde_json::to_writer(out, &Trace { trace_events: &self.events })?;
+        Ok(())
+    }
+
+    fn micros_since_start(&self, instant: Instant) -> f64 {
+        instant.saturating_duration_since(self.start).as_secs_f64() * 1_000_000.0
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+lazy_static! {
+    static ref PROFILER: Mutex<Profiler> = Mutex::new(Profiler::new());
+    /// The records of every thread that has timed a scope.
+    static ref THREADS: Mutex<Vec<Arc<Mutex<ThreadRecords>>>> = Mutex::new(vec![]);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Set once the trace holds `MAX_TRACE_EVENTS`, so threads stop keeping
+/// scopes for it.
+#[cfg(not(target_arch = "wasm32"))]
+static TRACE_FULL: AtomicBool = AtomicBool::new(false);
+
+#[cfg(not(target_arch = "wasm32"))]
+thread_local! {
+    /// Tells threads apart in the trace.
+    static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    /// The scopes timed on this thread. Only this thread and `end_frame` lock
+    /// it.
+    static RECORDS: Arc<Mutex<ThreadRecords>> = {
+        let records = Arc::new(Mutex::new(ThreadRecords::new(THREAD_ID.with(|&id| id))));
+        THREADS.lock().unwrap().push(records.clone());
+        records
+    };
+}
+
+/// Adds the time spent in each scope since the last frame, on every thread, to
+/// its statistics.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn end_frame() {
+    let mut profiler = PROFILER.lock().unwrap();
+    profiler.collect();
+    profiler.end_frame();
+}
+
+/// Gets the statistics of every scope, by the path of names of the scopes it
+/// is nested in, separated by `" > "`. Parents come before their children.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn stats() -> Vec<(String, ScopeStats)> {
+    let profiler = PROFILER.lock().unwrap();
+    profiler
+        .stats
+        .iter()
+        .map(|(path, stats)| (path.clone(), *stats))
+        .collect()
+}
+
+/// Writes every recorded scope as a Chrome trace in JSON.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_chrome_trace<W: Write>(out: W) -> io::Result<()> {
+    let mut profiler = PROFILER.lock().unwrap();
+    profiler.collect();
+    profiler.write_chrome_trace(out)
+}
+
+/// Logs the statistics of every scope, and saves the trace to the file named
+/// by the `NEONET_TRACE` environment variable, if it is set.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn finish() {
+    for (path, stats) in stats() {
+        let depth = path.matches(PATH_SEPARATOR).count();
+        let name = path.rsplit(PATH_SEPARATOR).next().unwrap();
+        info!(
+            "{:indent$}{}: min {:?}, avg {:?}, max {:?} over {} frames",
+            "",
+            name,
+            stats.min,
+            stats.average(),
+            stats.max,
+            stats.frames,
+            indent = depth * 2
+        );
+    }
+
+    if let Ok(path) = std::env::var("NEONET_TRACE") {
+        let result = File::create(&path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            write_chrome_trace(&mut out)?;
+            out.flush()
+        });
+        match result {
+            Ok(()) => info!("Saved the trace to {:?}", path),
+            Err(err) => error!("Error saving the trace to {:?}: {:?}", path, err),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use crate::timer::{end_frame, stats, Profiler, ThreadRecords, Timer};
+    use std::{
+        sync::Mutex,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// Held by tests that end frames of the shared profiler, which would
+    /// otherwise split each other's frames.
+    static SHARED_PROFILER: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_nested_scopes() {
+        let _lock = SHARED_PROFILER.lock().unwrap();
+        for sleep in [1, 3] {
+            let outer = Timer::from_str("test outer");
+            for _ in 0..2 {
+                let _inner = Timer::from_str("test inner");
+                thread::sleep(Duration::from_millis(sleep));
+            }
+            drop(outer);
+            end_frame();
+        }
+
+        let stats: Vec<_> = stats()
+            .into_iter()
+            .filter(|(path, _)| path.starts_with("test"))
+            .collect();
+        let paths: Vec<&str> = stats.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["test outer", "test outer > test inner"]);
+
+        // Both inner scopes in a frame are added together.
+        let inner = stats[1].1;
+        assert_eq!(inner.frames, 2);
+        assert!(inner.min >= Duration::from_millis(2));
+        assert!(inner.max >= Duration::from_millis(6));
+        assert!(inner.min <= inner.average() && inner.average() <= inner.max);
+        assert!(stats[0].1.min >= inner.min);
+    }
+
+    #[test]
+    fn test_other_threads() {
+        let _lock = SHARED_PROFILER.lock().unwrap();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(|| {
+                    let _outer = Timer::from_str("thread outer");
+                    let _inner = Timer::from_str("thread inner");
+                    thread::sleep(Duration::from_millis(1));
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        end_frame();
+
+        // The time every thread spent is added into one frame.
+        let stats: Vec<_> = stats()
+            .into_iter()
+            .filter(|(path, _)| path.starts_with("thread"))
+            .collect();
+        let paths: Vec<&str> = stats.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["thread outer", "thread outer > thread inner"]);
+        assert_eq!(stats[1].1.frames, 1);
+        assert!(stats[1].1.total >= Duration::from_millis(4));
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        // Other tests record into the shared profiler at the same time, so this
+        // uses its own.
+        let mut profiler = Profiler::new();
+        let mut records = ThreadRecords::new(1);
+        let start = Instant::now();
+        records.open("outer");
+        records.open("inner");
+        records.close(start + Duration::from_millis(1), Duration::from_millis(1));
+        records.close(start, Duration::from_millis(3));
+        profiler.merge(&mut records);
+        profiler.end_frame();
+        assert!(records.events.is_empty());
+
+        let mut json = vec![];
+        profiler.write_chrome_trace(&mut json).unwrap();
+        let trace: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 3);
+
+        // Scopes are added as they close, so inner scopes come first.
+        let (inner, outer) = (&events[0], &events[1]);
+        assert_eq!(
+            (outer["name"].as_str(), outer["ph"].as_str()),
+            (Some("outer"), Some("X"))
+        );
+        assert_eq!(
+            (inner["name"].as_str(), inner["ph"].as_str()),
+            (Some("inner"), Some("X"))
+        );
+        let micros = |event: &serde_json::Value, field: &str| event[field].as_f64().unwrap();
+        assert!((micros(outer, "dur") - 3000.0).abs() < 1.0);
+        assert!((micros(inner, "ts") - micros(outer, "ts") - 1000.0).abs() < 1.0);
+        assert!(inner.get("s").is_none());
+        assert_eq!(
+            (events[2]["ph"].as_str(), events[2]["s"].as_str()),
+            (Some("i"), Some("g"))
+        );
     }
 }
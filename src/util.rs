@@ -1,3 +1,7 @@
+use std::time::SystemTime;
+#[cfg(target_arch = "wasm32")]
+use std::time::{Duration, UNIX_EPOCH};
+
 pub fn least_power_of_2_greater(x: u64) -> u64 {
     if x < 1 {
         return 0;
@@ -14,6 +18,31 @@ pub fn least_power_of_2_greater(x: u64) -> u64 {
     x + 1
 }
 
+/// Encodes a linear color channel, as used for rendering, as an 8-bit sRGB
+/// value.
+pub fn linear_to_srgb(channel: f64) -> u8 {
+    let channel = channel.clamp(0.0, 1.0);
+    let srgb = if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+/// Gets the current time. `SystemTime::now` panics on the web, so there it is
+/// read from the page's performance clock instead.
+pub fn now() -> SystemTime {
+    #[cfg(target_arch = "wasm32")]
+    {
+        // Keep the fraction of a millisecond, for timing short spans.
+        let performance = web_sys::window().unwrap().performance().unwrap();
+        UNIX_EPOCH + Duration::from_secs_f64(performance.now() / 1_000.0)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    SystemTime::now()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::util::least_power_of_2_greater;
@@ -1,8 +1,13 @@
+#[cfg(target_arch = "wasm32")]
 use futures::prelude::*;
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsValue;
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen_futures::JsFuture;
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
 
+#[cfg(target_arch = "wasm32")]
 wasm_bindgen_test_configure!(run_in_browser);
 
 // This runs a unit test in native Rust, so it can only use Rust APIs.
@@ -12,6 +17,7 @@ fn rust_test() {
 }
 
 // This runs a unit test in the browser, so it can use browser APIs.
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen_test]
 fn web_test() {
     assert_eq!(1, 1);
@@ -19,6 +25,7 @@ fn web_test() {
 
 // This runs a unit test in the browser, and in addition it supports
 // asynchronous Future APIs.
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen_test(async)]
 fn async_test() -> impl Future<Output = ()> {
     // Creates a JavaScript Promise which will asynchronously resolve with the value